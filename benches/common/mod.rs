@@ -0,0 +1,26 @@
+//! Synthetic XISF images shared by the benchmarks.
+
+/// Position of the image data in the synthetic XISF files.
+pub const ATTACHMENT_START: usize = 4096;
+
+/// Build an uncompressed Float32 XISF image of `width` x `height` pixels and `channels`
+/// channels in memory.
+pub fn xisf_float32_image(width: usize, height: usize, channels: usize) -> Vec<u8> {
+    let length = width * height * channels * 4;
+    let xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <xisf version=\"1.0\" xmlns=\"http://www.pixinsight.com/xisf\">\
+         <Image geometry=\"{}:{}:{}\" sampleFormat=\"Float32\" bounds=\"0:1\" \
+         colorSpace=\"Gray\" location=\"attachment:{}:{}\"/></xisf>",
+        width, height, channels, ATTACHMENT_START, length
+    );
+    let mut xisf = b"XISF0100".to_vec();
+    xisf.extend_from_slice(&(xml.len() as u32).to_le_bytes());
+    xisf.extend_from_slice(&[0; 4]);
+    xisf.extend_from_slice(xml.as_bytes());
+    xisf.resize(ATTACHMENT_START, 0);
+    for sample in 0..width * height * channels {
+        xisf.extend_from_slice(&((sample % 1000) as f32 / 1000.0).to_le_bytes());
+    }
+    xisf
+}
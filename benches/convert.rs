@@ -0,0 +1,142 @@
+//! Benchmarks of the hot paths of a conversion: decompression, conversion of the image
+//! data to FITS and writing of the FITS file. A baseline is saved in `benches/baseline`;
+//! compare against it, or save a new one, with:
+//!
+//! ```bash
+//! $ CRITERION_HOME=benches/baseline cargo bench --bench convert -- --baseline main
+//! $ CRITERION_HOME=benches/baseline cargo bench --bench convert -- --save-baseline main
+//! ```
+//!
+//! The conversion of unsigned samples can also be compared with the `simd` feature:
+//!
+//! ```bash
+//! $ cargo bench --bench convert -- "unsigned to fits" --save-baseline scalar
+//! $ cargo bench --features simd --bench convert -- "unsigned to fits" --baseline scalar
+//! ```
+
+mod common;
+
+use common::{xisf_float32_image, ATTACHMENT_START};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::{fs, io::Cursor};
+use xisfits::{convert, fitswriter, xisf_data_to_fits, xisfreader::XISFile, ByteOrder};
+
+/// Zlib compressed 16 bits image, with its data decompressed on read.
+const XISF_ZLIB_IMAGE: &str = "tests/images/xisf-image-gray-256x256-16bits-zlib.xisf";
+
+fn bench_unshuffle(c: &mut Criterion) {
+    let array: Vec<u8> = (0..1 << 20).map(|byte| byte as u8).collect();
+    let mut group = c.benchmark_group("unshuffle");
+    let _ = group.throughput(Throughput::Bytes(array.len() as u64));
+    let _ = group.bench_function("1 MiB 4 bytes items", |b| {
+        b.iter(|| convert::unshuffle(&array, 4))
+    });
+    group.finish();
+}
+
+fn bench_unsigned_to_fits(c: &mut Criterion) {
+    // A 16 Mpixels camera frame. Compare with and without the `simd` feature
+    let u16_values: Vec<u16> = (0..4096 * 4096).map(|value| value as u16).collect();
+    let u32_values: Vec<u32> = (0..4096 * 4096).collect();
+    let mut group = c.benchmark_group("unsigned to fits");
+    let _ = group.throughput(Throughput::Bytes(u16_values.len() as u64 * 2));
+    let _ = group.bench_function("uint16 4096x4096", |b| {
+        b.iter(|| convert::u16_to_i16_to_v_u8_be(&u16_values))
+    });
+    let _ = group.throughput(Throughput::Bytes(u32_values.len() as u64 * 4));
+    let _ = group.bench_function("uint32 4096x4096", |b| {
+        b.iter(|| convert::u32_to_i32_to_v_u8_be(&u32_values))
+    });
+    group.finish();
+}
+
+/// Conversion of float samples before the buffer was preallocated, appending the bytes of
+/// each sample, to compare with `convert::f32_to_v_u8_be`.
+fn f32_to_v_u8_be_appended(values: &[f32]) -> Vec<u8> {
+    let mut old = Vec::new();
+    for float in values {
+        let mut value = float.to_bits().to_be_bytes().to_vec();
+        old.append(&mut value);
+    }
+    old
+}
+
+fn bench_float_to_fits(c: &mut Criterion) {
+    // Every bit pattern of the first 10M values, NaNs and infinities included
+    let f32_values: Vec<f32> = (0..10_000_000).map(f32::from_bits).collect();
+    assert_eq!(
+        f32_to_v_u8_be_appended(&f32_values),
+        convert::f32_to_v_u8_be(&f32_values)
+    );
+    let mut group = c.benchmark_group("float to fits");
+    let _ = group.throughput(Throughput::Bytes(f32_values.len() as u64 * 4));
+    let _ = group.bench_function("float32 10M preallocated", |b| {
+        b.iter(|| convert::f32_to_v_u8_be(&f32_values))
+    });
+    let _ = group.bench_function("float32 10M appended", |b| {
+        b.iter(|| f32_to_v_u8_be_appended(&f32_values))
+    });
+    group.finish();
+}
+
+fn bench_uncompress(c: &mut Criterion) {
+    // The decompression isn't public, so the whole read of the compressed image is measured
+    let xisf = fs::read(XISF_ZLIB_IMAGE).unwrap();
+    let mut group = c.benchmark_group("uncompress");
+    let _ = group.throughput(Throughput::Bytes(256 * 256 * 2));
+    let _ = group.bench_function("zlib uint16 256x256", |b| {
+        b.iter(|| XISFile::read_from(Cursor::new(&xisf)).unwrap())
+    });
+    group.finish();
+}
+
+fn bench_data_to_fits(c: &mut Criterion) {
+    let xisf = xisf_float32_image(256, 256, 3);
+    let file = XISFile::read_from(Cursor::new(&xisf)).unwrap();
+    let mut group = c.benchmark_group("data to fits");
+    let _ = group.throughput(Throughput::Bytes((xisf.len() - ATTACHMENT_START) as u64));
+    let _ = group.bench_function("float32 256x256x3 channels", |b| {
+        b.iter(|| xisf_data_to_fits(&file, ByteOrder::Big))
+    });
+    group.finish();
+}
+
+fn bench_fits_write(c: &mut Criterion) {
+    let xisf = xisf_float32_image(256, 256, 3);
+    let file = XISFile::read_from(Cursor::new(&xisf)).unwrap();
+    let (data_bytes, bitpix) = xisf_data_to_fits(&file, ByteOrder::Big);
+    let fits_hd = fitswriter::FitsHeaderData {
+        bitpix,
+        naxis: 3,
+        naxis_vec: vec![256, 256, 3],
+        bzero: 0,
+        bscale: 1,
+        datamin: 0,
+        datamax: 0,
+        history: Vec::new(),
+        comment: Vec::new(),
+        extra_keywords: Vec::new(),
+        data_bytes,
+    };
+    let mut group = c.benchmark_group("fits write");
+    let _ = group.throughput(Throughput::Bytes(fits_hd.data_bytes.len() as u64));
+    let _ = group.bench_function("float32 256x256x3 channels", |b| {
+        b.iter(|| {
+            let mut fits = Vec::with_capacity(fits_hd.data_bytes.len() + 2880 * 2);
+            fitswriter::fits_write_data_to(&mut fits, &fits_hd).unwrap();
+            fits
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_unshuffle,
+    bench_unsigned_to_fits,
+    bench_float_to_fits,
+    bench_uncompress,
+    bench_data_to_fits,
+    bench_fits_write
+);
+criterion_main!(benches);
@@ -0,0 +1,28 @@
+//! Benchmarks of the XISF reader. Run them with and without the `parallel` feature to
+//! compare the sequential and parallel decoding of the channels:
+//!
+//! ```bash
+//! $ cargo bench --bench read
+//! $ cargo bench --bench read --no-default-features --features date
+//! ```
+
+mod common;
+
+use common::{xisf_float32_image, ATTACHMENT_START};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use std::io::Cursor;
+use xisfits::xisfreader::XISFile;
+
+fn bench_read_multichannel(c: &mut Criterion) {
+    let xisf = xisf_float32_image(1024, 1024, 10);
+    let mut group = c.benchmark_group("read");
+    let _ = group.throughput(Throughput::Bytes((xisf.len() - ATTACHMENT_START) as u64));
+    let _ = group.sample_size(20);
+    let _ = group.bench_function("float32 1024x1024x10 channels", |b| {
+        b.iter(|| XISFile::read_from(Cursor::new(&xisf)).unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_read_multichannel);
+criterion_main!(benches);
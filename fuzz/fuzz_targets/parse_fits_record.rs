@@ -0,0 +1,16 @@
+//! Fuzz the parser of FITS header records with arbitrary 80 bytes records (and a shorter
+//! last one): parsing them must return an error, never panic.
+//!
+//! ```bash
+//! $ cargo +nightly fuzz run parse_fits_record
+//! ```
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use xisfits::fitswriter::FITSKeyword;
+
+fuzz_target!(|data: &[u8]| {
+    for record in data.chunks(80) {
+        let _ = FITSKeyword::from_fits_record(record);
+    }
+});
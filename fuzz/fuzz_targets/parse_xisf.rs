@@ -0,0 +1,14 @@
+//! Fuzz the XISF reader (fixed header, XML header and image data) with arbitrary
+//! files: reading them must return an error, never panic.
+//!
+//! ```bash
+//! $ cargo +nightly fuzz run parse_xisf -- -max_len=65536
+//! ```
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use xisfits::xisfreader::XISFile;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = XISFile::from_bytes(data);
+});
@@ -0,0 +1,48 @@
+//! Non-blocking reading of XISF images with tokio (`async` feature). The XISF files are
+//! read, decompressed and decoded by the synchronous reader in tokio's blocking thread
+//! pool, so the runtime isn't blocked meanwhile.
+
+use crate::{error::XisfError, xisfreader::XISFile};
+use std::{io, panic, path::Path};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt},
+    task::{self, JoinError},
+};
+
+impl XISFile {
+    /// Read XISF file and decode headers and image, as `read_file`, without blocking
+    /// the runtime.
+    pub async fn read_file_async<P>(xisf_filename: P) -> Result<Self, XisfError>
+    where
+        P: AsRef<Path>,
+    {
+        let xisf_filename = xisf_filename.as_ref().to_path_buf();
+        task::spawn_blocking(move || Self::read_file(&xisf_filename))
+            .await
+            .map_err(join_error)?
+    }
+
+    /// Read a monolithic XISF image from any asynchronous reader (e.g. an upload), as
+    /// `from_bytes`. The whole image is read in memory first, then decoded without
+    /// blocking the runtime.
+    pub async fn read_from_async<R>(reader: R) -> Result<Self, XisfError>
+    where
+        R: AsyncRead + AsyncSeek + Unpin,
+    {
+        let mut reader = reader;
+        let _ = reader.seek(io::SeekFrom::Start(0)).await?;
+        let mut bytes = Vec::new();
+        let _ = reader.read_to_end(&mut bytes).await?;
+        task::spawn_blocking(move || Self::from_bytes(&bytes))
+            .await
+            .map_err(join_error)?
+    }
+}
+
+/// Resumes the panics of the blocking tasks, and reports their cancellation as an error.
+fn join_error(e: JoinError) -> XisfError {
+    if e.is_panic() {
+        panic::resume_unwind(e.into_panic());
+    }
+    XisfError::Io(io::Error::new(io::ErrorKind::Interrupted, e.to_string()))
+}
@@ -1,7 +1,10 @@
+//! Conversions of sample vectors to and from bytes.
+
+use crate::xisfreader::{Complex32, Complex64};
 use byteorder::{LittleEndian, ReadBytesExt};
 use std::io::Cursor;
 
-#[allow(dead_code)]
+/// From Vec<u8> to i8
 pub fn u8_to_i8(vector: &[u8]) -> Vec<i8> {
     let mut rdr = Cursor::new(vector);
     let mut values = Vec::new();
@@ -17,7 +20,8 @@ pub fn u8_to_i8(vector: &[u8]) -> Vec<i8> {
 }
 
 macro_rules! u8_to_t {
-    ($func_name:ident, $read_func:ident, $type:ty) => {
+    ($func_name:ident, $read_func:ident, $type:ty, $doc:expr) => {
+        #[doc = $doc]
         #[allow(dead_code)]
         pub fn $func_name(vector: &[u8]) -> Vec<$type> {
             let mut rdr = Cursor::new(vector);
@@ -35,105 +39,488 @@ macro_rules! u8_to_t {
     };
 }
 
-u8_to_t!(u8_to_v_u16, read_u16, u16);
-u8_to_t!(u8_to_v_i16, read_i16, i16);
-u8_to_t!(u8_to_v_u32, read_u32, u32);
-u8_to_t!(u8_to_v_i32, read_i32, i32);
-u8_to_t!(u8_to_v_u64, read_u64, u64);
-u8_to_t!(u8_to_v_i64, read_i64, i64);
-u8_to_t!(u8_to_v_u128, read_u128, u128);
-u8_to_t!(u8_to_v_i128, read_i128, i128);
-u8_to_t!(u8_to_v_f32, read_f32, f32);
-u8_to_t!(u8_to_v_f64, read_f64, f64);
+u8_to_t!(
+    u8_to_v_u16,
+    read_u16,
+    u16,
+    "From Vec<u8> (Little Endian) to u16"
+);
+u8_to_t!(
+    u8_to_v_i16,
+    read_i16,
+    i16,
+    "From Vec<u8> (Little Endian) to i16"
+);
+u8_to_t!(
+    u8_to_v_u32,
+    read_u32,
+    u32,
+    "From Vec<u8> (Little Endian) to u32"
+);
+u8_to_t!(
+    u8_to_v_i32,
+    read_i32,
+    i32,
+    "From Vec<u8> (Little Endian) to i32"
+);
+u8_to_t!(
+    u8_to_v_u64,
+    read_u64,
+    u64,
+    "From Vec<u8> (Little Endian) to u64"
+);
+u8_to_t!(
+    u8_to_v_i64,
+    read_i64,
+    i64,
+    "From Vec<u8> (Little Endian) to i64"
+);
+u8_to_t!(
+    u8_to_v_u128,
+    read_u128,
+    u128,
+    "From Vec<u8> (Little Endian) to u128"
+);
+u8_to_t!(
+    u8_to_v_i128,
+    read_i128,
+    i128,
+    "From Vec<u8> (Little Endian) to i128"
+);
+u8_to_t!(
+    u8_to_v_f32,
+    read_f32,
+    f32,
+    "From Vec<u8> (Little Endian) to f32"
+);
+u8_to_t!(
+    u8_to_v_f64,
+    read_f64,
+    f64,
+    "From Vec<u8> (Little Endian) to f64"
+);
 
 macro_rules! t_to_u8_be {
-    ($func_name:ident, $type:ty) => {
+    ($func_name:ident, $type:ty, $doc:expr) => {
+        #[doc = $doc]
         #[allow(dead_code)]
         pub fn $func_name(vector: &[$type]) -> Vec<u8> {
-            let mut values = Vec::new();
+            let mut values = Vec::with_capacity(vector.len() * std::mem::size_of::<$type>());
             for value in vector {
-                let bytes = value.to_be_bytes();
-                for n in 0..bytes.len() {
-                    values.push(bytes[n]);
-                }
+                values.extend_from_slice(&value.to_be_bytes());
             }
             values
         }
     };
 }
 
-t_to_u8_be!(i8_to_v_u8_be, i8);
-t_to_u8_be!(u16_to_v_u8_be, u16);
-t_to_u8_be!(i16_to_v_u8_be, i16);
-t_to_u8_be!(u32_to_v_u8_be, u32);
-t_to_u8_be!(i32_to_v_u8_be, i32);
-t_to_u8_be!(u64_to_v_u8_be, u64);
-t_to_u8_be!(i64_to_v_u8_be, i64);
-t_to_u8_be!(i128_to_v_u8_be, i128);
-t_to_u8_be!(u128_to_v_u8_be, u128);
+t_to_u8_be!(i8_to_v_u8_be, i8, "From i8 to Vec<u8> (Big Endian)");
+t_to_u8_be!(u16_to_v_u8_be, u16, "From u16 to Vec<u8> (Big Endian)");
+t_to_u8_be!(i16_to_v_u8_be, i16, "From i16 to Vec<u8> (Big Endian)");
+t_to_u8_be!(u32_to_v_u8_be, u32, "From u32 to Vec<u8> (Big Endian)");
+t_to_u8_be!(i32_to_v_u8_be, i32, "From i32 to Vec<u8> (Big Endian)");
+t_to_u8_be!(u64_to_v_u8_be, u64, "From u64 to Vec<u8> (Big Endian)");
+t_to_u8_be!(i64_to_v_u8_be, i64, "From i64 to Vec<u8> (Big Endian)");
+t_to_u8_be!(i128_to_v_u8_be, i128, "From i128 to Vec<u8> (Big Endian)");
+t_to_u8_be!(u128_to_v_u8_be, u128, "From u128 to Vec<u8> (Big Endian)");
 
-/// From u16 to i16 to Vec<u8> (Big Endian)
+macro_rules! t_to_u8_le {
+    ($func_name:ident, $type:ty, $doc:expr) => {
+        #[doc = $doc]
+        #[allow(dead_code)]
+        pub fn $func_name(vector: &[$type]) -> Vec<u8> {
+            let mut values = Vec::with_capacity(vector.len() * std::mem::size_of::<$type>());
+            for value in vector {
+                values.extend_from_slice(&value.to_le_bytes());
+            }
+            values
+        }
+    };
+}
+
+t_to_u8_le!(i8_to_v_u8_le, i8, "From i8 to Vec<u8> (Little Endian)");
+t_to_u8_le!(u16_to_v_u8_le, u16, "From u16 to Vec<u8> (Little Endian)");
+t_to_u8_le!(i16_to_v_u8_le, i16, "From i16 to Vec<u8> (Little Endian)");
+t_to_u8_le!(u32_to_v_u8_le, u32, "From u32 to Vec<u8> (Little Endian)");
+t_to_u8_le!(i32_to_v_u8_le, i32, "From i32 to Vec<u8> (Little Endian)");
+t_to_u8_le!(u64_to_v_u8_le, u64, "From u64 to Vec<u8> (Little Endian)");
+t_to_u8_le!(i64_to_v_u8_le, i64, "From i64 to Vec<u8> (Little Endian)");
+t_to_u8_le!(
+    i128_to_v_u8_le,
+    i128,
+    "From i128 to Vec<u8> (Little Endian)"
+);
+t_to_u8_le!(
+    u128_to_v_u8_le,
+    u128,
+    "From u128 to Vec<u8> (Little Endian)"
+);
+
+/// From u16 to i16 to Vec<u8> (Big Endian). Values are stored with an offset of
+/// -32768 (FITS BZERO = 32768), so the whole range is kept.
 #[allow(clippy::cast_possible_wrap)]
 pub fn u16_to_i16_to_v_u8_be(v: &[u16]) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::new();
-    for integer in v {
-        let mut v_u = *integer;
-        if v_u > i16::max_value() as u16 {
-            v_u = i16::max_value() as u16;
-        }
-        let v_i = v_u as i16;
-        result.append(&mut v_i.to_be_bytes().to_vec());
+    let mut result: Vec<u8> = vec![0; v.len() * 2];
+    let converted = simd_u16_to_i16_be(v, &mut result);
+    for (integer, bytes) in v[converted..]
+        .iter()
+        .zip(result[converted * 2..].chunks_exact_mut(2))
+    {
+        // Flipping the sign bit is the same as subtracting 32768
+        let v_i = (integer ^ (1 << 15)) as i16;
+        bytes.copy_from_slice(&v_i.to_be_bytes());
     }
     result
 }
 
-/// From u32 to i32 to Vec<u8> (Big Endian)
+/// From u32 to i32 to Vec<u8> (Big Endian). Values are stored with an offset of
+/// -2147483648 (FITS BZERO = 2147483648), so the whole range is kept.
 #[allow(clippy::cast_possible_wrap)]
 pub fn u32_to_i32_to_v_u8_be(v: &[u32]) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::new();
-    for integer in v {
-        let mut v_u = *integer;
-        if v_u > i32::max_value() as u32 {
-            v_u = i32::max_value() as u32;
+    let mut result: Vec<u8> = vec![0; v.len() * 4];
+    let converted = simd_u32_to_i32_be(v, &mut result);
+    for (integer, bytes) in v[converted..]
+        .iter()
+        .zip(result[converted * 4..].chunks_exact_mut(4))
+    {
+        // Flipping the sign bit is the same as subtracting 2147483648
+        let v_i = (integer ^ (1 << 31)) as i32;
+        bytes.copy_from_slice(&v_i.to_be_bytes());
+    }
+    result
+}
+
+/// Converts the first samples of `v` to `out` as `u16_to_i16_to_v_u8_be` with SIMD
+/// instructions, if the `simd` feature is enabled and the CPU supports them. Returns
+/// the number of samples converted.
+#[allow(unused_variables, unsafe_code)]
+fn simd_u16_to_i16_be(v: &[u16], out: &mut [u8]) -> usize {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("ssse3") {
+            // Safety: SSSE3 is available
+            return unsafe { simd::u16_to_i16_be(v, out) };
+        }
+    }
+    0
+}
+
+/// Converts the first samples of `v` to `out` as `u32_to_i32_to_v_u8_be` with SIMD
+/// instructions, if the `simd` feature is enabled and the CPU supports them. Returns
+/// the number of samples converted.
+#[allow(unused_variables, unsafe_code)]
+fn simd_u32_to_i32_be(v: &[u32], out: &mut [u8]) -> usize {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("ssse3") {
+            // Safety: SSSE3 is available
+            return unsafe { simd::u32_to_i32_be(v, out) };
+        }
+    }
+    0
+}
+
+/// SSSE3 conversions of unsigned samples to big endian signed samples: the sign bit
+/// of 16 bytes of samples is flipped and their bytes swapped with a shuffle.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+#[allow(unsafe_code, clippy::cast_ptr_alignment)]
+mod simd {
+    use std::arch::x86_64::{
+        __m128i, _mm_loadu_si128, _mm_set1_epi16, _mm_set1_epi32, _mm_setr_epi8, _mm_shuffle_epi8,
+        _mm_storeu_si128, _mm_xor_si128,
+    };
+
+    /// Converts the whole 16 bytes blocks of `v` to `out`, which must hold
+    /// `2 * v.len()` bytes. Returns the number of samples converted.
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn u16_to_i16_be(v: &[u16], out: &mut [u8]) -> usize {
+        assert!(out.len() >= v.len() * 2);
+        let sign = _mm_set1_epi16(i16::min_value());
+        let swap = _mm_setr_epi8(1, 0, 3, 2, 5, 4, 7, 6, 9, 8, 11, 10, 13, 12, 15, 14);
+        let blocks = v.len() / 8;
+        for i in 0..blocks {
+            // Unaligned loads and stores
+            let samples = _mm_loadu_si128(v.as_ptr().add(i * 8).cast::<__m128i>());
+            let samples = _mm_shuffle_epi8(_mm_xor_si128(samples, sign), swap);
+            _mm_storeu_si128(out.as_mut_ptr().add(i * 16).cast::<__m128i>(), samples);
         }
-        let v_i = v_u as i32;
-        result.append(&mut v_i.to_be_bytes().to_vec());
+        blocks * 8
+    }
+
+    /// Converts the whole 16 bytes blocks of `v` to `out`, which must hold
+    /// `4 * v.len()` bytes. Returns the number of samples converted.
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn u32_to_i32_be(v: &[u32], out: &mut [u8]) -> usize {
+        assert!(out.len() >= v.len() * 4);
+        let sign = _mm_set1_epi32(i32::min_value());
+        let swap = _mm_setr_epi8(3, 2, 1, 0, 7, 6, 5, 4, 11, 10, 9, 8, 15, 14, 13, 12);
+        let blocks = v.len() / 4;
+        for i in 0..blocks {
+            let samples = _mm_loadu_si128(v.as_ptr().add(i * 4).cast::<__m128i>());
+            let samples = _mm_shuffle_epi8(_mm_xor_si128(samples, sign), swap);
+            _mm_storeu_si128(out.as_mut_ptr().add(i * 16).cast::<__m128i>(), samples);
+        }
+        blocks * 4
+    }
+}
+
+/// From u16 to i16 to Vec<u8> (Little Endian). Values are stored with an offset of
+/// -32768 (FITS BZERO = 32768), so the whole range is kept.
+#[allow(clippy::cast_possible_wrap)]
+pub fn u16_to_i16_to_v_u8_le(v: &[u16]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 2);
+    for integer in v {
+        // Flipping the sign bit is the same as subtracting 32768
+        let v_i = (integer ^ (1 << 15)) as i16;
+        result.extend_from_slice(&v_i.to_le_bytes());
+    }
+    result
+}
+
+/// From u32 to i32 to Vec<u8> (Little Endian). Values are stored with an offset of
+/// -2147483648 (FITS BZERO = 2147483648), so the whole range is kept.
+#[allow(clippy::cast_possible_wrap)]
+pub fn u32_to_i32_to_v_u8_le(v: &[u32]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 4);
+    for integer in v {
+        // Flipping the sign bit is the same as subtracting 2147483648
+        let v_i = (integer ^ (1 << 31)) as i32;
+        result.extend_from_slice(&v_i.to_le_bytes());
+    }
+    result
+}
+
+/// From i8 to i16 to Vec<u8> (Big Endian). FITS 8 bits integers are unsigned, so
+/// signed bytes are widened to 16 bits.
+pub fn i8_to_i16_to_v_u8_be(v: &[i8]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 2);
+    for integer in v {
+        result.extend_from_slice(&i16::from(*integer).to_be_bytes());
+    }
+    result
+}
+
+/// From i8 to i16 to Vec<u8> (Little Endian). FITS 8 bits integers are unsigned, so
+/// signed bytes are widened to 16 bits.
+pub fn i8_to_i16_to_v_u8_le(v: &[i8]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 2);
+    for integer in v {
+        result.extend_from_slice(&i16::from(*integer).to_le_bytes());
     }
     result
 }
 
+/// From floating point values in the range [lower, upper] to i16, scaled to the
+/// full 16 bits range. Values are stored with an offset of -32768 (FITS BZERO = 32768).
+/// Values out of the range are clipped.
+#[allow(clippy::cast_possible_truncation)]
+pub fn float_to_v_i16_scaled<T>(v: &[T], lower: f64, upper: f64) -> Vec<i16>
+where
+    T: Copy + Into<f64>,
+{
+    let scale = f64::from(u16::max_value()) / (upper - lower);
+    let mut result: Vec<i16> = Vec::with_capacity(v.len());
+    for float in v {
+        let value = ((*float).into() - lower) * scale;
+        let value = value.max(0.0).min(f64::from(u16::max_value())).round();
+        result.push((value - 32768.0) as i16);
+    }
+    result
+}
+
+/// From floating point values to i16, rounded. Values out of the i16 range are
+/// clipped (saturated), and NaN is stored as the minimum value.
+#[allow(clippy::cast_possible_truncation)]
+pub fn float_to_v_i16_saturated<T>(v: &[T]) -> Vec<i16>
+where
+    T: Copy + Into<f64>,
+{
+    let (min, max) = (f64::from(i16::min_value()), f64::from(i16::max_value()));
+    v.iter()
+        .map(|float| (*float).into().round().max(min).min(max) as i16)
+        .collect()
+}
+
+/// From floating point values to i32, rounded. Values out of the i32 range are
+/// clipped (saturated), and NaN is stored as the minimum value.
+#[allow(clippy::cast_possible_truncation)]
+pub fn float_to_v_i32_saturated<T>(v: &[T]) -> Vec<i32>
+where
+    T: Copy + Into<f64>,
+{
+    let (min, max) = (f64::from(i32::min_value()), f64::from(i32::max_value()));
+    v.iter()
+        .map(|float| (*float).into().round().max(min).min(max) as i32)
+        .collect()
+}
+
 /// From f32 to Vec<u8> (Big Endian)
 pub fn f32_to_v_u8_be(v: &[f32]) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::new();
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 4);
     for float in v {
-        let mut value = float.to_bits().to_be_bytes().to_vec();
-        result.append(&mut value);
+        result.extend_from_slice(&float.to_bits().to_be_bytes());
     }
     result
 }
 
 /// From f64 to Vec<u8> (Big Endian)
 pub fn f64_to_v_u8_be(v: &[f64]) -> Vec<u8> {
-    let mut result: Vec<u8> = Vec::new();
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 8);
+    for float in v {
+        result.extend_from_slice(&float.to_bits().to_be_bytes());
+    }
+    result
+}
+
+/// From f32 to Vec<u8> (Little Endian)
+pub fn f32_to_v_u8_le(v: &[f32]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 4);
+    for float in v {
+        result.extend_from_slice(&float.to_bits().to_le_bytes());
+    }
+    result
+}
+
+/// From f64 to Vec<u8> (Little Endian)
+pub fn f64_to_v_u8_le(v: &[f64]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 8);
     for float in v {
-        let mut value = float.to_bits().to_be_bytes().to_vec();
-        result.append(&mut value);
+        result.extend_from_slice(&float.to_bits().to_le_bytes());
+    }
+    result
+}
+
+/// From Vec<u8> to complex numbers (Little Endian), each one stored as its real part
+/// followed by its imaginary part
+pub fn u8_to_v_complex32(vector: &[u8]) -> Vec<Complex32> {
+    u8_to_v_f32(vector)
+        .chunks_exact(2)
+        .map(|parts| Complex32 {
+            re: parts[0],
+            im: parts[1],
+        })
+        .collect()
+}
+
+/// From Vec<u8> to complex numbers (Little Endian), each one stored as its real part
+/// followed by its imaginary part
+pub fn u8_to_v_complex64(vector: &[u8]) -> Vec<Complex64> {
+    u8_to_v_f64(vector)
+        .chunks_exact(2)
+        .map(|parts| Complex64 {
+            re: parts[0],
+            im: parts[1],
+        })
+        .collect()
+}
+
+/// From Complex32 to Vec<u8> (Big Endian), interleaving the real and imaginary parts
+pub fn complex32_to_v_u8_be(v: &[Complex32]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 8);
+    for complex in v {
+        result.extend_from_slice(&complex.re.to_bits().to_be_bytes());
+        result.extend_from_slice(&complex.im.to_bits().to_be_bytes());
+    }
+    result
+}
+
+/// From Complex64 to Vec<u8> (Big Endian), interleaving the real and imaginary parts
+pub fn complex64_to_v_u8_be(v: &[Complex64]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 16);
+    for complex in v {
+        result.extend_from_slice(&complex.re.to_bits().to_be_bytes());
+        result.extend_from_slice(&complex.im.to_bits().to_be_bytes());
+    }
+    result
+}
+
+/// From Complex32 to Vec<u8> (Little Endian), interleaving the real and imaginary parts
+pub fn complex32_to_v_u8_le(v: &[Complex32]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 8);
+    for complex in v {
+        result.extend_from_slice(&complex.re.to_bits().to_le_bytes());
+        result.extend_from_slice(&complex.im.to_bits().to_le_bytes());
+    }
+    result
+}
+
+/// From Complex64 to Vec<u8> (Little Endian), interleaving the real and imaginary parts
+pub fn complex64_to_v_u8_le(v: &[Complex64]) -> Vec<u8> {
+    let mut result: Vec<u8> = Vec::with_capacity(v.len() * 16);
+    for complex in v {
+        result.extend_from_slice(&complex.re.to_bits().to_le_bytes());
+        result.extend_from_slice(&complex.im.to_bits().to_le_bytes());
     }
     result
 }
 
-/// Unshuffle byte array
+/// Interleave the samples of `channels` channels stored one after another
+/// (RRRR...GGGG...BBBB), so the samples of each pixel are contiguous (RGBRGB...)
+pub fn interleave<T: Copy>(planar: &[T], channels: usize) -> Vec<T> {
+    if channels == 0 {
+        return Vec::new();
+    }
+    let pixels = planar.len() / channels;
+    let mut interleaved = Vec::with_capacity(pixels * channels);
+    for pixel in 0..pixels {
+        for channel in 0..channels {
+            interleaved.push(planar[channel * pixels + pixel]);
+        }
+    }
+    interleaved
+}
+
+/// Split interleaved samples (RGBRGB...) into `channels` channels (RRRR..., GGGG..., BBBB...)
+pub fn deinterleave<T: Copy>(interleaved: &[T], channels: usize) -> Vec<Vec<T>> {
+    (0..channels)
+        .map(|channel| {
+            interleaved
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .take(interleaved.len() / channels)
+                .copied()
+                .collect()
+        })
+        .collect()
+}
+
+/// Shuffle byte array: group the bytes of items of `byte_size` bytes by their position
+/// in the item (the inverse of `unshuffle`). Bytes beyond the last whole item are kept
+/// as they are.
+pub fn shuffle(array: &[u8], byte_size: usize) -> Vec<u8> {
+    if byte_size <= 1 {
+        return array.to_vec();
+    }
+    let n_items = array.len() / byte_size;
+    let mut shuffled = Vec::with_capacity(array.len());
+    for j in 0..byte_size {
+        shuffled.extend(
+            array[..n_items * byte_size]
+                .iter()
+                .skip(j)
+                .step_by(byte_size),
+        );
+    }
+    shuffled.extend_from_slice(&array[n_items * byte_size..]);
+    shuffled
+}
+
+/// Unshuffle byte array: the bytes of items of `byte_size` bytes were stored grouped by
+/// their position in the item (all the first bytes, then all the second bytes, ...).
+/// Bytes beyond the last whole item are kept as they are.
 pub fn unshuffle(array: &[u8], byte_size: usize) -> Vec<u8> {
     // Based on http://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#byte_shuffling
-    let array_size = array.len();
-    let mut unshuffled = Vec::with_capacity(array_size);
-    unshuffled.resize(unshuffled.capacity(), 0_u8);
-    let n_items = array_size / byte_size;
-    for j in 0..(byte_size - 1) {
-        let array_start = j * n_items * byte_size;
-        for i in 0..(n_items - 1) {
-            unshuffled[j + byte_size] = array[array_start + i];
+    if byte_size <= 1 {
+        return array.to_vec();
+    }
+    let n_items = array.len() / byte_size;
+    let mut unshuffled = Vec::with_capacity(array.len());
+    for i in 0..n_items {
+        for j in 0..byte_size {
+            unshuffled.push(array[j * n_items + i]);
         }
     }
+    unshuffled.extend_from_slice(&array[n_items * byte_size..]);
     unshuffled
 }
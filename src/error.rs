@@ -0,0 +1,393 @@
+//! Errors of the XISF reader.
+
+use std::{io, num::ParseIntError};
+use thiserror::Error;
+
+/// Errors found while reading a XISF file.
+#[derive(Debug, Error)]
+pub enum XisfError {
+    /// Error reading the file.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    /// The file is shorter than the fixed XISF header (signature, header length and reserved field).
+    #[error("not a XISF file: {size} bytes is shorter than the XISF header")]
+    TooShort {
+        /// Size of the file in bytes.
+        size: u64,
+    },
+    /// The file doesn't start with the XISF signature. `found` holds the first bytes of the file.
+    #[error("not a XISF file: bad signature {found:?}")]
+    InvalidSignature {
+        /// First bytes of the file.
+        found: String,
+    },
+    /// The XML header is malformed. `position` is the offset in the XML header where
+    /// the error was found.
+    #[error("invalid XML header at position {position}: {source}")]
+    Xml {
+        /// Offset in the XML header.
+        position: usize,
+        /// Error of the XML parser.
+        source: quick_xml::Error,
+    },
+    /// An attribute of the XML header can't be parsed.
+    #[error("invalid XML header: {0}")]
+    Parse(#[from] XisfParseError),
+    /// The sample format of an element (e.g. `Image`) is unknown.
+    #[error("{source} at {element} element")]
+    InvalidSampleFormat {
+        /// Name of the element.
+        element: String,
+        /// The unknown sample format.
+        source: SampleFormatParseError,
+    },
+    /// The geometry of the image isn't valid ("dim1:...:dimN:channel-count").
+    #[error("invalid image geometry: {geometry:?}")]
+    InvalidGeometry {
+        /// Value of the `geometry` attribute.
+        geometry: String,
+    },
+    /// The size in bytes of the image (dimensions x channel count x sample size) doesn't
+    /// fit in memory addresses.
+    #[error("image geometry too large: {geometry:?}")]
+    GeometryTooLarge {
+        /// Value of the `geometry` attribute.
+        geometry: String,
+    },
+    /// A selected channel isn't one of the `channel_count` channels of the image.
+    #[error("channel {channel} out of range: the image has {channel_count} channels")]
+    ChannelOutOfRange {
+        /// The selected channel (0-based).
+        channel: usize,
+        /// Number of channels of the image.
+        channel_count: usize,
+    },
+    /// The number of channels of the image doesn't match its color space.
+    #[error("{color_space} color space doesn't match the {channels} channels of the image")]
+    ColorSpaceMismatch {
+        /// Color space of the image.
+        color_space: String,
+        /// Number of channels of the image.
+        channels: usize,
+    },
+    /// An element (e.g. `Image`) has attributes that aren't recognized.
+    #[error(
+        "{} unrecognized {} attribute{}: {}",
+        .names.len(),
+        .element,
+        plural(.names.len()),
+        .names.join(", ")
+    )]
+    UnknownAttributes {
+        /// Name of the element.
+        element: String,
+        /// Names of the unrecognized attributes.
+        names: Vec<String>,
+    },
+    /// The image data has a sample format that can't be converted (e.g. `UInt64`).
+    #[error("unsupported sample format: {sample_format}")]
+    UnsupportedSampleFormat {
+        /// The sample format of the image.
+        sample_format: String,
+    },
+    /// The XML header has no `Image` element with a `sampleFormat` attribute.
+    #[error("no image with a sample format")]
+    MissingSampleFormat,
+    /// The `Image` element has no `geometry` attribute.
+    #[error("no image geometry")]
+    MissingGeometry,
+    /// The length of the XML header is larger than any realistic header
+    /// (`XisfReadOptions::max_header_bytes`).
+    #[error("XML header too large: {length} bytes")]
+    HeaderTooLarge {
+        /// Declared length of the XML header in bytes.
+        length: u32,
+    },
+    /// The XML header (`length` bytes after the fixed XISF header) goes beyond the end of the file.
+    #[error(
+        "XML header out of bounds: {length} bytes after the XISF header, but the file has \
+         {file_size} bytes"
+    )]
+    HeaderOutOfBounds {
+        /// Declared length of the XML header in bytes.
+        length: u32,
+        /// Size of the file in bytes.
+        file_size: u64,
+    },
+    /// The XML header ends (after `read` of its `length` bytes) before the end of the
+    /// root `xisf` element.
+    #[error("truncated XML header: document ends after {read} of {length} bytes")]
+    TruncatedHeader {
+        /// Bytes of the XML header read.
+        read: usize,
+        /// Declared length of the XML header in bytes.
+        length: u32,
+    },
+    /// The image doesn't declare the location of its data.
+    #[error("the image doesn't declare a data location")]
+    MissingLocation,
+    /// The image data is stored in a way that can't be read (e.g. a remote URL).
+    #[error("unsupported data location method: {method}")]
+    UnsupportedLocationMethod {
+        /// The location method (e.g. `url`).
+        method: String,
+    },
+    /// The image data attachment (`start` + `length`) goes beyond the end of the file.
+    #[error(
+        "image data out of bounds: {length} bytes at position {start}, but the file has \
+         {file_size} bytes"
+    )]
+    AttachmentOutOfBounds {
+        /// Position of the attachment in the file.
+        start: u64,
+        /// Length of the attachment in bytes.
+        length: u64,
+        /// Size of the file in bytes.
+        file_size: u64,
+    },
+    /// The image data of an image read from a reader is stored in another file, but
+    /// `XisfReadOptions::external_data` isn't set.
+    #[error("image data stored in another file, but external data is disabled: {location}")]
+    ExternalDataDisabled {
+        /// The data location of the image.
+        location: String,
+    },
+    /// The data file of a `url(...)` location is outside the directory of the XISF file.
+    #[error("data file outside the directory of the XISF file: {url}")]
+    UrlOutsideDirectory {
+        /// URL of the data file.
+        url: String,
+    },
+    /// The image data is stored in a remote file, but the `remote` feature is disabled.
+    #[error("remote blocks not supported in this build (enable the remote feature): {url}")]
+    RemoteUnsupported {
+        /// URL of the remote file.
+        url: String,
+    },
+    /// The block of a remote file ends beyond the maximum size downloaded.
+    #[error("remote block too large: {length} bytes of {url}, but the limit is {limit} bytes")]
+    RemoteTooLarge {
+        /// URL of the remote file.
+        url: String,
+        /// Bytes needed to read the block.
+        length: u64,
+        /// Maximum size downloaded in bytes.
+        limit: u64,
+    },
+    /// The request of a remote file failed.
+    #[error("request of {url} failed: {message}")]
+    Remote {
+        /// URL of the remote file.
+        url: String,
+        /// Description of the failure.
+        message: String,
+    },
+    /// The XISB data blocks file of a `path(...)` location can't be read.
+    #[error("can't read the data blocks file {path}: {source}")]
+    BlockFile {
+        /// Path of the XISB file.
+        path: String,
+        /// Error reading the file.
+        source: io::Error,
+    },
+    /// The XISB data blocks file has no valid signature or block index.
+    #[error("not a XISB data blocks file: {path}")]
+    InvalidBlockFile {
+        /// Path of the XISB file.
+        path: String,
+    },
+    /// The XISB data blocks file has no block with the identifier of the location.
+    #[error("data block {id:x} not found in {path}")]
+    BlockNotFound {
+        /// Path of the XISB file.
+        path: String,
+        /// Identifier of the block.
+        id: u64,
+    },
+    /// The data block (`start` + `length`) goes beyond the end of the XISB file.
+    #[error(
+        "data block of {length} bytes at position {start} beyond the end of {path} \
+         ({file_size} bytes)"
+    )]
+    BlockOutOfBounds {
+        /// Path of the XISB file.
+        path: String,
+        /// Position of the block in the file.
+        start: u64,
+        /// Length of the block in bytes.
+        length: u64,
+        /// Size of the XISB file in bytes.
+        file_size: u64,
+    },
+    /// The image data attachment starts at `start`, before the end of the XML header
+    /// (`header_end`).
+    #[error(
+        "image data overlaps the XML header: it starts at position {start}, but the header \
+         ends at {header_end}"
+    )]
+    AttachmentOverlapsHeader {
+        /// Position of the attachment in the file.
+        start: u64,
+        /// Position of the end of the XML header.
+        header_end: u64,
+    },
+    /// The image data is compressed with an unknown codec.
+    #[error("unsupported compression codec: {codec}")]
+    UnsupportedCompression {
+        /// Name of the codec.
+        codec: String,
+    },
+    /// The compressed image data can't be uncompressed.
+    #[error("can't uncompress {codec} image data: {source}")]
+    Decompression {
+        /// Name of the codec.
+        codec: String,
+        /// Error of the decoder.
+        source: io::Error,
+    },
+    /// The image data declared by the location (or the compression, for compressed data)
+    /// doesn't have the size of the image geometry (only an error in strict mode).
+    #[error("image data of {length} bytes, but the geometry has {expected} bytes")]
+    DataSizeMismatch {
+        /// Size of the image data in bytes.
+        length: u64,
+        /// Size of the image geometry in bytes.
+        expected: u64,
+    },
+    /// The uncompressed image data doesn't have the size declared by the image.
+    #[error("uncompressed image data of {size} bytes, expected {expected} bytes")]
+    UncompressedSizeMismatch {
+        /// Size of the uncompressed data in bytes.
+        size: usize,
+        /// Declared uncompressed size in bytes.
+        expected: usize,
+    },
+    /// The uncompressed image data is larger than the size declared by the image.
+    #[error("uncompressed image data larger than the declared {expected} bytes")]
+    UncompressedSizeExceeded {
+        /// Declared uncompressed size in bytes.
+        expected: usize,
+    },
+    /// The image data can't be filled in with zeros in lenient mode: the image would be
+    /// larger than its stored data could plausibly hold (`max_size` bytes).
+    #[error(
+        "the missing image data can't be filled in: the image would be larger \
+         than the {max_size} bytes its data could hold"
+    )]
+    ImplausibleDataSize {
+        /// Maximum size of the image in bytes.
+        max_size: u64,
+    },
+    /// The image data holds fewer channels than declared by the image geometry.
+    #[error("truncated image data: {channels} of {expected} channels")]
+    TruncatedData {
+        /// Channels held by the image data.
+        channels: usize,
+        /// Channels of the image geometry.
+        expected: usize,
+    },
+    /// The checksum of the image data doesn't match the one declared by the image.
+    #[error("checksum mismatch: expected {expected}, got {got}")]
+    ChecksumMismatch {
+        /// Checksum declared by the image.
+        expected: String,
+        /// Checksum of the image data.
+        got: String,
+    },
+    /// The image doesn't declare a checksum (only an error in strict mode).
+    #[error("the image data has no checksum")]
+    MissingChecksum,
+    /// The file deviates from the XISF specification (only an error in strict mode).
+    #[error("{message}")]
+    SpecDeviation {
+        /// Description of the deviation.
+        message: String,
+    },
+}
+
+/// Errors found while parsing the attributes of the XML header. `element` is the
+/// name of the element, `attribute` the name of the attribute and `value` its raw value.
+#[derive(Debug, Error)]
+pub enum XisfParseError {
+    /// The attributes of the element are malformed (e.g. a value without quotes).
+    #[error("malformed attributes of {element} element: {source}")]
+    Attribute {
+        /// Name of the element.
+        element: Box<str>,
+        /// Error of the XML parser.
+        source: quick_xml::Error,
+    },
+    /// The value of the attribute isn't valid UTF-8 or has an unknown escape sequence.
+    #[error("can't decode {element}.{attribute} attribute {value:?}: {source}")]
+    Decode {
+        /// Name of the element.
+        element: Box<str>,
+        /// Name of the attribute.
+        attribute: Box<str>,
+        /// Raw value of the attribute.
+        value: String,
+        /// Error of the XML parser.
+        source: quick_xml::Error,
+    },
+    /// The value of the attribute isn't a valid integer.
+    #[error("invalid integer in {element}.{attribute} attribute {value:?}: {source}")]
+    Integer {
+        /// Name of the element.
+        element: Box<str>,
+        /// Name of the attribute.
+        attribute: Box<str>,
+        /// Raw value of the attribute.
+        value: String,
+        /// Error parsing the integer.
+        source: ParseIntError,
+    },
+    /// The geometry of the image isn't valid ("dim1:...:dimN:channel-count").
+    #[error("invalid image geometry in {element}.{attribute} attribute: {value:?}")]
+    Geometry {
+        /// Name of the element.
+        element: Box<str>,
+        /// Name of the attribute.
+        attribute: Box<str>,
+        /// Raw value of the attribute.
+        value: String,
+    },
+}
+
+/// Errors found while parsing a FITS header record (an 80 bytes card).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum FitsRecordParseError {
+    /// The record isn't 80 bytes long.
+    #[error("FITS records are 80 bytes long, found {length} bytes")]
+    Length {
+        /// Length of the record in bytes.
+        length: usize,
+    },
+    /// The record has a byte other than a printable ASCII character (`column` is 1-based).
+    #[error("invalid byte {byte:#04x} at column {column} of the FITS record")]
+    InvalidByte {
+        /// The invalid byte.
+        byte: u8,
+        /// Column of the byte (1-based).
+        column: usize,
+    },
+    /// A character string value isn't closed by a quote.
+    #[error("unterminated character string in the FITS record")]
+    UnterminatedString,
+}
+
+/// Error parsing an unknown XISF sample format.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unsupported sample format '{format}'")]
+pub struct SampleFormatParseError {
+    /// The sample format found.
+    pub format: String,
+}
+
+// Plural suffix of a count of `n` things
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
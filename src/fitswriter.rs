@@ -1,38 +1,192 @@
+//! FITS header keywords and writing of FITS headers and data.
+
+use crate::error::FitsRecordParseError;
 use log::info;
+#[cfg(feature = "json")]
+use serde::Serialize;
 use std::{
     fs::File,
     io::{self, BufWriter, Write},
     path::Path,
 };
 
-pub struct FitsHeaderData<'h> {
+/// Structure and data of a FITS HDU to be written.
+#[derive(Debug)]
+pub struct FitsHeaderData {
+    /// Number of bits per data value (BITPIX): 8, 16, 32, 64, -32 or -64.
     pub bitpix: i64,
+    /// Number of axes (NAXIS).
     pub naxis: u64,
-    pub naxis_vec: &'h [usize],
+    /// Length of each axis (NAXIS1, NAXIS2...).
+    pub naxis_vec: Vec<usize>,
+    /// Offset of the data values (BZERO).
     pub bzero: u64,
+    /// Scale of the data values (not written yet: BSCALE is always 1).
     pub bscale: u64,
+    /// Minimum data value (DATAMIN, not written yet).
     pub datamin: u64,
+    /// Maximum data value (DATAMAX, not written yet).
     pub datamax: u64,
+    /// Lines of the HISTORY cards.
     pub history: Vec<String>,
+    /// Lines of the COMMENT cards.
     pub comment: Vec<String>,
+    /// Keywords written after the mandatory cards.
+    pub extra_keywords: Vec<FITSKeyword>,
+    /// Data unit, already in FITS byte order.
     pub data_bytes: Box<[u8]>,
 }
 
-// Struct to store FITS keywords
-#[derive(Debug, Default)]
+/// Struct to store FITS keywords
+#[derive(Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
 pub struct FITSKeyword {
+    /// Keyword name (up to 8 characters).
     pub name: String,
+    /// Value as written in the card (strings include their quotes).
     pub value: String,
+    /// Comment of the card, or the text of commentary cards.
     pub comment: String,
 }
 
+impl FITSKeyword {
+    /// Creates a keyword whose value is written as is (numbers and logicals).
+    pub fn new(name: &str, value: &str, comment: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            comment: comment.to_owned(),
+        }
+    }
+
+    /// Creates a keyword with a character string value, quoted as FITS requires.
+    pub fn string(name: &str, value: &str, comment: &str) -> Self {
+        Self::new(name, &format!("'{}'", value.replace('\'', "''")), comment)
+    }
+
+    /// Parses a FITS header record (an 80 bytes card, e.g. of a FITS file). Values are
+    /// kept as written (character strings quoted), as the keywords of XISF files, and
+    /// the text of commentary records (`COMMENT`, `HISTORY`...) is the comment.
+    pub fn from_fits_record(record: &[u8]) -> Result<Self, FitsRecordParseError> {
+        if record.len() != 80 {
+            return Err(FitsRecordParseError::Length {
+                length: record.len(),
+            });
+        }
+        if let Some(column) = record.iter().position(|byte| !(b' '..=b'~').contains(byte)) {
+            return Err(FitsRecordParseError::InvalidByte {
+                byte: record[column],
+                column: column + 1,
+            });
+        }
+        let record: String = record.iter().map(|&byte| char::from(byte)).collect();
+        let name = record[..8].trim_end();
+        if &record[8..10] != "= " {
+            return Ok(Self::new(name, "", record[8..].trim_end()));
+        }
+
+        let field = &record[10..];
+        let start = field.len() - field.trim_start().len();
+        let (value, rest) = if field[start..].starts_with('\'') {
+            // The string ends at the first quote not followed by another one ('' is a
+            // quote in the string)
+            let bytes = field.as_bytes();
+            let mut end = start + 1;
+            loop {
+                match bytes.get(end) {
+                    None => return Err(FitsRecordParseError::UnterminatedString),
+                    Some(b'\'') if bytes.get(end + 1) == Some(&b'\'') => end += 2,
+                    Some(b'\'') => break,
+                    Some(_) => end += 1,
+                }
+            }
+            (&field[start..=end], &field[end + 1..])
+        } else {
+            match field.find('/') {
+                Some(slash) => (&field[..slash], &field[slash..]),
+                None => (field, ""),
+            }
+        };
+        let comment = match rest.find('/') {
+            Some(slash) => rest[slash + 1..].trim(),
+            None => "",
+        };
+        Ok(Self::new(name, value.trim(), comment))
+    }
+
+    /// Creates a keyword with a (finite) real value, written with an exponent when it's
+    /// very large or small (e.g. 1E300), so it fits in the card.
+    pub fn real(name: &str, value: f64, comment: &str) -> Self {
+        let magnitude = value.abs();
+        let value = if magnitude == 0.0 || (1e-4..1e15).contains(&magnitude) {
+            value.to_string()
+        } else {
+            format!("{:E}", value)
+        };
+        Self::new(name, &value, comment)
+    }
+}
+
+/// Gets the DATE keyword with the current date and time (UTC), when the file is written.
+#[cfg(feature = "date")]
+pub fn fits_date_keyword() -> Option<FITSKeyword> {
+    // RFC 3339 timestamps are ISO-8601, FITS dates don't have the "Z" suffix
+    let now = humantime::format_rfc3339_seconds(std::time::SystemTime::now()).to_string();
+    Some(FITSKeyword::string(
+        "DATE",
+        now.trim_end_matches('Z'),
+        "Date the file was written (UTC)",
+    ))
+}
+
+/// Gets the DATE keyword (disabled without the `date` feature).
+#[cfg(not(feature = "date"))]
+pub fn fits_date_keyword() -> Option<FITSKeyword> {
+    None
+}
+
+// Write the DATE keyword, unless one of the keywords is DATE
+fn fits_write_date<W>(fits: &mut W, keywords: &[&[FITSKeyword]], bytes: &mut u64) -> io::Result<()>
+where
+    W: Write,
+{
+    let has_date = keywords
+        .iter()
+        .any(|keywords| keywords.iter().any(|keyword| keyword.name == "DATE"));
+    match fits_date_keyword() {
+        Some(date) if !has_date => fits_write_keywords(fits, &[date], bytes),
+        _ => Ok(()),
+    }
+}
+
+/// Length of the value of a card, after the `= ` value indicator.
+const FITS_VALUE_LENGTH: usize = 70;
+
+// FITS headers only allow printable ASCII characters: others are replaced by '?'
+fn fits_ascii(string: &str) -> String {
+    string
+        .chars()
+        .map(|c| {
+            if c == ' ' || c.is_ascii_graphic() {
+                c
+            } else {
+                '?'
+            }
+        })
+        .collect()
+}
+
 // Private functions to write the FITS headers to disk
 fn fits_write_header<W>(fits: &mut W, string: &str, bytes: &mut u64) -> io::Result<()>
 where
     W: Write,
 {
-    let mut header = string.to_string();
+    // Cards are 80 bytes long
+    let mut header = fits_ascii(string);
     header.truncate(80);
+    while header.len() < 80 {
+        header.push(' ');
+    }
     info!("FITS header: \"{}\"", header);
     let header_bytes = header.as_bytes();
     fits.write_all(header_bytes)?;
@@ -50,7 +204,7 @@ fn fits_write_header_u64<W>(
 where
     W: Write,
 {
-    let string = format!("{:8} = {:<19} / {:47}", header, value, comment);
+    let string = format!("{:8}= {:<20} / {:47}", header, value, comment);
     fits_write_header(fits, &string, bytes)
 }
 
@@ -64,7 +218,7 @@ fn fits_write_header_i64<W>(
 where
     W: Write,
 {
-    let string = format!("{:8} = {:<19} / {:47}", header, value, comment);
+    let string = format!("{:8}= {:<20} / {:47}", header, value, comment);
     fits_write_header(fits, &string, bytes)
 }
 
@@ -78,7 +232,7 @@ fn fits_write_header_string<W>(
 where
     W: Write,
 {
-    let string = format!("{:8} = {:<19} / {:48}", header, value, comment);
+    let string = format!("{:8}= {:<20} / {:48}", header, value, comment);
     fits_write_header(fits, &string, bytes)
 }
 
@@ -103,6 +257,107 @@ where
     fits_write_header(fits, &string, bytes)
 }
 
+fn fits_write_keywords<W>(
+    fits: &mut W,
+    fits_keywords: &[FITSKeyword],
+    bytes: &mut u64,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    for keyword in fits_keywords {
+        if keyword.name == "HISTORY" || keyword.name == "COMMENT" {
+            fits_write_header_comment(fits, &keyword.name, &keyword.comment, bytes)?;
+        } else {
+            let value = fits_ascii(&keyword.value);
+            if value.len() > FITS_VALUE_LENGTH && value.starts_with('\'') && value.ends_with('\'') {
+                fits_write_header_long_string(
+                    fits,
+                    &keyword.name,
+                    &value,
+                    &keyword.comment,
+                    bytes,
+                )?;
+            } else {
+                fits_write_header_string(fits, &keyword.name, &value, &keyword.comment, bytes)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Write a string value that doesn't fit in a card, split in CONTINUE cards
+// (long string convention): each part but the last one ends with '&'
+fn fits_write_header_long_string<W>(
+    fits: &mut W,
+    header: &str,
+    value: &str,
+    comment: &str,
+    bytes: &mut u64,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    // Split the unquoted string, so that each quoted part fits in a card with its '&'
+    let string = value[1..value.len() - 1].replace("''", "'");
+    let mut parts = vec![String::new()];
+    for c in string.chars() {
+        let escaped = if c == '\'' {
+            "''".to_owned()
+        } else {
+            c.to_string()
+        };
+        if parts[parts.len() - 1].len() + escaped.len() > FITS_VALUE_LENGTH - 3 {
+            parts.push(String::new());
+        }
+        let last = parts.len() - 1;
+        parts[last].push_str(&escaped);
+    }
+    let last = parts.len() - 1;
+    for (i, part) in parts.iter().enumerate() {
+        let name = if i == 0 { header } else { "CONTINUE" };
+        let indicator = if i == 0 { "= " } else { "  " };
+        let string = if i == last {
+            format!("{:8}{}'{}' / {}", name, indicator, part, comment)
+        } else {
+            format!("{:8}{}'{}&'", name, indicator, part)
+        };
+        fits_write_header(fits, &string, bytes)?;
+    }
+    Ok(())
+}
+
+// Write each non-empty line as a commentary card (HISTORY or COMMENT)
+fn fits_write_commentary<W>(
+    fits: &mut W,
+    header: &str,
+    lines: &[String],
+    bytes: &mut u64,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    for line in lines.iter().filter(|line| !line.is_empty()) {
+        fits_write_header_comment(fits, header, line, bytes)?;
+    }
+    Ok(())
+}
+
+// Fill the rest of the 2880 byte-block of the header with spaces
+fn fits_write_header_padding<W>(fits: &mut W, bytes: u64) -> io::Result<()>
+where
+    W: Write,
+{
+    let rest = bytes % 2880;
+    if rest > 0 {
+        let rest = 2880 - rest;
+        for _i in 0..rest {
+            fits.write_all(b" ")?;
+        }
+    }
+    Ok(())
+}
+
 fn fits_write_image_data<W>(fits: &mut W, fits_hd: &FitsHeaderData, _bytes: u64) -> io::Result<()>
 where
     W: Write,
@@ -110,7 +365,15 @@ where
     // Write Data Unit
     info!("FITS write > Write image data");
     fits.write_all(&fits_hd.data_bytes)?;
-    let total = fits_hd.data_bytes.len() as u64;
+    fits_write_data_padding(fits, fits_hd.data_bytes.len() as u64)
+}
+
+/// Fill the rest of the 2880 byte-block of the data unit with zeros, once `total`
+/// bytes of image data have been written
+pub fn fits_write_data_padding<W>(fits: &mut W, total: u64) -> io::Result<()>
+where
+    W: Write,
+{
     let data_unit_rest = total % 2880;
     info!("FITS write > Write image data > Bytes total: {}", total);
     // Write Data Unit (fill the rest of the 2880 byte-block)
@@ -124,49 +387,153 @@ where
     Ok(())
 }
 
+/// Write FITS data to a file
 pub fn fits_write_data(filename: &Path, fits_hd: &FitsHeaderData) -> io::Result<()> {
     info!("FITS write > File name > {}", filename.display());
     let mut fits = BufWriter::new(File::create(filename)?);
-    let mut bytes = 0;
+    fits_write_data_to(&mut fits, fits_hd)
+}
 
-    // Write HDU
-    info!("FITS write > Write headers");
-    fits_write_header_string(&mut fits, "SIMPLE", "T", "", &mut bytes)?;
-    fits_write_header_i64(&mut fits, "BITPIX", fits_hd.bitpix, "", &mut bytes)?;
-    fits_write_header_u64(&mut fits, "NAXIS", fits_hd.naxis, "", &mut bytes)?;
+/// Write FITS data to any writer (e.g. a file or a buffer in memory)
+pub fn fits_write_data_to<W>(fits: &mut W, fits_hd: &FitsHeaderData) -> io::Result<()>
+where
+    W: Write,
+{
+    fits_write_header_to(fits, fits_hd)?;
+
+    // Write Data Unit
+    fits_write_image_data(fits, &fits_hd, 0)?;
+    Ok(())
+}
+
+/// Write the FITS header only. The image data must be written afterwards,
+/// followed by `fits_write_data_padding`.
+pub fn fits_write_header_to<W>(fits: &mut W, fits_hd: &FitsHeaderData) -> io::Result<()>
+where
+    W: Write,
+{
+    fits_write_header_keywords_to(fits, fits_hd, &[])
+}
+
+// Write the mandatory cards of the primary header, describing the data as written
+fn fits_write_primary_cards<W>(
+    fits: &mut W,
+    fits_hd: &FitsHeaderData,
+    bytes: &mut u64,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    fits_write_header_string(fits, "SIMPLE", "T", "", bytes)?;
+    fits_write_header_i64(fits, "BITPIX", fits_hd.bitpix, "", bytes)?;
+    fits_write_header_u64(fits, "NAXIS", fits_hd.naxis, "", bytes)?;
     for i in 0..fits_hd.naxis_vec.len() {
         let header_name = format!("NAXIS{}", i + 1);
-        fits_write_header_u64(
-            &mut fits,
-            &header_name,
-            fits_hd.naxis_vec[i] as u64,
-            "",
-            &mut bytes,
-        )?;
-    }
-    fits_write_header_string(&mut fits, "EXTEND", "T", "", &mut bytes)?;
-    fits_write_header_string(&mut fits, "BZERO", "0", "", &mut bytes)?;
-    fits_write_header_string(&mut fits, "BSCALE", "1", "", &mut bytes)?;
+        fits_write_header_u64(fits, &header_name, fits_hd.naxis_vec[i] as u64, "", bytes)?;
+    }
+    fits_write_header_string(fits, "EXTEND", "T", "", bytes)?;
+    fits_write_header_u64(fits, "BZERO", fits_hd.bzero, "", bytes)?;
+    fits_write_header_string(fits, "BSCALE", "1", "", bytes)
     // fits_write_header_u64(&mut fits, "BSCALE", fits_hd.bscale, ""), &mut bytes)?;
     // fits_write_header_u64(&mut fits, "DATAMIN", fits_hd.datamin, ""), &mut bytes)?;
     // fits_write_header_u64(&mut fits, "DATAMAX", fits_hd.datamax, ""), &mut bytes)?;
-    fits_write_header_no_comment(&mut fits, "END", &mut bytes)?;
+}
 
-    // Write HDU (fill the rest of the 2880 byte-block)
-    let rest = bytes % 2880;
-    if rest > 0 {
-        let rest = 2880 - rest;
-        for _i in 0..rest {
-            fits.write_all(b" ")?;
+// Whether a keyword describes the original HDU: its structure (e.g. BITPIX or NAXIS1),
+// written from `FitsHeaderData` as the data is converted, or its checksums, which don't
+// apply to the converted data.
+fn fits_reserved_keyword(name: &str) -> bool {
+    match name {
+        "SIMPLE" | "BITPIX" | "NAXIS" | "EXTEND" | "BZERO" | "BSCALE" | "XTENSION" | "PCOUNT"
+        | "GCOUNT" | "END" | "CHECKSUM" | "DATASUM" => true,
+        _ => {
+            name.len() > 5
+                && name.starts_with("NAXIS")
+                && name[5..].bytes().all(|byte| byte.is_ascii_digit())
         }
     }
+}
+
+/// Write an IMAGE extension (to be appended after the primary HDU)
+pub fn fits_write_image_extension_to<W>(fits: &mut W, fits_hd: &FitsHeaderData) -> io::Result<()>
+where
+    W: Write,
+{
+    fits_write_image_extension_header_to(fits, fits_hd)?;
 
     // Write Data Unit
-    fits_write_image_data(&mut fits, &fits_hd, bytes)?;
+    fits_write_image_data(fits, &fits_hd, 0)?;
     Ok(())
 }
 
-// Write FITS data, but use FITS keywords for the header
+// Write the header of an IMAGE extension only
+fn fits_write_image_extension_header_to<W>(fits: &mut W, fits_hd: &FitsHeaderData) -> io::Result<()>
+where
+    W: Write,
+{
+    let mut bytes = 0;
+
+    // Write extension header
+    info!("FITS write > Write extension headers");
+    fits_write_header_string(fits, "XTENSION", "'IMAGE   '", "", &mut bytes)?;
+    fits_write_header_i64(fits, "BITPIX", fits_hd.bitpix, "", &mut bytes)?;
+    fits_write_header_u64(fits, "NAXIS", fits_hd.naxis, "", &mut bytes)?;
+    for (i, naxis) in fits_hd.naxis_vec.iter().enumerate() {
+        let header_name = format!("NAXIS{}", i + 1);
+        fits_write_header_u64(fits, &header_name, *naxis as u64, "", &mut bytes)?;
+    }
+    fits_write_header_string(fits, "PCOUNT", "0", "", &mut bytes)?;
+    fits_write_header_string(fits, "GCOUNT", "1", "", &mut bytes)?;
+    fits_write_header_u64(fits, "BZERO", fits_hd.bzero, "", &mut bytes)?;
+    fits_write_header_string(fits, "BSCALE", "1", "", &mut bytes)?;
+    fits_write_keywords(fits, &fits_hd.extra_keywords, &mut bytes)?;
+    fits_write_commentary(fits, "HISTORY", &fits_hd.history, &mut bytes)?;
+    fits_write_commentary(fits, "COMMENT", &fits_hd.comment, &mut bytes)?;
+    fits_write_header_no_comment(fits, "END", &mut bytes)?;
+
+    // Write extension header (fill the rest of the 2880 byte-block)
+    fits_write_header_padding(fits, bytes)
+}
+
+/// Kind of HDU written by `fits_write_hdu_to`.
+#[derive(Debug, Clone, Copy)]
+pub enum FitsHdu<'a> {
+    /// The primary HDU, with the given FITS keywords (see `fits_write_header_keywords_to`).
+    Primary(&'a [FITSKeyword]),
+    /// An IMAGE extension, appended after the primary HDU.
+    ImageExtension,
+}
+
+/// Writes a FITS HDU (its header and data unit). With `checksum`, its CHECKSUM and
+/// DATASUM keywords are added to the header, which is written to memory first to fill
+/// them in.
+pub fn fits_write_hdu_to<W>(
+    fits: &mut W,
+    mut fits_hd: FitsHeaderData,
+    hdu: FitsHdu<'_>,
+    checksum: bool,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    if checksum {
+        fits_hd.extra_keywords.append(&mut fits_checksum_keywords());
+    }
+    let mut header = Vec::new();
+    match hdu {
+        FitsHdu::Primary(fits_keywords) => {
+            fits_write_header_keywords_to(&mut header, &fits_hd, fits_keywords)
+        }
+        FitsHdu::ImageExtension => fits_write_image_extension_header_to(&mut header, &fits_hd),
+    }?;
+    if checksum {
+        fits_update_checksum(&mut header, &fits_hd.data_bytes)?;
+    }
+    fits.write_all(&header)?;
+    fits_write_image_data(fits, &fits_hd, 0)
+}
+
+/// Write FITS data, but use FITS keywords for the header
 pub fn fits_write_data_keywords(
     filename: &Path,
     fits_hd: &FitsHeaderData,
@@ -174,27 +541,146 @@ pub fn fits_write_data_keywords(
 ) -> io::Result<()> {
     info!("FITS write > File name > {}", filename.display());
     let mut fits = File::create(filename)?;
+    fits_write_data_keywords_to(&mut fits, fits_hd, fits_keywords)
+}
+
+/// Write FITS data to any writer, but use FITS keywords for the header
+pub fn fits_write_data_keywords_to<W>(
+    fits: &mut W,
+    fits_hd: &FitsHeaderData,
+    fits_keywords: &[FITSKeyword],
+) -> io::Result<()>
+where
+    W: Write,
+{
+    fits_write_header_keywords_to(fits, fits_hd, fits_keywords)?;
+
+    // Write Data Unit
+    fits_write_image_data(fits, &fits_hd, 0)?;
+
+    Ok(())
+}
+
+/// Write the FITS header only, with the FITS keywords of the XISF file after the mandatory
+/// cards (the ones describing the structure of the HDU are skipped, as the data written
+/// may differ from the original one). The image data must be written afterwards, followed
+/// by `fits_write_data_padding`.
+pub fn fits_write_header_keywords_to<W>(
+    fits: &mut W,
+    fits_hd: &FitsHeaderData,
+    fits_keywords: &[FITSKeyword],
+) -> io::Result<()>
+where
+    W: Write,
+{
     let mut bytes = 0;
+    let fits_keywords: Vec<_> = fits_keywords
+        .iter()
+        .filter(|keyword| !fits_reserved_keyword(&keyword.name))
+        .cloned()
+        .collect();
+    let fits_keywords = &fits_keywords[..];
 
     // Write HDU
     info!("FITS write > Write headers");
-    for keyword in fits_keywords.iter() {
-        if keyword.name == "HISTORY" || keyword.name == "COMMENT" {
-            fits_write_header_comment(&mut fits, &keyword.name, &keyword.comment, &mut bytes)?;
-        } else {
-            fits_write_header_string(
-                &mut fits,
-                &keyword.name,
-                &keyword.value,
-                &keyword.comment,
-                &mut bytes,
-            )?;
+    fits_write_primary_cards(fits, fits_hd, &mut bytes)?;
+    fits_write_keywords(fits, fits_keywords, &mut bytes)?;
+    fits_write_date(fits, &[fits_keywords, &fits_hd.extra_keywords], &mut bytes)?;
+    fits_write_keywords(fits, &fits_hd.extra_keywords, &mut bytes)?;
+    fits_write_commentary(fits, "HISTORY", &fits_hd.history, &mut bytes)?;
+    fits_write_commentary(fits, "COMMENT", &fits_hd.comment, &mut bytes)?;
+    fits_write_header_no_comment(fits, "END", &mut bytes)?;
+
+    // Write HDU (fill the rest of the 2880 byte-block)
+    fits_write_header_padding(fits, bytes)
+}
+
+/// Gets placeholder CHECKSUM and DATASUM keywords, to be filled in by
+/// `fits_update_checksum` once the header and the data unit are known.
+pub fn fits_checksum_keywords() -> Vec<FITSKeyword> {
+    vec![
+        FITSKeyword::string("CHECKSUM", "0000000000000000", "HDU checksum"),
+        FITSKeyword::string("DATASUM", "0", "Data unit checksum"),
+    ]
+}
+
+// Add the 32 bits words (big endian) of `bytes` to the ones' complement checksum `sum`.
+// A last partial word is padded with zeros, as the data unit is.
+#[allow(clippy::cast_possible_truncation)]
+fn fits_checksum(bytes: &[u8], sum: u32) -> u32 {
+    let mut hi = u64::from(sum >> 16);
+    let mut lo = u64::from(sum & 0xffff);
+    for word in bytes.chunks(4) {
+        let mut padded = [0; 4];
+        padded[..word.len()].copy_from_slice(word);
+        hi += u64::from(u16::from_be_bytes([padded[0], padded[1]]));
+        lo += u64::from(u16::from_be_bytes([padded[2], padded[3]]));
+    }
+    // Fold the carries of each half into the other one
+    while hi > 0xffff || lo > 0xffff {
+        let (hi_carry, lo_carry) = (hi >> 16, lo >> 16);
+        hi = (hi & 0xffff) + lo_carry;
+        lo = (lo & 0xffff) + hi_carry;
+    }
+    ((hi << 16) | lo) as u32
+}
+
+// Encode the complement of a checksum as 16 alphanumeric characters, so that the checksum
+// of the HDU including them is -0 (all bits set)
+fn fits_encode_checksum(sum: u32) -> String {
+    // Punctuation characters between the digits and the letters
+    let excluded = |c: u8| (0x3a..=0x40).contains(&c) || (0x5b..=0x60).contains(&c);
+    let mut ascii = [0; 16];
+    for (i, byte) in (!sum).to_be_bytes().iter().enumerate() {
+        let mut chars = [byte / 4 + b'0'; 4];
+        chars[0] += byte % 4;
+        let mut check = true;
+        while check {
+            check = false;
+            for j in (0..4).step_by(2) {
+                if excluded(chars[j]) || excluded(chars[j + 1]) {
+                    chars[j] += 1;
+                    chars[j + 1] -= 1;
+                    check = true;
+                }
+            }
+        }
+        for (j, c) in chars.iter().enumerate() {
+            ascii[4 * j + i] = *c;
         }
     }
-    fits_write_header_no_comment(&mut fits, "END", &mut bytes)?;
+    // Rotate the characters one position to the right
+    (0..16).map(|i| char::from(ascii[(i + 15) % 16])).collect()
+}
 
-    // Write Data Unit
-    fits_write_image_data(&mut fits, &fits_hd, bytes)?;
+// Find the position of the card of a keyword in a FITS header
+fn fits_find_card(header: &[u8], name: &str) -> Option<usize> {
+    let name = format!("{:8}", name);
+    header
+        .chunks(80)
+        .position(|card| card.len() == 80 && card[..8] == *name.as_bytes())
+        .map(|card| card * 80)
+}
 
+/// Fills in the CHECKSUM and DATASUM keywords of a FITS header (see
+/// `fits_checksum_keywords`) for the HDU made of the header and the `data` unit (its
+/// padding, made of zeros, doesn't change the checksum).
+pub fn fits_update_checksum(header: &mut [u8], data: &[u8]) -> io::Result<()> {
+    let missing = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "the FITS header has no CHECKSUM and DATASUM keywords",
+        )
+    };
+    let checksum_card = fits_find_card(header, "CHECKSUM").ok_or_else(missing)?;
+    let datasum_card = fits_find_card(header, "DATASUM").ok_or_else(missing)?;
+    let datasum = fits_checksum(data, 0);
+    info!("FITS write > Data unit checksum: {}", datasum);
+    let value = format!("{:<20}", format!("'{}'", datasum));
+    header[datasum_card + 10..datasum_card + 30].copy_from_slice(value.as_bytes());
+    let checksum = checksum_card + 11..checksum_card + 27;
+    header[checksum.clone()].copy_from_slice(b"0000000000000000");
+    let encoded = fits_encode_checksum(fits_checksum(header, datasum));
+    header[checksum].copy_from_slice(encoded.as_bytes());
     Ok(())
 }
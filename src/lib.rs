@@ -0,0 +1,802 @@
+//! XISFITS converts XISF images to FITS. Besides the command line tool, the
+//! conversion can be used as a library, e.g. with
+//! [`convert_xisf_to_fits`](fn.convert_xisf_to_fits.html) for images in memory.
+
+#![forbid(anonymous_parameters)]
+#![warn(clippy::pedantic)]
+#![deny(
+    clippy::all,
+    variant_size_differences,
+    unused_results,
+    unused_qualifications,
+    unused_import_braces,
+    unsafe_code,
+    trivial_numeric_casts,
+    trivial_casts,
+    missing_docs,
+    unused_extern_crates,
+    missing_debug_implementations,
+    missing_copy_implementations
+)]
+#![allow(clippy::must_use_candidate, clippy::missing_errors_doc)]
+
+#[cfg(feature = "async")]
+mod asyncreader;
+pub mod convert;
+pub mod error;
+pub mod fitswriter;
+pub mod xisfreader;
+
+use crate::{
+    error::XisfError,
+    fitswriter::FITSKeyword,
+    xisfreader::{XISFColorSpace, XISFData, XISFSampleFormat, XISFile, XisfReadOptions},
+};
+use log::{debug, info, log_enabled, Level};
+use std::{
+    convert::TryFrom,
+    fmt::{self, Write},
+    fs::File,
+    io::{BufWriter, Cursor, Write as _},
+    ops::Range,
+    path::Path,
+    str::FromStr,
+};
+
+/// Byte order used to write the FITS image data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Big endian, as the FITS standard requires.
+    Big,
+    /// Little endian (non-standard FITS).
+    Little,
+    /// Byte order of the machine running the conversion.
+    Native,
+}
+
+impl ByteOrder {
+    /// Resolves the native byte order to big or little endian.
+    #[must_use]
+    pub fn resolve(self) -> Self {
+        match self {
+            Self::Native if cfg!(target_endian = "little") => Self::Little,
+            Self::Native => Self::Big,
+            byte_order => byte_order,
+        }
+    }
+}
+
+impl FromStr for ByteOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "big" => Ok(Self::Big),
+            "little" => Ok(Self::Little),
+            "native" => Ok(Self::Native),
+            _ => Err(format!("unsupported byte order: {}", s)),
+        }
+    }
+}
+
+/// Steps of a conversion reported to a `ConversionProgress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversionStep {
+    /// The XML header has been parsed: the bytes of the input read so far (up to the
+    /// end of the XML header) of the input size.
+    ReadHeader,
+    /// The image data is being read from the file: the bytes read so far of the bytes
+    /// stored. Reported as the image data is read, and once it's done.
+    ReadData,
+    /// The image data has been read and, if compressed, decompressed: the bytes of
+    /// image data as stored in the file of its decompressed size.
+    Decompress,
+    /// The image data has been converted to the FITS data type: its size in bytes.
+    ConvertData,
+    /// The FITS file has been written: its size in bytes.
+    WriteFits,
+}
+
+impl fmt::Display for ConversionStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::ReadHeader => "read header",
+            Self::ReadData => "read data",
+            Self::Decompress => "decompress",
+            Self::ConvertData => "convert",
+            Self::WriteFits => "write",
+        })
+    }
+}
+
+/// Receives the progress of a conversion, e.g. to update a progress bar.
+pub trait ConversionProgress {
+    /// Called after each step of the conversion (and while the image data is read),
+    /// with the bytes done of the total bytes of the step (see `ConversionStep`).
+    fn on_step(&self, step: ConversionStep, bytes_done: u64, bytes_total: u64);
+}
+
+/// Progress receiver that ignores the progress, used when there's no other.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullProgress;
+
+impl ConversionProgress for NullProgress {
+    fn on_step(&self, _step: ConversionStep, _bytes_done: u64, _bytes_total: u64) {}
+}
+
+/// FITS data type of the converted image, overriding the one derived from the XISF
+/// sample format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// 16 bits integers (BITPIX 16).
+    I16,
+    /// 32 bits integers (BITPIX 32).
+    I32,
+    /// 32 bits floating point (BITPIX -32).
+    F32,
+    /// 64 bits floating point (BITPIX -64).
+    F64,
+}
+
+impl OutputFormat {
+    /// Gets the FITS BITPIX of the format.
+    pub fn bitpix(self) -> i64 {
+        match self {
+            Self::I16 => 16,
+            Self::I32 => 32,
+            Self::F32 => -32,
+            Self::F64 => -64,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "i16" => Ok(Self::I16),
+            "i32" => Ok(Self::I32),
+            "f32" => Ok(Self::F32),
+            "f64" => Ok(Self::F64),
+            _ => Err(format!("unsupported output format: {}", s)),
+        }
+    }
+}
+
+/// Format of the output file: a FITS file, with the data type of the image data
+/// overridden, or the raw pixel bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFileFormat {
+    /// FITS file with image data of the given type.
+    Fits(OutputFormat),
+    /// Pixel bytes of the FITS data unit, without FITS header nor padding.
+    Raw,
+}
+
+impl FromStr for OutputFileFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            _ => s.parse().map(Self::Fits),
+        }
+    }
+}
+
+/// How the channels of multichannel (e.g. RGB) images are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RgbMode {
+    /// A single HDU with the channels as the last axis (a cube).
+    Cube,
+    /// The first channel in the primary HDU, and the others in IMAGE extensions.
+    Extensions,
+    /// Each channel in its own FITS file.
+    Split,
+}
+
+impl Default for RgbMode {
+    fn default() -> Self {
+        Self::Cube
+    }
+}
+
+impl FromStr for RgbMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cube" => Ok(Self::Cube),
+            "extensions" => Ok(Self::Extensions),
+            "split" => Ok(Self::Split),
+            _ => Err(format!("unsupported RGB mode: {}", s)),
+        }
+    }
+}
+
+/// Channels of the image to convert, as a comma separated list of channel indices
+/// ("N") or inclusive ranges ("N:M"), e.g. "0,2" or "0:1".
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelSelection(Vec<usize>);
+
+impl ChannelSelection {
+    /// Gets the selected channel indices, in order.
+    pub fn indices(&self) -> &[usize] {
+        &self.0
+    }
+}
+
+impl FromStr for ChannelSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |index: &str| {
+            index
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid channel selection: {}", s))
+        };
+        let mut indices = Vec::new();
+        for item in s.split(',') {
+            let mut bounds = item.splitn(2, ':');
+            let first = parse(bounds.next().unwrap_or(""))?;
+            match bounds.next() {
+                Some(last) => {
+                    let last = parse(last)?;
+                    if last < first {
+                        return Err(format!("invalid channel selection: {}", s));
+                    }
+                    indices.extend(first..=last);
+                }
+                None => indices.push(first),
+            }
+        }
+        Ok(Self(indices))
+    }
+}
+
+/// Options of `XISFile::write_fits`.
+#[derive(Debug, Clone, Copy)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct XisfWriteOptions {
+    /// Store unsigned 16 and 32 bits samples as signed integers with an offset, which
+    /// BZERO undoes (the FITS convention). Otherwise their values are stored as they
+    /// are, in a wider data type (32 bits integers and 64 bits floats respectively).
+    pub bzero_correction: bool,
+    /// Write the CHECKSUM and DATASUM keywords of each HDU.
+    pub add_checksum: bool,
+    /// Write only this channel of the image, instead of all of them.
+    pub channel_filter: Option<usize>,
+    /// Write the first channel in the primary HDU and the others in IMAGE extensions
+    /// (named by channel, e.g. "RED"), instead of a single HDU with the channels as
+    /// the last axis.
+    pub split_hdus: bool,
+}
+
+impl Default for XisfWriteOptions {
+    fn default() -> Self {
+        XisfWriteOptions {
+            bzero_correction: true,
+            add_checksum: false,
+            channel_filter: None,
+            split_hdus: false,
+        }
+    }
+}
+
+/// Gets the name of a channel: RGB channels are named by color ("red", "green" and
+/// "blue"), other channels by number
+pub fn xisf_channel_name(channel: usize, color_space: XISFColorSpace) -> String {
+    match (color_space, channel) {
+        (XISFColorSpace::Rgb, 0) => "red".to_owned(),
+        (XISFColorSpace::Rgb, 1) => "green".to_owned(),
+        (XISFColorSpace::Rgb, 2) => "blue".to_owned(),
+        _ => channel.to_string(),
+    }
+}
+
+/// Convert XISF binary data to FITS format (Big Endian, unless other byte order is requested)
+pub fn xisf_data_to_fits(xisf_file: &XISFile, byte_order: ByteOrder) -> (Box<[u8]>, i64) {
+    let channel_count = xisf_file.header().geometry().channel_count();
+    xisf_channels_to_fits(xisf_file, byte_order, 0..channel_count)
+}
+
+/// Convert some channels of XISF binary data to FITS format
+pub fn xisf_channels_to_fits(
+    xisf_file: &XISFile,
+    byte_order: ByteOrder,
+    channels: Range<usize>,
+) -> (Box<[u8]>, i64) {
+    xisf_image_data_to_fits(xisf_file.data(), byte_order, channels)
+}
+
+/// Convert some channels of any XISF image (e.g. the thumbnail) to FITS format
+pub fn xisf_image_data_to_fits(
+    xisf_data: &XISFData,
+    byte_order: ByteOrder,
+    channels: Range<usize>,
+) -> (Box<[u8]>, i64) {
+    let little_endian = byte_order.resolve() == ByteOrder::Little;
+    let mut fits_data = Vec::new();
+    let mut bitpix = 0;
+
+    // +-----------+---------+------+
+    // | XISF      > Rust    > FITS |
+    // +-----------+---------+------+
+    // | UInt8     | u8      | 8    |
+    // | UInt16    | i16     | 16   |
+    // | UInt32    | i32     | 32   |
+    // | Int8      | i16     | 16   |
+    // | Int16     | i16     | 16   |
+    // | Int32     | i32     | 32   |
+    // | Float32   | f32     | -32  |
+    // | Float64   | f64     | -64  |
+    // | Complex32 | 2 x f32 | -32  |
+    // | Complex64 | 2 x f64 | -64  |
+    // +-----------+---------+------+
+    match xisf_data {
+        XISFData::UInt8(ref data) => {
+            info!("XISF data to FITS > UInt8");
+            bitpix = 8;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                fits_data.extend_from_slice(channel);
+            }
+        }
+        XISFData::UInt16(ref data) => {
+            info!("XISF data to FITS > UInt16");
+            bitpix = 16;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::u16_to_i16_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::u16_to_i16_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::UInt32(ref data) => {
+            info!("XISF data to FITS > UInt32");
+            bitpix = 32;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::u32_to_i32_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::u32_to_i32_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::Int8(ref data) => {
+            info!("XISF data to FITS > Int8");
+            bitpix = 16;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::i8_to_i16_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::i8_to_i16_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::Int16(ref data) => {
+            info!("XISF data to FITS > Int16");
+            bitpix = 16;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::i16_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::i16_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::Int32(ref data) => {
+            info!("XISF data to FITS > Int32");
+            bitpix = 32;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::i32_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::i32_to_v_u8_be(channel));
+                }
+            }
+        }
+        // XISFData::UInt64(ref data) => unimplemented!(),
+        XISFData::Float32(ref data) => {
+            info!("XISF data to FITS > Float32");
+            bitpix = -32;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::f32_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::f32_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::Float64(ref data) => {
+            info!("XISF data to FITS > Float64");
+            bitpix = -64;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::f64_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::f64_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::Complex32(ref data) => {
+            info!("XISF data to FITS > Complex32");
+            bitpix = -32;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::complex32_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::complex32_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::Complex64(ref data) => {
+            info!("XISF data to FITS > Complex64");
+            bitpix = -64;
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                if little_endian {
+                    fits_data.append(&mut convert::complex64_to_v_u8_le(channel));
+                } else {
+                    fits_data.append(&mut convert::complex64_to_v_u8_be(channel));
+                }
+            }
+        }
+        XISFData::Empty => {}
+    }
+
+    // Show the first 20 bytes of the converted image when debugging
+    if log_enabled!(Level::Debug) && fits_data.len() > 20 {
+        let mut message = String::with_capacity(20 * 3);
+        for byte in fits_data.iter().take(20) {
+            let _ = write!(message, "{:x} ", byte);
+        }
+        debug!("{}", message);
+    }
+
+    (fits_data.into_boxed_slice(), bitpix)
+}
+
+/// Convert some channels of XISF float data to FITS 16 bits integers, rescaling the
+/// bounds (lower, upper) to the full 16 bits range. The FITS values are stored with
+/// BZERO = 32768. Returns `None` if the data isn't floating point.
+pub fn xisf_channels_to_fits_bounded(
+    xisf_file: &XISFile,
+    byte_order: ByteOrder,
+    channels: Range<usize>,
+    bounds: (f64, f64),
+) -> Option<Box<[u8]>> {
+    let little_endian = byte_order.resolve() == ByteOrder::Little;
+    let (lower, upper) = bounds;
+    let mut values = Vec::new();
+    match xisf_file.data() {
+        XISFData::Float32(ref data) => {
+            info!("XISF data to FITS > Float32 > Bounds {}:{}", lower, upper);
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                values.append(&mut convert::float_to_v_i16_scaled(channel, lower, upper));
+            }
+        }
+        XISFData::Float64(ref data) => {
+            info!("XISF data to FITS > Float64 > Bounds {}:{}", lower, upper);
+            for channel in data.iter().skip(channels.start).take(channels.len()) {
+                values.append(&mut convert::float_to_v_i16_scaled(channel, lower, upper));
+            }
+        }
+        _ => return None,
+    }
+
+    let fits_data = if little_endian {
+        convert::i16_to_v_u8_le(&values)
+    } else {
+        convert::i16_to_v_u8_be(&values)
+    };
+    Some(fits_data.into_boxed_slice())
+}
+
+/// Convert some channels of XISF data to the FITS data type of `format`, storing the
+/// values as they are (BZERO = 0). Returns `None` for complex data.
+///
+/// Precision may be lost: float values are rounded to integers, values out of the
+/// range of the integer format are clipped (e.g. `UInt16` values above 32767 with `I16`),
+/// and 32 bits integers above 2^24 don't fit exactly in `F32`.
+pub fn xisf_channels_to_fits_as(
+    xisf_file: &XISFile,
+    byte_order: ByteOrder,
+    channels: Range<usize>,
+    format: OutputFormat,
+) -> Option<Box<[u8]>> {
+    fn values<T: Copy>(
+        data: &[Box<[T]>],
+        channels: Range<usize>,
+        value: impl Fn(T) -> f64,
+    ) -> Vec<f64> {
+        data.iter()
+            .skip(channels.start)
+            .take(channels.len())
+            .flat_map(|channel| channel.iter().map(|v| value(*v)))
+            .collect()
+    }
+    info!("XISF data to FITS > Output format {:?}", format);
+    let values = match xisf_file.data() {
+        XISFData::UInt8(ref data) => values(data, channels, f64::from),
+        XISFData::UInt16(ref data) => values(data, channels, f64::from),
+        XISFData::UInt32(ref data) => values(data, channels, f64::from),
+        XISFData::Int8(ref data) => values(data, channels, f64::from),
+        XISFData::Int16(ref data) => values(data, channels, f64::from),
+        XISFData::Int32(ref data) => values(data, channels, f64::from),
+        XISFData::Float32(ref data) => values(data, channels, f64::from),
+        XISFData::Float64(ref data) => values(data, channels, |value| value),
+        _ => return None,
+    };
+
+    let little_endian = byte_order.resolve() == ByteOrder::Little;
+    let fits_data = match format {
+        OutputFormat::I16 => {
+            let values = convert::float_to_v_i16_saturated(&values);
+            if little_endian {
+                convert::i16_to_v_u8_le(&values)
+            } else {
+                convert::i16_to_v_u8_be(&values)
+            }
+        }
+        OutputFormat::I32 => {
+            let values = convert::float_to_v_i32_saturated(&values);
+            if little_endian {
+                convert::i32_to_v_u8_le(&values)
+            } else {
+                convert::i32_to_v_u8_be(&values)
+            }
+        }
+        OutputFormat::F32 => {
+            #[allow(clippy::cast_possible_truncation)]
+            let values: Vec<f32> = values.iter().map(|value| *value as f32).collect();
+            if little_endian {
+                convert::f32_to_v_u8_le(&values)
+            } else {
+                convert::f32_to_v_u8_be(&values)
+            }
+        }
+        OutputFormat::F64 => {
+            if little_endian {
+                convert::f64_to_v_u8_le(&values)
+            } else {
+                convert::f64_to_v_u8_be(&values)
+            }
+        }
+    };
+    Some(fits_data.into_boxed_slice())
+}
+
+/// Layout of complex data in the FITS file, written as a COMMENT card
+pub const COMPLEX_LAYOUT_COMMENT: &str =
+    "Complex data: NAXIS1 = 2 holds the real and imaginary parts";
+
+/// Gets the FITS axis sizes (NAXIS1, NAXIS2, ...) of an image with the given spatial
+/// `dimensions` and `channel_count` channels:
+///
+/// 1. For complex images, an axis of size 2 holding the real and imaginary parts
+///    (FITS has no complex numbers).
+/// 2. The spatial dimensions, in the same order as XISF (NAXIS1 = width, NAXIS2 =
+///    height, NAXIS3 = depth of 3-D images, ...).
+/// 3. For multichannel images, the channels, stored one after another.
+///
+/// E.g. the XISF geometry "64:64:10:3" (a cube of 3 channels) has the FITS axes
+/// `[64, 64, 10, 3]`, and "64:64:10:1" has `[64, 64, 10]`.
+pub fn fits_axes(
+    dimensions: &[usize],
+    channel_count: usize,
+    sample_format: XISFSampleFormat,
+) -> Vec<usize> {
+    let mut naxis_vec = Vec::with_capacity(dimensions.len() + 2);
+    if sample_format.is_complex() {
+        naxis_vec.push(2);
+    }
+    naxis_vec.extend_from_slice(dimensions);
+    if channel_count > 1 {
+        naxis_vec.push(channel_count);
+    }
+    naxis_vec
+}
+
+/// Build the FITS header of the converted image (the image data of `channel_count`
+/// channels must be in `data_bytes`, with the given BITPIX)
+pub fn fits_header_data(
+    xisf_file: &XISFile,
+    data_bytes: Box<[u8]>,
+    bitpix: i64,
+    channel_count: usize,
+) -> fitswriter::FitsHeaderData {
+    let mut extra_keywords = xisf_file.header().fits_keywords();
+    // The original FITS keywords take precedence over those derived from the XISF header
+    // (but commentary keywords, which may be repeated)
+    extra_keywords.retain(|keyword| {
+        keyword.name == "COMMENT"
+            || keyword.name == "HISTORY"
+            || xisf_file
+                .keywords()
+                .iter()
+                .all(|original| original.name != keyword.name)
+    });
+    let mut comment = Vec::new();
+    let sample_format = xisf_file.header().sample_format();
+    let naxis_vec = fits_axes(
+        xisf_file.header().geometry().dimensions(),
+        channel_count,
+        sample_format,
+    );
+    if sample_format.is_complex() {
+        comment.push(COMPLEX_LAYOUT_COMMENT.to_owned());
+    }
+    let offset = xisf_file.header().offset();
+    if offset != 0.0 {
+        extra_keywords.push(FITSKeyword::new(
+            "PEDESTAL",
+            &offset.to_string(),
+            "Value added to the data (XISF offset)",
+        ));
+    }
+    fitswriter::FitsHeaderData {
+        bitpix,
+        naxis: naxis_vec.len() as u64,
+        naxis_vec,
+        bzero: fits_bzero(xisf_file.header().sample_format()),
+        bscale: 1,
+        datamin: 0,
+        datamax: 0,
+        history: xisf_file.header().history(),
+        comment,
+        extra_keywords,
+        data_bytes,
+    }
+}
+
+/// Reorder the FITS image data of `channel_count` channels so the samples of each pixel
+/// are contiguous (RGBRGB...). The channel axis becomes the first one (the second one
+/// for complex data, after the real and imaginary parts).
+pub fn fits_interleave_channels(
+    fits_hd: &mut fitswriter::FitsHeaderData,
+    channel_count: usize,
+    sample_format: XISFSampleFormat,
+) {
+    if channel_count < 2 {
+        return;
+    }
+    let complex = sample_format.is_complex();
+    let value_size = usize::try_from(fits_hd.bitpix.abs() / 8).unwrap_or(1);
+    let sample_size = if complex { 2 * value_size } else { value_size };
+    let samples: Vec<&[u8]> = fits_hd.data_bytes.chunks(sample_size).collect();
+    fits_hd.data_bytes = convert::interleave(&samples, channel_count)
+        .concat()
+        .into_boxed_slice();
+    if let Some(channel_axis) = fits_hd.naxis_vec.pop() {
+        fits_hd.naxis_vec.insert(usize::from(complex), channel_axis);
+    }
+}
+
+/// Gets the FITS BZERO of the converted data. Unsigned integers are stored as signed
+/// integers with an offset, which BZERO undoes.
+pub fn fits_bzero(sample_format: XISFSampleFormat) -> u64 {
+    match sample_format {
+        XISFSampleFormat::UInt16 => 32768,
+        XISFSampleFormat::UInt32 => 2_147_483_648,
+        _ => 0,
+    }
+}
+
+/// Convert a XISF image in memory to a FITS file in memory (big endian data).
+pub fn convert_xisf_to_fits(input: &[u8]) -> Result<Vec<u8>, XisfError> {
+    convert_xisf_to_fits_with_progress(input, None)
+}
+
+/// Convert a XISF image in memory to a FITS file in memory, as `convert_xisf_to_fits`,
+/// reporting the progress of each step to `progress`.
+pub fn convert_xisf_to_fits_with_progress(
+    input: &[u8],
+    progress: Option<&dyn ConversionProgress>,
+) -> Result<Vec<u8>, XisfError> {
+    let progress = progress.unwrap_or(&NullProgress);
+    let xisf_file =
+        XISFile::read_from_with_progress(Cursor::new(input), XisfReadOptions::default(), progress)?;
+    let (data_bytes, bitpix) = xisf_data_to_fits(&xisf_file, ByteOrder::Big);
+    if bitpix == 0 {
+        return Err(XisfError::UnsupportedSampleFormat {
+            sample_format: xisf_file.header().sample_format().to_string(),
+        });
+    }
+    let size = data_bytes.len() as u64;
+    progress.on_step(ConversionStep::ConvertData, size, size);
+    let channel_count = xisf_file.header().geometry().channel_count();
+    let fits_hd = fits_header_data(&xisf_file, data_bytes, bitpix, channel_count);
+
+    let mut fits = Vec::new();
+    let primary = fitswriter::FitsHdu::Primary(xisf_file.keywords());
+    fitswriter::fits_write_hdu_to(&mut fits, fits_hd, primary, false)?;
+    progress.on_step(
+        ConversionStep::WriteFits,
+        fits.len() as u64,
+        fits.len() as u64,
+    );
+    Ok(fits)
+}
+
+impl XISFile {
+    /// Convert the image to FITS (big endian data) and write it to `path`: the image
+    /// data is converted, its FITS header built from the XISF header and keywords, and
+    /// both written as requested by `options`.
+    pub fn write_fits(&self, path: &Path, options: &XisfWriteOptions) -> Result<(), XisfError> {
+        let channel_count = self.header().geometry().channel_count();
+        let channels = match options.channel_filter {
+            Some(channel) if channel >= channel_count => {
+                return Err(XisfError::ChannelOutOfRange {
+                    channel,
+                    channel_count,
+                })
+            }
+            Some(channel) => channel..channel + 1,
+            None => 0..channel_count,
+        };
+        let hdu_channels: Vec<Range<usize>> = if options.split_hdus {
+            channels.map(|channel| channel..channel + 1).collect()
+        } else {
+            vec![channels]
+        };
+
+        info!("FITS write > File name > {}", path.display());
+        let mut fits = BufWriter::new(File::create(path)?);
+        for (hdu, channels) in hdu_channels.into_iter().enumerate() {
+            let mut fits_hd = self.fits_hdu_data(channels.clone(), options)?;
+            if hdu > 0 {
+                let name = xisf_channel_name(channels.start, self.header().color_space());
+                fits_hd.history.clear();
+                fits_hd.comment.clear();
+                fits_hd.extra_keywords = vec![FITSKeyword::string(
+                    "EXTNAME",
+                    &name.to_uppercase(),
+                    "XISF channel",
+                )];
+            }
+            let kind = if hdu == 0 {
+                fitswriter::FitsHdu::Primary(self.keywords())
+            } else {
+                fitswriter::FitsHdu::ImageExtension
+            };
+            fitswriter::fits_write_hdu_to(&mut fits, fits_hd, kind, options.add_checksum)?;
+        }
+        fits.flush()?;
+        Ok(())
+    }
+
+    /// Convert some channels of the image to a FITS HDU
+    fn fits_hdu_data(
+        &self,
+        channels: Range<usize>,
+        options: &XisfWriteOptions,
+    ) -> Result<fitswriter::FitsHeaderData, XisfError> {
+        let channel_count = channels.len();
+        let sample_format = self.header().sample_format();
+        let wider_format = match sample_format {
+            XISFSampleFormat::UInt16 if !options.bzero_correction => Some(OutputFormat::I32),
+            XISFSampleFormat::UInt32 if !options.bzero_correction => Some(OutputFormat::F64),
+            _ => None,
+        };
+        let (data_bytes, bitpix) = match wider_format {
+            Some(format) => (
+                xisf_channels_to_fits_as(self, ByteOrder::Big, channels, format)
+                    .unwrap_or_default(),
+                format.bitpix(),
+            ),
+            None => xisf_channels_to_fits(self, ByteOrder::Big, channels),
+        };
+        if bitpix == 0 {
+            return Err(XisfError::UnsupportedSampleFormat {
+                sample_format: sample_format.to_string(),
+            });
+        }
+        let mut fits_hd = fits_header_data(self, data_bytes, bitpix, channel_count);
+        if wider_format.is_some() {
+            fits_hd.bzero = 0;
+        }
+        Ok(fits_hd)
+    }
+}
@@ -18,33 +18,161 @@
 )]
 #![allow(clippy::must_use_candidate)]
 
-mod convert;
-mod fitswriter;
-mod xisfreader;
-
-use crate::xisfreader::{XISFData, XISFile};
-use log::info;
+use log::{error, info, warn, LevelFilter};
+use quick_xml::{events::Event, Reader, Writer};
 use std::{
-    io,
+    cmp,
+    convert::TryFrom,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    process,
+    time::Duration,
 };
 use structopt::StructOpt;
+use xisfits::{
+    convert,
+    error::XisfError,
+    fits_bzero, fitswriter,
+    fitswriter::{FITSKeyword, FitsHdu},
+    xisf_channel_name, xisf_channels_to_fits, xisf_channels_to_fits_as,
+    xisf_channels_to_fits_bounded, xisf_image_data_to_fits,
+    xisfreader::{
+        ParseMode, XISFColorSpace, XISFHeader, XISFImage, XISFSampleFormat, XISFile,
+        XisfReadOptions,
+    },
+    ByteOrder, ChannelSelection, ConversionProgress, ConversionStep, NullProgress,
+    OutputFileFormat, OutputFormat, RgbMode,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(about)]
+#[allow(clippy::struct_excessive_bools)]
 struct Cli {
-    // Wether to include extra information while doing the conversion in
-    #[structopt(short, long)]
-    verbose: bool,
+    /// Print log messages: warnings (-v), information (-vv) or debugging traces (-vvv).
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Don't print warnings, log messages nor the progress bar, only errors.
+    #[structopt(short, long, conflicts_with = "verbose")]
+    quiet: bool,
     /// Path to the XISF input file.
     #[structopt(name = "input-file", parse(from_os_str))]
     input: PathBuf,
     /// Path to the FITS output file.
     #[structopt(name = "output-file", parse(from_os_str))]
     output: PathBuf,
+    /// Byte order of the FITS image data. FITS requires big endian, so "little" (and "native" on
+    /// little endian machines) produces non-standard files.
+    #[structopt(
+        long,
+        default_value = "big",
+        possible_values = &["big", "little", "native"]
+    )]
+    byte_order: ByteOrder,
+    /// Write the XISF display function (screen stretch) as DSPF_* FITS keywords.
+    #[structopt(long)]
+    preserve_display_function: bool,
+    /// Write the XISF display function (screen stretch) as FITS COMMENT cards.
+    #[structopt(long)]
+    keep_display_function: bool,
+    /// Convert the image in memory and print the FITS header instead of writing the output file.
+    #[structopt(long)]
+    dry_run: bool,
+    /// Save the ICC profile of the XISF image next to the FITS file (<output-file stem>.icc).
+    #[structopt(long)]
+    save_icc: bool,
+    /// Write each channel of the image to its own FITS file (<output-file stem>_<channel>.fits).
+    /// Same as `--rgb-mode split`.
+    #[structopt(long, conflicts_with = "rgb-mode")]
+    split_channels: bool,
+    /// Write the channels of multichannel images as a cube ("cube", the default), as the
+    /// primary HDU (first channel) and IMAGE extensions ("extensions"), or each to its own
+    /// FITS file ("split").
+    #[structopt(long, possible_values = &["cube", "extensions", "split"])]
+    rgb_mode: Option<RgbMode>,
+    /// Write the image data as "i16", "i32", "f32" or "f64" FITS values instead of the type
+    /// matching the XISF sample format. Float values are rounded to integers, and integer
+    /// values out of the range of the type are clipped. "raw" writes only the pixel bytes,
+    /// without FITS header, to <output-file stem>.raw, described by <output-file stem>.rawinfo.
+    #[structopt(long, possible_values = &["i16", "i32", "f32", "f64", "raw"])]
+    output_format: Option<OutputFileFormat>,
+    /// Convert only some channels of the image, given as a comma separated list of
+    /// channel indices or inclusive ranges, e.g. "0,2" or "0:1".
+    #[structopt(long, value_name = "CHANNELS")]
+    channels: Option<ChannelSelection>,
+    /// Rescale float images to 16 bits integers using the bounds declared by the XISF image.
+    #[structopt(long)]
+    apply_bounds: bool,
+    /// Interleave the channels of multichannel images (RGBRGB...), writing the channels as
+    /// the first FITS axis (NAXIS1) instead of the last one.
+    #[structopt(long)]
+    interleaved: bool,
+    /// Write the XISF thumbnail as a FITS IMAGE extension after the main image.
+    #[structopt(long)]
+    save_thumbnail: bool,
+    /// Write the FITS CHECKSUM and DATASUM keywords, so the integrity of the file can be
+    /// verified (e.g. by archives).
+    #[structopt(long)]
+    checksum: bool,
+    /// Subtract the XISF offset (pedestal) from the image data instead of writing it as PEDESTAL.
+    #[structopt(long)]
+    subtract_offset: bool,
+    /// Don't verify the checksum of the XISF image data.
+    #[structopt(long)]
+    skip_checksum: bool,
+    /// Fail on any deviation from the XISF specification (e.g. a color space that doesn't match
+    /// the number of channels, unrecognized attributes or a missing checksum) instead of warning.
+    #[structopt(long)]
+    strict: bool,
+    /// Recover as much as possible of damaged XISF files (e.g. truncated or badly compressed
+    /// image data, or checksum mismatches), warning instead of failing.
+    #[structopt(long, conflicts_with = "strict")]
+    lenient: bool,
+    /// Print the statistics (min, max, mean, median and standard deviation) of each channel.
+    #[structopt(long)]
+    stats: bool,
+    /// Write the XML header of the XISF file to PATH (--dump-xml=PATH) or, without a path,
+    /// pretty-printed to the standard error.
+    #[structopt(long, value_name = "PATH", require_equals = true)]
+    dump_xml: Option<Option<PathBuf>>,
+    /// Timeout of the requests of image data stored in remote files, in seconds (with the
+    /// remote feature).
+    #[structopt(long, value_name = "SECONDS", default_value = "30")]
+    timeout: u64,
+    /// Embed the XML header of the XISF file in the FITS header as COMMENT cards.
+    #[structopt(long)]
+    embed_xml: bool,
+    /// Maximum memory (in bytes) of the conversion. If the image data doesn't fit in memory,
+    /// uncompressed image data is read channel by channel, or the conversion fails early.
+    #[structopt(long, value_name = "BYTES")]
+    max_memory: Option<u64>,
+    /// Write the metadata of the XISF file (header, FITS keywords and XISF properties) as
+    /// JSON to PATH (with the json feature).
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    json_meta: Option<PathBuf>,
 }
 
 impl Cli {
+    /// Gets the level of the log messages to print. Warnings and errors are log
+    /// messages too, so only errors are printed by default or with `--quiet`.
+    pub fn log_level(&self) -> LevelFilter {
+        match self.verbose {
+            _ if self.quiet => LevelFilter::Error,
+            0 => LevelFilter::Error,
+            1 => LevelFilter::Warn,
+            2 => LevelFilter::Info,
+            _ => LevelFilter::Debug,
+        }
+    }
+
+    /// Whether the progress of the conversion may be shown (with the progress feature,
+    /// when stderr is a terminal).
+    #[cfg_attr(not(feature = "progress"), allow(dead_code))]
+    pub fn progress(&self) -> bool {
+        !self.quiet
+    }
+
     /// Gets the path to the input XISF file.
     pub fn input(&self) -> &Path {
         self.input.as_path()
@@ -54,120 +182,1038 @@ impl Cli {
     pub fn output(&self) -> &Path {
         self.output.as_path()
     }
+
+    /// Gets the byte order of the FITS image data.
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order.resolve()
+    }
+
+    /// Whether to write the XISF display function as FITS keywords.
+    pub fn preserve_display_function(&self) -> bool {
+        self.preserve_display_function
+    }
+
+    /// Whether to write the XISF display function as FITS comments.
+    pub fn keep_display_function(&self) -> bool {
+        self.keep_display_function
+    }
+
+    /// Whether to skip writing the output file.
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Whether to save the ICC profile to a sidecar file.
+    pub fn save_icc(&self) -> bool {
+        self.save_icc
+    }
+
+    /// Gets the path to the ICC profile sidecar file.
+    pub fn icc_output(&self) -> PathBuf {
+        self.output.with_extension("icc")
+    }
+
+    /// Gets the FITS data type requested for the image data, if any.
+    pub fn output_format(&self) -> Option<OutputFormat> {
+        match self.output_format {
+            Some(OutputFileFormat::Fits(format)) => Some(format),
+            _ => None,
+        }
+    }
+
+    /// Whether to write the raw pixel bytes instead of a FITS file.
+    pub fn raw(&self) -> bool {
+        self.output_format == Some(OutputFileFormat::Raw)
+    }
+
+    /// Gets the path to the raw pixel bytes file. FITS extensions (or none) are
+    /// replaced with `.raw`, other extensions are kept.
+    pub fn raw_output(&self) -> PathBuf {
+        let extension = self
+            .output
+            .extension()
+            .map(|extension| extension.to_string_lossy());
+        match extension {
+            Some(extension) if !["fits", "fit", "fts"].contains(&&*extension.to_lowercase()) => {
+                self.output.clone()
+            }
+            _ => self.output.with_extension("raw"),
+        }
+    }
+
+    /// Gets the path to the JSON file describing the raw pixel bytes.
+    pub fn rawinfo_output(&self) -> PathBuf {
+        self.raw_output().with_extension("rawinfo")
+    }
+
+    /// Gets the channels of the image to convert, if not all of them.
+    pub fn channels(&self) -> Option<&ChannelSelection> {
+        self.channels.as_ref()
+    }
+
+    /// Whether to rescale float images to integers using their bounds.
+    pub fn apply_bounds(&self) -> bool {
+        self.apply_bounds
+    }
+
+    /// Whether to interleave the channels of the image data.
+    pub fn interleaved(&self) -> bool {
+        self.interleaved
+    }
+
+    /// Whether to write the thumbnail as a FITS IMAGE extension.
+    pub fn save_thumbnail(&self) -> bool {
+        self.save_thumbnail
+    }
+
+    /// Whether to write the FITS checksum keywords.
+    pub fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    /// Whether to subtract the offset declared by the XISF image from the data.
+    pub fn subtract_offset(&self) -> bool {
+        self.subtract_offset
+    }
+
+    /// Whether to skip the verification of the image data checksum.
+    pub fn skip_checksum(&self) -> bool {
+        self.skip_checksum
+    }
+
+    /// Gets how strictly the XISF specification is enforced.
+    pub fn parse_mode(&self) -> ParseMode {
+        if self.strict {
+            ParseMode::Strict
+        } else if self.lenient {
+            ParseMode::Lenient
+        } else {
+            ParseMode::Normal
+        }
+    }
+
+    /// Whether to embed the XISF XML header in the FITS header.
+    pub fn embed_xml(&self) -> bool {
+        self.embed_xml
+    }
+
+    /// Whether to print the statistics of each channel.
+    pub fn stats(&self) -> bool {
+        self.stats
+    }
+
+    /// Gets the options to read the XISF file.
+    pub fn read_options(&self) -> XisfReadOptions {
+        XisfReadOptions {
+            verify_checksum: !self.skip_checksum,
+            parse_mode: self.parse_mode(),
+            remote_timeout: Duration::from_secs(self.timeout),
+            ..XisfReadOptions::default()
+        }
+    }
+
+    /// Gets where to write the XML header: `Some(None)` for the standard error,
+    /// `Some(Some(path))` for a file.
+    pub fn dump_xml(&self) -> Option<Option<&Path>> {
+        self.dump_xml
+            .as_ref()
+            .map(|path| path.as_ref().map(PathBuf::as_path))
+    }
+
+    /// Gets the maximum memory of the conversion, in bytes, if limited.
+    pub fn max_memory(&self) -> Option<u64> {
+        self.max_memory
+    }
+
+    /// Gets the path to the JSON metadata file, if requested.
+    pub fn json_meta(&self) -> Option<&Path> {
+        self.json_meta.as_ref().map(PathBuf::as_path)
+    }
+
+    /// Gets how the channels of multichannel images are written.
+    pub fn rgb_mode(&self) -> RgbMode {
+        match self.rgb_mode {
+            Some(rgb_mode) => rgb_mode,
+            None if self.split_channels => RgbMode::Split,
+            None => RgbMode::default(),
+        }
+    }
+
+    /// Gets the path to the FITS file of a channel. RGB channels are named by
+    /// color (e.g. `image_red.fits`), other channels by number (`image_0.fits`).
+    pub fn channel_output(&self, channel: usize, color_space: XISFColorSpace) -> PathBuf {
+        let suffix = xisf_channel_name(channel, color_space);
+        let stem = self
+            .output
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        let mut file_name = format!("{}_{}", stem, suffix);
+        if let Some(extension) = self.output.extension() {
+            file_name.push('.');
+            file_name.push_str(&extension.to_string_lossy());
+        }
+        self.output.with_file_name(file_name)
+    }
+}
+
+/// Build the FITS header of the converted image, with the display function if requested
+fn fits_header_data(
+    cli: &Cli,
+    xisf_file: &XISFile,
+    data_bytes: Box<[u8]>,
+    bitpix: i64,
+    channel_count: usize,
+) -> fitswriter::FitsHeaderData {
+    let mut fits_hd = xisfits::fits_header_data(xisf_file, data_bytes, bitpix, channel_count);
+    if let Some(display_function) = xisf_file.header().display_function() {
+        if cli.preserve_display_function() {
+            fits_hd
+                .extra_keywords
+                .append(&mut display_function.fits_keywords());
+        }
+        if cli.keep_display_function() {
+            fits_hd
+                .comment
+                .append(&mut display_function.comment_lines());
+        }
+    }
+    if cli.embed_xml() {
+        fits_hd.comment.append(&mut xisf_file.xml_comment_lines());
+    }
+    // The offset is removed from the data instead
+    if cli.subtract_offset() {
+        fits_hd
+            .extra_keywords
+            .retain(|keyword| keyword.name != "PEDESTAL");
+    }
+    fits_hd
+}
+
+/// Write the FITS file (or report what would be written in a dry run)
+fn write_fits(
+    cli: &Cli,
+    xisf_file: &XISFile,
+    output: &Path,
+    fits_hd: fitswriter::FitsHeaderData,
+    extensions: Vec<fitswriter::FitsHeaderData>,
+) -> io::Result<()> {
+    if !cli.dry_run() {
+        info!("FITS write > File name > {}", output.display());
+        let mut fits = BufWriter::new(File::create(output)?);
+        write_fits_hdus(cli, xisf_file, &mut fits, fits_hd, extensions)?;
+        return fits.flush();
+    }
+
+    // Write the FITS file to memory, to report it
+    let mut fits = Vec::new();
+    write_fits_hdus(cli, xisf_file, &mut fits, fits_hd, extensions)?;
+    for card in fits.chunks(80) {
+        let card = String::from_utf8_lossy(card);
+        println!("{}", card.trim_end());
+        if card.starts_with("END ") {
+            break;
+        }
+    }
+    println!(
+        "Dry run: {} bytes would be written to {}",
+        fits.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+/// Write the primary HDU, with the FITS keywords of the XISF file, followed by the other
+/// channels and the thumbnail (if requested) as IMAGE extensions
+fn write_fits_hdus<W>(
+    cli: &Cli,
+    xisf_file: &XISFile,
+    fits: &mut W,
+    fits_hd: fitswriter::FitsHeaderData,
+    extensions: Vec<fitswriter::FitsHeaderData>,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let primary = FitsHdu::Primary(xisf_file.keywords());
+    fitswriter::fits_write_hdu_to(fits, fits_hd, primary, cli.checksum())?;
+    for extension in extensions {
+        fitswriter::fits_write_hdu_to(fits, extension, FitsHdu::ImageExtension, cli.checksum())?;
+    }
+    if cli.save_thumbnail() {
+        match xisf_file.thumbnail() {
+            Some(thumbnail) => {
+                write_thumbnail(fits, thumbnail, cli.byte_order(), cli.checksum())?;
+            }
+            None => warn!("The XISF file doesn't have a thumbnail"),
+        }
+    }
+    Ok(())
+}
+
+/// Write the pixel bytes of the converted image, without FITS header, and the JSON
+/// file describing them (or report what would be written in a dry run)
+fn write_raw(
+    cli: &Cli,
+    fits_hd: &fitswriter::FitsHeaderData,
+    channel_count: usize,
+) -> io::Result<()> {
+    let raw_info = raw_info(cli, fits_hd, channel_count);
+    if cli.dry_run() {
+        print!("{}", raw_info);
+        println!(
+            "Dry run: {} bytes would be written to {}",
+            fits_hd.data_bytes.len(),
+            cli.raw_output().display()
+        );
+        return Ok(());
+    }
+    info!("Raw write > File name > {}", cli.raw_output().display());
+    fs::write(cli.raw_output(), &fits_hd.data_bytes)?;
+    fs::write(cli.rawinfo_output(), raw_info)
+}
+
+/// Describe the raw pixel bytes as JSON: the sample type, the offset of unsigned
+/// values (as FITS BZERO), the byte order, the axes (the first one varies fastest, as
+/// FITS NAXISn) and the number of channels
+fn raw_info(cli: &Cli, fits_hd: &fitswriter::FitsHeaderData, channel_count: usize) -> String {
+    let sample_type = match fits_hd.bitpix {
+        8 => "uint8",
+        16 => "int16",
+        32 => "int32",
+        -32 => "float32",
+        _ => "float64",
+    };
+    let byte_order = match cli.byte_order() {
+        ByteOrder::Little => "little",
+        _ => "big",
+    };
+    let axes: Vec<_> = fits_hd.naxis_vec.iter().map(ToString::to_string).collect();
+    format!(
+        "{{\n  \"sample_type\": \"{}\",\n  \"bzero\": {},\n  \"byte_order\": \"{}\",\n  \
+         \"axes\": [{}],\n  \"channel_count\": {},\n  \"interleaved\": {}\n}}\n",
+        sample_type,
+        fits_hd.bzero,
+        byte_order,
+        axes.join(", "),
+        channel_count,
+        cli.interleaved()
+    )
+}
+
+/// Append the XISF thumbnail, if any, to the FITS file as an IMAGE extension
+fn append_thumbnail(cli: &Cli, xisf_file: &XISFile, output: &Path) -> io::Result<()> {
+    if let Some(thumbnail) = xisf_file.thumbnail() {
+        let mut fits = BufWriter::new(OpenOptions::new().append(true).open(output)?);
+        write_thumbnail(&mut fits, thumbnail, cli.byte_order(), cli.checksum())?;
+        fits.flush()?;
+    }
+    Ok(())
 }
 
-/// Convert XISF binary data to FITS format (Big Endian)
-pub fn xisf_data_to_fits(xisf_file: &XISFile) -> (Box<[u8]>, i64) {
-    let mut fits_data = Vec::new();
-    let mut bitpix = 0;
+/// Write a XISF thumbnail as a FITS IMAGE extension (with its checksum, if requested)
+fn write_thumbnail<W>(
+    fits: &mut W,
+    thumbnail: &XISFImage,
+    byte_order: ByteOrder,
+    checksum: bool,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let channel_count = thumbnail.geometry().channel_count();
+    let (data_bytes, bitpix) =
+        xisf_image_data_to_fits(thumbnail.data(), byte_order, 0..channel_count);
+    let naxis_vec = xisfits::fits_axes(
+        thumbnail.geometry().dimensions(),
+        channel_count,
+        thumbnail.sample_format(),
+    );
+    let fits_hd = fitswriter::FitsHeaderData {
+        bitpix,
+        naxis: naxis_vec.len() as u64,
+        naxis_vec,
+        bzero: fits_bzero(thumbnail.sample_format()),
+        bscale: 1,
+        datamin: 0,
+        datamax: 0,
+        history: Vec::new(),
+        comment: Vec::new(),
+        extra_keywords: vec![FITSKeyword::string(
+            "EXTNAME",
+            "THUMBNAIL",
+            "XISF thumbnail",
+        )],
+        data_bytes,
+    };
+    fitswriter::fits_write_hdu_to(fits, fits_hd, FitsHdu::ImageExtension, checksum)
+}
 
-    // +---------+-------+------+
-    // | XISF    > Rust  > FITS |
-    // +---------+-------+------+
-    // | UInt8   | u8    | 8    |
-    // | UInt16  | i16   | 16   |
-    // | UInt32  | i32   | 32   |
-    // | Float32 | f32   | -32  |
-    // | Float64 | f64   | -64  |
-    // +---------+-------+------+
-    match xisf_file.data() {
-        XISFData::UInt8(ref data) => {
-            info!("XISF data to FITS > UInt8");
-            bitpix = 8;
-            for channel in data.iter() {
-                fits_data.extend_from_slice(channel);
+/// Indent a XML document, one element per line. Returns `None` if it isn't well-formed.
+fn pretty_xml(xml: &str) -> Option<String> {
+    let mut reader = Reader::from_str(xml);
+    let _ = reader.trim_text(true);
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Eof) => break,
+            Ok(event) => {
+                let _ = writer.write_event(event).ok()?;
             }
+            Err(_) => return None,
+        }
+        buf.clear();
+    }
+    String::from_utf8(writer.into_inner()).ok()
+}
+
+/// Write the XML header of the XISF file to `path`, or pretty-printed to the standard error
+fn dump_xml(xisf_file: &XISFile, path: Option<&Path>) -> io::Result<()> {
+    if let Some(path) = path {
+        info!("XML header > File name > {}", path.display());
+        fs::write(path, xisf_file.raw_xml())
+    } else {
+        let xml = xisf_file.raw_xml();
+        eprintln!("{}", pretty_xml(xml).unwrap_or_else(|| xml.to_owned()));
+        Ok(())
+    }
+}
+
+/// Write the metadata of the XISF file as JSON to `path` (or report it in a dry run)
+#[cfg(feature = "json")]
+fn write_json_meta(cli: &Cli, xisf_file: &XISFile, path: &Path) -> io::Result<()> {
+    let mut json = serde_json::to_string_pretty(&xisf_file.metadata())?;
+    json.push('\n');
+    if cli.dry_run() {
+        println!(
+            "Dry run: {} bytes of JSON metadata would be written to {}",
+            json.len(),
+            path.display()
+        );
+        return Ok(());
+    }
+    info!("JSON metadata > File name > {}", path.display());
+    fs::write(path, json)
+}
+
+/// Writing the metadata as JSON needs the json feature
+#[cfg(not(feature = "json"))]
+fn write_json_meta(_cli: &Cli, _xisf_file: &XISFile, _path: &Path) -> io::Result<()> {
+    warn!("Writing the metadata as JSON needs the json feature, skipping --json-meta");
+    Ok(())
+}
+
+/// Print the statistics of each channel of the image data
+fn print_stats(xisf_file: &XISFile) {
+    for (channel, stats) in xisf_file.data().channel_stats().iter().enumerate() {
+        match stats {
+            Some(stats) => println!("Channel {}: {}", channel, stats),
+            None => println!("Channel {}: no samples", channel),
+        }
+    }
+}
+
+/// Convert the image in memory and write it
+fn convert_fits(
+    cli: &Cli,
+    xisf_file: &XISFile,
+    progress: &dyn ConversionProgress,
+) -> io::Result<()> {
+    let channel_count = xisf_file.header().geometry().channel_count();
+    // Convert some channels, reporting the size of the converted data
+    let convert = |channels: Range<usize>| {
+        let fits_hd = convert_channels(cli, xisf_file, channels);
+        if let Some(ref fits_hd) = fits_hd {
+            let size = fits_hd.data_bytes.len() as u64;
+            progress.on_step(ConversionStep::ConvertData, size, size);
+        }
+        fits_hd
+    };
+    if cli.raw() {
+        if cli.rgb_mode() != RgbMode::Cube || cli.save_thumbnail() || cli.checksum() {
+            warn!("The RGB mode, thumbnail and checksum options don't apply to raw output");
         }
-        XISFData::UInt16(ref data) => {
-            info!("XISF data to FITS > UInt16");
-            bitpix = 16;
-            for channel in data.iter() {
-                fits_data.append(&mut convert::u16_to_i16_to_v_u8_be(channel));
+        if let Some(fits_hd) = convert(0..channel_count) {
+            write_raw(cli, &fits_hd, channel_count)?;
+            report_written(cli, progress, &cli.raw_output());
+        }
+        return Ok(());
+    }
+    match cli.rgb_mode() {
+        RgbMode::Split if channel_count > 1 => {
+            for channel in 0..channel_count {
+                let output = cli.channel_output(channel, xisf_file.header().color_space());
+                if let Some(fits_hd) = convert(channel..channel + 1) {
+                    write_fits(cli, xisf_file, &output, fits_hd, Vec::new())?;
+                    report_written(cli, progress, &output);
+                }
             }
         }
-        XISFData::UInt32(ref data) => {
-            info!("XISF data to FITS > UInt32");
-            bitpix = 32;
-            for channel in data.iter() {
-                fits_data.append(&mut convert::u32_to_i32_to_v_u8_be(channel));
+        RgbMode::Extensions if channel_count > 1 => {
+            // The first channel is the primary HDU, the others follow as IMAGE extensions
+            let extensions: Vec<_> = (1..channel_count)
+                .filter_map(|channel| channel_extension(cli, xisf_file, channel))
+                .collect();
+            if let Some(fits_hd) = convert(0..1) {
+                write_fits(cli, xisf_file, cli.output(), fits_hd, extensions)?;
+                report_written(cli, progress, cli.output());
             }
         }
-        // XISFData::UInt64(ref data) => unimplemented!(),
-        XISFData::Float32(ref data) => {
-            info!("XISF data to FITS > Float32");
-            bitpix = -32;
-            for channel in data.iter() {
-                fits_data.append(&mut convert::f32_to_v_u8_be(channel));
+        _ => {
+            if let Some(fits_hd) = convert(0..channel_count) {
+                write_fits(cli, xisf_file, cli.output(), fits_hd, Vec::new())?;
+                report_written(cli, progress, cli.output());
             }
         }
-        XISFData::Float64(ref data) => {
-            info!("XISF data to FITS > Float64");
-            bitpix = -64;
-            for channel in data.iter() {
-                fits_data.append(&mut convert::f64_to_v_u8_be(channel));
+    }
+    Ok(())
+}
+
+/// Report the size of a written file (nothing is written in a dry run)
+fn report_written(cli: &Cli, progress: &dyn ConversionProgress, output: &Path) {
+    if cli.dry_run() {
+        return;
+    }
+    if let Ok(metadata) = fs::metadata(output) {
+        progress.on_step(ConversionStep::WriteFits, metadata.len(), metadata.len());
+    }
+}
+
+/// Convert some channels of the image to FITS data, with the FITS header of the image.
+/// Returns `None` if the sample format can't be converted.
+fn convert_channels(
+    cli: &Cli,
+    xisf_file: &XISFile,
+    channels: Range<usize>,
+) -> Option<fitswriter::FitsHeaderData> {
+    info!("Convert to FITS > Image data to bytes");
+    // The requested output format takes precedence over the bounds
+    let formatted_data = cli.output_format().and_then(|format| {
+        xisf_channels_to_fits_as(xisf_file, cli.byte_order(), channels.clone(), format)
+            .map(|fits_data| (fits_data, format.bitpix()))
+    });
+    let formatted = formatted_data.is_some();
+    if cli.output_format().is_some() && !formatted {
+        warn!("The output format doesn't apply to complex images");
+    }
+    let bounded_data = if !formatted && cli.apply_bounds() {
+        xisf_file.header().bounds().and_then(|bounds| {
+            xisf_channels_to_fits_bounded(xisf_file, cli.byte_order(), channels.clone(), bounds)
+        })
+    } else {
+        None
+    };
+    let bounded = bounded_data.is_some();
+    let (fits_data, bitpix) = if let Some(formatted_data) = formatted_data {
+        formatted_data
+    } else if let Some(fits_data) = bounded_data {
+        (fits_data, 16)
+    } else {
+        xisf_channels_to_fits(xisf_file, cli.byte_order(), channels.clone())
+    };
+    if bitpix == 0 {
+        return None;
+    }
+
+    let mut fits_hd = fits_header_data(cli, xisf_file, fits_data, bitpix, channels.len());
+    if formatted {
+        // The values are stored as they are
+        fits_hd.bzero = 0;
+    }
+    if bounded {
+        fits_hd.bzero = 32768;
+        // The bounds of the float data don't apply to the rescaled data
+        fits_hd
+            .extra_keywords
+            .retain(|keyword| keyword.name != "DATAMIN" && keyword.name != "DATAMAX");
+    }
+    if cli.interleaved() {
+        let sample_format = xisf_file.header().sample_format();
+        xisfits::fits_interleave_channels(&mut fits_hd, channels.len(), sample_format);
+    }
+    Some(fits_hd)
+}
+
+/// Convert a channel of the image to an IMAGE extension, named after the channel (e.g.
+/// `GREEN`). The keywords of the image are only written to the primary HDU.
+fn channel_extension(
+    cli: &Cli,
+    xisf_file: &XISFile,
+    channel: usize,
+) -> Option<fitswriter::FitsHeaderData> {
+    let name = xisf_channel_name(channel, xisf_file.header().color_space()).to_uppercase();
+    convert_channels(cli, xisf_file, channel..channel + 1).map(|fits_hd| {
+        fitswriter::FitsHeaderData {
+            history: Vec::new(),
+            comment: Vec::new(),
+            extra_keywords: vec![FITSKeyword::string("EXTNAME", &name, "XISF channel")],
+            ..fits_hd
+        }
+    })
+}
+
+/// Gets the FITS BITPIX if the image data can be streamed (uncompressed attachment of
+/// a supported sample format, converted as is to a single file), `None` otherwise.
+fn stream_bitpix(cli: &Cli, xisf_file: &XISFile) -> Option<i64> {
+    let header = xisf_file.header();
+    if cli.dry_run()
+        || cli.stats()
+        || cli.parse_mode() == ParseMode::Lenient
+        || cli.output_format().is_some()
+        || cli.raw()
+        || cli.channels().is_some()
+        || cli.checksum()
+        || cli.rgb_mode() != RgbMode::Cube
+        || (cli.interleaved() && header.geometry().channel_count() > 1)
+        || (cli.apply_bounds() && header.bounds().is_some())
+        || (cli.subtract_offset() && header.offset() != 0.0)
+        || !header.compression().is_empty()
+        || header.location_method() != "attachment"
+        || header.location_length()
+            != (header.channel_size() * header.geometry().channel_count()) as u64
+    {
+        return None;
+    }
+    match header.sample_format() {
+        XISFSampleFormat::UInt8 => Some(8),
+        // Int8 is widened to 16 bits
+        XISFSampleFormat::UInt16 | XISFSampleFormat::Int8 | XISFSampleFormat::Int16 => Some(16),
+        XISFSampleFormat::UInt32 | XISFSampleFormat::Int32 => Some(32),
+        XISFSampleFormat::Float32 => Some(-32),
+        XISFSampleFormat::Float64 => Some(-64),
+        _ => None,
+    }
+}
+
+/// Size of the chunks of the streaming conversion (a multiple of every sample size)
+const STREAM_CHUNK_SIZE: usize = 1 << 20;
+
+/// How the image data is converted, from the least to the most memory used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ConversionStrategy {
+    /// Chunk by chunk, from the XISF file to the FITS file.
+    Streaming,
+    /// In memory, reading uncompressed image data channel by channel.
+    ChannelByChannel,
+    /// In memory.
+    InMemory,
+}
+
+impl ConversionStrategy {
+    fn description(self) -> &'static str {
+        match self {
+            Self::Streaming => "streaming",
+            Self::ChannelByChannel => "in memory, reading channel by channel",
+            Self::InMemory => "in memory",
+        }
+    }
+}
+
+/// Chooses how to convert the image data: streamed if the options allow it, otherwise in
+/// memory, reading uncompressed image data channel by channel if it doesn't fit in
+/// `--max-memory` otherwise. Fails if the conversion doesn't fit in `--max-memory` at all.
+fn conversion_strategy(cli: &Cli, xisf_file: &XISFile) -> io::Result<ConversionStrategy> {
+    let header = xisf_file.header();
+    let strategies: &[_] = if stream_bitpix(cli, xisf_file).is_some() {
+        &[ConversionStrategy::Streaming]
+    } else if header.compression().is_empty() && header.location_method() == "attachment" {
+        &[
+            ConversionStrategy::InMemory,
+            ConversionStrategy::ChannelByChannel,
+        ]
+    } else {
+        &[ConversionStrategy::InMemory]
+    };
+    let max_memory = cli.max_memory().unwrap_or(u64::max_value());
+    let mut estimate = 0;
+    for &strategy in strategies {
+        estimate = memory_estimate(cli, xisf_file.header(), strategy);
+        info!(
+            "Convert to FITS > Memory estimate ({}): {} bytes",
+            strategy.description(),
+            estimate
+        );
+        if estimate <= max_memory {
+            info!("Convert to FITS > Strategy: {}", strategy.description());
+            return Ok(strategy);
+        }
+    }
+    let reason = if strategies[0] == ConversionStrategy::Streaming {
+        ""
+    } else {
+        " (the image data can't be streamed: it's compressed or stored elsewhere, or the \
+         options need the whole image in memory)"
+    };
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        format!(
+            "the conversion needs about {} bytes of memory, more than --max-memory {}{}",
+            estimate, max_memory, reason
+        ),
+    ))
+}
+
+/// Estimates the peak memory (in bytes) of the conversion of the image data with a
+/// strategy, from the sizes of the XISF image data and the FITS data.
+fn memory_estimate(cli: &Cli, header: &XISFHeader, strategy: ConversionStrategy) -> u64 {
+    // The chunk read and the chunk converted
+    if strategy == ConversionStrategy::Streaming {
+        return 2 * STREAM_CHUNK_SIZE as u64;
+    }
+    let channel_size = header.channel_size() as u64;
+    let image_size = channel_size.saturating_mul(header.geometry().channel_count() as u64);
+    let location_length = header.location_length();
+    let read = if !header.compression().is_empty() {
+        // The compressed and decompressed data, then the decompressed data and the samples
+        let decompressed = header.compression_size() as u64;
+        cmp::max(
+            location_length.saturating_add(decompressed),
+            decompressed.saturating_add(image_size),
+        )
+    } else if header.location_method() == "attachment"
+        && (strategy == ConversionStrategy::ChannelByChannel
+            || location_length > cli.read_options().channel_streaming_threshold)
+    {
+        // A channel of data and the samples
+        image_size.saturating_add(channel_size)
+    } else {
+        // The whole image data and the samples
+        location_length.saturating_add(image_size)
+    };
+
+    // The samples and the FITS data (twice to interleave the channels)
+    let samples = image_size / header.sample_format().size() as u64;
+    let fits_size = samples.saturating_mul(fits_sample_size(cli, header));
+    let copies = if cli.interleaved() { 2 } else { 1 };
+    let convert = image_size.saturating_add(fits_size.saturating_mul(copies));
+    cmp::max(read, convert)
+}
+
+/// Gets the size of the FITS samples (in bytes per XISF sample) of the conversion.
+fn fits_sample_size(cli: &Cli, header: &XISFHeader) -> u64 {
+    let sample_format = header.sample_format();
+    match cli.output_format() {
+        // The output format doesn't apply to complex images
+        Some(format) if !sample_format.is_complex() => {
+            u64::try_from(format.bitpix().abs() / 8).unwrap_or(8)
+        }
+        _ if sample_format.is_float() && cli.apply_bounds() && header.bounds().is_some() => 2,
+        // Int8 is widened to 16 bits
+        _ if sample_format == XISFSampleFormat::Int8 => 2,
+        _ => sample_format.size() as u64,
+    }
+}
+
+/// Convert uncompressed XISF image data to FITS chunk by chunk, without buffering
+/// the whole image, reporting the bytes read after each chunk. Returns the number of
+/// bytes written.
+fn xisf_stream_to_fits<R, W>(
+    xisf: &mut R,
+    fits: &mut W,
+    sample_format: XISFSampleFormat,
+    length: u64,
+    byte_order: ByteOrder,
+    progress: &dyn ConversionProgress,
+) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+{
+    let little_endian = byte_order.resolve() == ByteOrder::Little;
+    let mut xisf = xisf.take(length);
+    let mut chunk = vec![0; STREAM_CHUNK_SIZE];
+    let mut total = 0;
+    loop {
+        // Fill the chunk, so samples are never split between chunks
+        let mut size = 0;
+        while size < chunk.len() {
+            let read = xisf.read(&mut chunk[size..])?;
+            if read == 0 {
+                break;
             }
+            size += read;
+        }
+        if size == 0 {
+            break;
         }
-        // XISFData::Complex32(ref data) => unimplemented!(),
-        // XISFData::Complex64(ref data) => unimplemented!(),
-        XISFData::Empty => {}
+
+        let chunk = &chunk[..size];
+        let fits_data = match (sample_format, little_endian) {
+            (XISFSampleFormat::UInt8, _) => chunk.to_vec(),
+            (XISFSampleFormat::UInt16, false) => {
+                convert::u16_to_i16_to_v_u8_be(&convert::u8_to_v_u16(chunk))
+            }
+            (XISFSampleFormat::UInt16, true) => {
+                convert::u16_to_i16_to_v_u8_le(&convert::u8_to_v_u16(chunk))
+            }
+            (XISFSampleFormat::UInt32, false) => {
+                convert::u32_to_i32_to_v_u8_be(&convert::u8_to_v_u32(chunk))
+            }
+            (XISFSampleFormat::UInt32, true) => {
+                convert::u32_to_i32_to_v_u8_le(&convert::u8_to_v_u32(chunk))
+            }
+            (XISFSampleFormat::Int8, false) => {
+                convert::i8_to_i16_to_v_u8_be(&convert::u8_to_i8(chunk))
+            }
+            (XISFSampleFormat::Int8, true) => {
+                convert::i8_to_i16_to_v_u8_le(&convert::u8_to_i8(chunk))
+            }
+            (XISFSampleFormat::Int16, false) => {
+                convert::i16_to_v_u8_be(&convert::u8_to_v_i16(chunk))
+            }
+            (XISFSampleFormat::Int16, true) => {
+                convert::i16_to_v_u8_le(&convert::u8_to_v_i16(chunk))
+            }
+            (XISFSampleFormat::Int32, false) => {
+                convert::i32_to_v_u8_be(&convert::u8_to_v_i32(chunk))
+            }
+            (XISFSampleFormat::Int32, true) => {
+                convert::i32_to_v_u8_le(&convert::u8_to_v_i32(chunk))
+            }
+            (XISFSampleFormat::Float32, false) => {
+                convert::f32_to_v_u8_be(&convert::u8_to_v_f32(chunk))
+            }
+            (XISFSampleFormat::Float32, true) => {
+                convert::f32_to_v_u8_le(&convert::u8_to_v_f32(chunk))
+            }
+            (XISFSampleFormat::Float64, false) => {
+                convert::f64_to_v_u8_be(&convert::u8_to_v_f64(chunk))
+            }
+            (XISFSampleFormat::Float64, true) => {
+                convert::f64_to_v_u8_le(&convert::u8_to_v_f64(chunk))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unsupported XISF type: {}", sample_format),
+                ))
+            }
+        };
+        fits.write_all(&fits_data)?;
+        total += fits_data.len() as u64;
+        progress.on_step(ConversionStep::ReadData, length - xisf.limit(), length);
+    }
+    Ok(total)
+}
+
+/// Write the FITS file streaming the image data from the XISF file
+fn stream_fits(
+    cli: &Cli,
+    xisf_file: &XISFile,
+    bitpix: i64,
+    progress: &dyn ConversionProgress,
+) -> io::Result<()> {
+    info!("Convert to FITS > Stream image data");
+    let header = xisf_file.header();
+    let channel_count = header.geometry().channel_count();
+    let fits_hd = fits_header_data(cli, xisf_file, Box::new([]), bitpix, channel_count);
+
+    info!("FITS write > File name > {}", cli.output().display());
+    let mut fits = BufWriter::new(File::create(cli.output())?);
+    if xisf_file.keywords().is_empty() {
+        fitswriter::fits_write_header_to(&mut fits, &fits_hd)?;
+    } else {
+        fitswriter::fits_write_header_keywords_to(&mut fits, &fits_hd, xisf_file.keywords())?;
+    }
+
+    let mut xisf = BufReader::new(File::open(cli.input())?);
+    let _ = xisf.seek(SeekFrom::Start(header.location_start()))?;
+    let total = xisf_stream_to_fits(
+        &mut xisf,
+        &mut fits,
+        header.sample_format(),
+        header.location_length(),
+        cli.byte_order(),
+        progress,
+    )?;
+    fitswriter::fits_write_data_padding(&mut fits, total)?;
+    fits.flush()?;
+    report_written(cli, progress, cli.output());
+
+    if cli.save_thumbnail() {
+        append_thumbnail(cli, xisf_file, cli.output())?;
     }
+    Ok(())
+}
 
-    // Show the first 20 bytes of the converted image
-    if fits_data.len() > 20 {
-        let mut message = String::with_capacity(20 * 2);
-        for byte in fits_data.iter().take(20) {
-            message.push_str(&format!("{:x} ", byte));
+/// Progress bar of the conversion on stderr (with the progress feature)
+#[cfg(feature = "progress")]
+struct ProgressBar(indicatif::ProgressBar);
+
+#[cfg(feature = "progress")]
+impl ProgressBar {
+    /// Creates the progress bar, unless it's disabled or stderr isn't a terminal
+    fn new(cli: &Cli) -> Option<Self> {
+        // The progress is reported a few times per step, so every report is drawn
+        let target = indicatif::ProgressDrawTarget::stderr_nohz();
+        let bar = indicatif::ProgressBar::with_draw_target(0, target);
+        if !cli.progress() || bar.is_hidden() {
+            return None;
         }
-        info!("{}", message);
+        bar.set_style(
+            indicatif::ProgressStyle::default_bar()
+                .template("{msg:>11} [{bar:40}] {bytes}/{total_bytes}")
+                .progress_chars("=> "),
+        );
+        Some(Self(bar))
     }
 
-    (fits_data.into_boxed_slice(), bitpix)
+    /// Prints the log messages above the progress bar, so the bar isn't broken by them
+    fn log_format(&self, logger: &mut env_logger::Builder) {
+        let bar = self.0.clone();
+        let _ = logger.format(move |buf, record| {
+            let line = format!(
+                "[{:<5} {}] {}",
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            // Until the first step of the conversion, the bar isn't drawn
+            if bar.length() == 0 {
+                writeln!(buf, "{}", line)
+            } else {
+                bar.println(line);
+                Ok(())
+            }
+        });
+    }
 }
 
-fn main() -> io::Result<()> {
-    // Init logger
-    env_logger::builder().format_timestamp(None).init();
+#[cfg(feature = "progress")]
+impl ConversionProgress for ProgressBar {
+    fn on_step(&self, step: ConversionStep, bytes_done: u64, bytes_total: u64) {
+        self.0.set_message(&step.to_string());
+        self.0.set_length(bytes_total);
+        self.0.set_position(bytes_done);
+    }
+}
+
+#[cfg(feature = "progress")]
+impl Drop for ProgressBar {
+    fn drop(&mut self) {
+        self.0.finish_and_clear();
+    }
+}
 
+fn main() {
     // CLI interface information.
     let cli = Cli::from_args();
 
-    // Open XISF image file
-    let xisf_file = XISFile::read_file(cli.input())?;
+    // Init logger
+    let mut logger = env_logger::builder();
+    let _ = logger.format_timestamp(None).filter_level(cli.log_level());
+    #[cfg(feature = "progress")]
+    let progress_bar = ProgressBar::new(&cli);
+    #[cfg(feature = "progress")]
+    {
+        if let Some(ref progress_bar) = progress_bar {
+            progress_bar.log_format(&mut logger);
+        }
+    }
+    logger.init();
 
-    // -- Convert XISF to FITS
-    info!("Convert to FITS > Image data to bytes");
-    let (fits_data, bitpix) = xisf_data_to_fits(&xisf_file);
+    #[cfg(feature = "progress")]
+    let result = match progress_bar {
+        Some(ref progress_bar) => run_with_progress(&cli, progress_bar),
+        None => run(&cli),
+    };
+    #[cfg(not(feature = "progress"))]
+    let result = run(&cli);
+    // Clear the progress bar before the error message
+    #[cfg(feature = "progress")]
+    drop(progress_bar);
+    if let Err(e) = result {
+        error!("Error converting {}: {}", cli.input().display(), e);
+        process::exit(1);
+    }
+}
 
-    // Write FITS image to disk
-    if bitpix != 0 {
-        info!("Convert to FITS > Write image data");
-        let fits_hd = fitswriter::FitsHeaderData {
-            bitpix,
-            naxis: xisf_file.header().geometry().dimensions().len() as u64,
-            naxis_vec: xisf_file.header().geometry().dimensions(),
-            bzero: 0,
-            bscale: 1,
-            datamin: 0,
-            datamax: 0,
-            history: vec![String::new()],
-            comment: vec![String::new()],
-            data_bytes: fits_data,
-        };
-        if xisf_file.keywords().is_empty() {
-            fitswriter::fits_write_data(cli.output(), &fits_hd)?;
-        } else {
-            fitswriter::fits_write_data_keywords(cli.output(), &fits_hd, &xisf_file.keywords())?;
+/// Convert the XISF file to FITS as requested by the command line
+fn run(cli: &Cli) -> Result<(), XisfError> {
+    run_with_progress(cli, &NullProgress)
+}
+
+/// Convert the XISF file to FITS, as `run`, reporting the progress of the conversion
+fn run_with_progress(cli: &Cli, progress: &dyn ConversionProgress) -> Result<(), XisfError> {
+    if cli.byte_order() == ByteOrder::Little {
+        warn!("Writing little endian image data, the FITS file will be non-standard");
+    }
+
+    // Open XISF image file (headers only, the image data may be streamed)
+    let options = XisfReadOptions {
+        read_data: false,
+        verify_checksum: false,
+        ..cli.read_options()
+    };
+    let xisf_file = XISFile::read_file_with_options(cli.input(), options)?;
+    if let Some(path) = cli.dump_xml() {
+        dump_xml(&xisf_file, path)?;
+    }
+    if let Some(path) = cli.json_meta() {
+        write_json_meta(cli, &xisf_file, path)?;
+    }
+
+    // -- Convert XISF to FITS
+    let strategy = conversion_strategy(cli, &xisf_file)?;
+    if let Some(bitpix) = stream_bitpix(cli, &xisf_file) {
+        if !cli.skip_checksum() {
+            xisf_file.verify_checksum(cli.input())?;
+        }
+        stream_fits(cli, &xisf_file, bitpix, progress)?;
+    } else {
+        let mut options = cli.read_options();
+        if strategy == ConversionStrategy::ChannelByChannel {
+            options.channel_streaming_threshold = 0;
         }
+        let mut xisf_file = XISFile::read_file_with_progress(cli.input(), options, progress)?;
+        if let Some(selection) = cli.channels() {
+            xisf_file.select_channels(selection.indices())?;
+        }
+        if cli.subtract_offset() {
+            xisf_file.subtract_offset();
+        }
+        if cli.stats() {
+            print_stats(&xisf_file);
+        }
+        convert_fits(cli, &xisf_file, progress)?;
     }
     // -- End of convert XISF to FITS
 
+    // Save the ICC profile next to the FITS file
+    if cli.save_icc() {
+        match xisf_file.icc_profile() {
+            Some(icc_profile) if cli.dry_run() => println!(
+                "Dry run: {} bytes of ICC profile would be written to {}",
+                icc_profile.len(),
+                cli.icc_output().display()
+            ),
+            Some(icc_profile) => {
+                info!("ICC profile > File name > {}", cli.icc_output().display());
+                fs::write(cli.icc_output(), icc_profile)?;
+            }
+            None => warn!("The XISF file doesn't have an ICC profile"),
+        }
+    }
+
+    // Report the XISF features that may have been ignored
+    if let Err(e) = xisf_file.header().check_unknown_attributes() {
+        warn!("{}", e);
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::xisfreader::XISFSampleFormat;
+    use proptest::prelude::*;
+    use std::convert::{TryFrom, TryInto};
+    use std::io::Cursor;
+    use xisfits::error::{FitsRecordParseError, SampleFormatParseError, XisfParseError};
+    use xisfits::xisfreader::{Complex32, XISFGeometry, XISFImageType, XISFResolutionUnit};
+    use xisfits::xisfreader::{XISFChannelStats, XISFData, XISFileLazy};
+    use xisfits::{xisf_data_to_fits, XisfWriteOptions, COMPLEX_LAYOUT_COMMENT};
 
     fn init() {
         let _ = env_logger::builder().is_test(true).try_init();
@@ -332,6 +1378,4029 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_xisf_read_zlibsh_8bits() {
+        init();
+
+        // The 8 bits zlib image, with its compression codec and size replaced (the image
+        // data is an attachment at 4096, after the XML header)
+        let xisf =
+            fs::read("tests/images/xisf-image-gray-8x8-8bits-compression-size.xisf").unwrap();
+        let header_length = u32::from_le_bytes(xisf[8..12].try_into().unwrap()) as usize;
+        let xml = String::from_utf8_lossy(&xisf[16..16 + header_length]).into_owned();
+        let read = |compression: &str| {
+            let xml = xml.replace("zlib:100", compression);
+            let mut image = b"XISF0100".to_vec();
+            image.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+            image.extend_from_slice(&[0; 4]);
+            image.extend_from_slice(xml.as_bytes());
+            image.resize(4096, 0);
+            image.extend_from_slice(&xisf[4096..]);
+            XISFile::from_bytes(&image)
+        };
+
+        // Test that shuffling 1 byte samples is a no-op
+        let zlib = read("zlib:64").unwrap();
+        let zlib_sh = read("zlib+sh:64").unwrap();
+        assert_eq!(zlib_sh.header().compression_codec(), "zlib+sh");
+        assert_eq!(xisf_values(zlib_sh.data()), xisf_values(zlib.data()));
+
+        // Test that shuffling is only a suffix of the supported codecs
+        for codec in &["lz4+sh:64", "zlib+sh+sh:64", "+sh:64"] {
+            match read(codec) {
+                Err(XisfError::UnsupportedCompression { codec }) => {
+                    assert!(codec.ends_with("+sh"));
+                }
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_rgb_working_space() {
+        init();
+
+        // Test that the RGB working space is parsed and mapped to FITS keywords
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-8x8-8bits-rgbws.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let rgbws = file.header().rgb_working_space().unwrap();
+        assert_eq!(rgbws.gamma(), "1.8");
+        assert_eq!(rgbws.x(), [0.64, 0.3, 0.15]);
+        assert_eq!(rgbws.y(), [0.33, 0.6, 0.06]);
+        assert_eq!(rgbws.luminance(), [0.2126, 0.7152, 0.0722]);
+        assert_eq!(rgbws.name(), "Custom Working Space");
+
+        let keywords = rgbws.fits_keywords();
+        assert!(!rgbws.is_srgb());
+        assert!(keywords.contains(&FITSKeyword::new("RGBWS_GA", "1.8", "RGBWS gamma")));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "RGBWS_SR",
+            "F",
+            "RGBWS uses the sRGB transfer function"
+        )));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "RGBWS_X1",
+            "0.64",
+            "RGBWS red x chromaticity"
+        )));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "RGBWS_L3",
+            "0.0722",
+            "RGBWS blue luminance coefficient"
+        )));
+        assert!(keywords.contains(&FITSKeyword::string(
+            "RGBWS_NM",
+            "Custom Working Space",
+            "RGBWS name"
+        )));
+
+        // sRGB transfer function instead of gamma
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-8x8-8bits-rgbws-srgb.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let rgbws = file.header().rgb_working_space().unwrap();
+        assert!(rgbws.is_srgb());
+        assert_eq!(rgbws.x(), [0.648_431, 0.321_152, 0.155_886]);
+        let keywords = rgbws.fits_keywords();
+        assert!(keywords.contains(&FITSKeyword::string("RGBWS_GA", "sRGB", "RGBWS gamma")));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "RGBWS_SR",
+            "T",
+            "RGBWS uses the sRGB transfer function"
+        )));
+
+        // Non-finite gammas are written as strings, and non-finite coordinates as zero
+        let xml = br#"<xisf version="1.0"><Image geometry="1:1:3" sampleFormat="UInt8" colorSpace="RGB"><RGBWorkingSpace gamma="inf" x="NaN:0.3:1e-20" y="0.33:inf:0.06" Y="0.2:0.7:0.1"/></Image></xisf>"#;
+        let file = read_xisf_xml(xml).unwrap();
+        let keywords = file.header().rgb_working_space().unwrap().fits_keywords();
+        let value = |name: &str| {
+            keywords
+                .iter()
+                .find(|keyword| keyword.name == name)
+                .map(|keyword| keyword.value.as_str())
+        };
+        assert_eq!(value("RGBWS_GA"), Some("'inf'"));
+        assert_eq!(value("RGBWS_X1"), Some("0"));
+        assert_eq!(value("RGBWS_X3"), Some("1E-20"));
+        assert_eq!(value("RGBWS_Y2"), Some("0"));
+
+        // Files without the element don't have a working space
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.header().rgb_working_space().is_none());
+    }
+
+    #[test]
+    fn test_xisf_read_display_function() {
+        init();
+
+        // Test that the display function is parsed and mapped to FITS keywords
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-16bits-zlib.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let display_function = file.header().display_function().unwrap();
+        assert_eq!(display_function.midtones().len(), 4);
+        assert_eq!(display_function.midtones()[3], 0.5);
+        assert_eq!(display_function.shadows()[3], 0.0);
+        assert_eq!(display_function.highlights(), &[1.0, 1.0, 1.0, 1.0]);
+        assert_eq!(display_function.low(), &[0.0, 0.0, 0.0, 0.0]);
+        assert_eq!(display_function.high(), &[1.0, 1.0, 1.0, 1.0]);
+
+        let keywords = display_function.fits_keywords();
+        assert_eq!(keywords.len(), 20);
+        assert!(keywords.contains(&FITSKeyword::new(
+            "DSPF_M3",
+            "0.5",
+            "Display function midtones balance 3"
+        )));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "DSPF_H0",
+            "1",
+            "Display function highlights clipping point 0"
+        )));
+
+        assert_eq!(
+            display_function.comment_lines()[3],
+            "XISF display function m3 = 0.5"
+        );
+        assert_eq!(display_function.comment_lines().len(), 20);
+
+        // Non-finite values are invalid numbers, and tiny ones are written with an exponent
+        let xml = br#"<xisf version="1.0"><Image geometry="1:1:1" sampleFormat="UInt8"><DisplayFunction m="inf:NaN:-inf:1e-300" s="0:0:0:0" h="1:1:1:1" l="0:0:0:0" r="1:1:1:1"/></Image></xisf>"#;
+        let file = read_xisf_xml(xml).unwrap();
+        let display_function = file.header().display_function().unwrap();
+        assert_eq!(display_function.midtones(), &[0.0, 0.0, 0.0, 1e-300]);
+        let values: Vec<_> = display_function.fits_keywords()[..4]
+            .iter()
+            .map(|keyword| keyword.value.clone())
+            .collect();
+        assert_eq!(values, vec!["0", "0", "0", "1E-300"]);
+
+        // Files without the element don't have a display function
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-16bits-zlib_sh.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.header().display_function().is_none());
+    }
+
+    #[test]
+    fn test_xisf_read_display_function_elements() {
+        init();
+
+        // Test that the parameters are also read from child elements
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-display-function-elements.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let display_function = file.header().display_function().unwrap();
+        assert_eq!(display_function.name(), "Elements");
+        assert_eq!(display_function.midtones(), &[0.25]);
+        assert_eq!(display_function.shadows(), &[0.125]);
+        assert_eq!(display_function.highlights(), &[0.75]);
+        assert_eq!(display_function.low(), &[0.0]);
+        assert_eq!(display_function.high(), &[1.0]);
+        assert_eq!(
+            display_function.comment_lines(),
+            vec![
+                "XISF display function name = Elements".to_owned(),
+                "XISF display function m0 = 0.25".to_owned(),
+                "XISF display function s0 = 0.125".to_owned(),
+                "XISF display function h0 = 0.75".to_owned(),
+                "XISF display function l0 = 0".to_owned(),
+                "XISF display function r0 = 1".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_xisf_read_resolution() {
+        init();
+
+        // Test that the resolution is parsed and mapped to pixel sizes
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-resolution-cm.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let resolution = file.header().resolution().unwrap();
+        assert_eq!(resolution.horizontal(), 100.0);
+        assert_eq!(resolution.vertical(), 50.0);
+        assert_eq!(resolution.unit(), XISFResolutionUnit::Centimeter);
+        assert_eq!(resolution.pixel_size(), (100.0, 200.0));
+        assert_eq!(
+            resolution.fits_keywords(),
+            vec![
+                FITSKeyword::new("XPIXSZ", "100", "[um] Pixel width from XISF resolution"),
+                FITSKeyword::new("YPIXSZ", "200", "[um] Pixel height from XISF resolution"),
+                FITSKeyword::new("XRESOL", "100", "[pixel/cm] XISF horizontal resolution"),
+                FITSKeyword::new("YRESOL", "50", "[pixel/cm] XISF vertical resolution"),
+            ]
+        );
+
+        // Inches are the default unit
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let resolution = file.header().resolution().unwrap();
+        assert_eq!(resolution.unit(), XISFResolutionUnit::Inch);
+        assert_eq!(resolution.horizontal(), 72.0);
+
+        // Non-numeric values are ignored
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-resolution-invalid.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.header().resolution().is_none());
+
+        // As infinite values
+        for resolution in &["inf", "-inf", "NaN", "1e400"] {
+            let xml = format!(
+                r#"<xisf version="1.0"><Image geometry="1:1:1" sampleFormat="UInt8"><Resolution horizontal="{}" vertical="72"/></Image></xisf>"#,
+                resolution
+            );
+            let file = read_xisf_xml(xml.as_bytes()).unwrap();
+            assert!(file.header().resolution().is_none(), "{}", resolution);
+        }
+
+        // Very large values are written with an exponent
+        let xml = br#"<xisf version="1.0"><Image geometry="1:1:1" sampleFormat="UInt8"><Resolution horizontal="1e300" vertical="72"/></Image></xisf>"#;
+        let file = read_xisf_xml(xml).unwrap();
+        let keywords = file.header().resolution().unwrap().fits_keywords();
+        assert_eq!(keywords[0].value, "2.54E-296");
+        assert_eq!(keywords[2].value, "1E300");
+        assert_eq!(keywords[3].value, "72");
+    }
+
+    #[test]
+    fn test_xisf_read_resolution_attributes() {
+        init();
+
+        // Test that the resolution attributes of <Image> are written as XPIXSZ/YPIXSZ
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-resolution-attributes.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let resolution = file.header().resolution().unwrap();
+        assert_eq!(resolution.horizontal(), 254.0);
+        assert_eq!(resolution.vertical(), 127.0);
+        assert_eq!(resolution.unit(), XISFResolutionUnit::Inch);
+        // 1 inch = 25400 um
+        assert_eq!(resolution.pixel_size(), (100.0, 200.0));
+
+        let cards = convert_fits_cards(xisf_filename);
+        assert!(cards.contains(&format!(
+            "{:80}",
+            "XPIXSZ  = 100                  / [um] Pixel width from XISF resolution"
+        )));
+        assert!(cards.contains(&format!(
+            "{:80}",
+            "YPIXSZ  = 200                  / [um] Pixel height from XISF resolution"
+        )));
+        assert!(cards.contains(&format!(
+            "{:80}",
+            "YRESOL  = 127                  / [pixel/inch] XISF vertical resolution"
+        )));
+
+        // No pixel size without resolution
+        let xisf_filename = Path::new("tests/images/xisf-image-dark-8x8-16bits.xisf");
+        let cards = convert_fits_cards(xisf_filename);
+        assert!(cards.iter().all(|card| !card.starts_with("XPIXSZ")));
+
+        // The pixel size of the camera's FITS keywords is kept instead
+        let xml = br#"<xisf version="1.0"><Image geometry="8:8:1" sampleFormat="UInt8" xResolution="254" yResolution="127"><FITSKeyword name="XPIXSZ" value="3.76" comment="Pixel width"/><FITSKeyword name="YPIXSZ" value="3.76" comment="Pixel height"/></Image></xisf>"#;
+        let file = read_xisf_xml(xml).unwrap();
+        let fits_hd = xisfits::fits_header_data(&file, Box::new([]), 8, 1);
+        let names: Vec<_> = fits_hd
+            .extra_keywords
+            .iter()
+            .map(|keyword| keyword.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["XRESOL", "YRESOL"]);
+    }
+
+    #[test]
+    fn test_xisf_read_cfa_pattern() {
+        init();
+
+        // Test that the Bayer pattern is parsed and written as BAYERPAT
+        let xisf_filename = Path::new("tests/images/xisf-image-cfa-8x8-16bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().cfa_pattern(), "RGGB");
+        assert_eq!(file.header().cfa_source_pattern_name(), "RGGB");
+        let keywords = file.header().fits_keywords();
+        assert!(keywords.contains(&FITSKeyword::string(
+            "BAYERPAT",
+            "RGGB",
+            "Bayer color pattern"
+        )));
+        // With the offsets of the pattern, as for the ColorFilterArray element
+        assert!(keywords.contains(&FITSKeyword::new(
+            "XBAYROFF",
+            "0",
+            "X offset of Bayer array"
+        )));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "YBAYROFF",
+            "0",
+            "Y offset of Bayer array"
+        )));
+
+        // No BAYERPAT without pattern
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().cfa_pattern(), "");
+        assert!(file
+            .header()
+            .fits_keywords()
+            .iter()
+            .all(|keyword| keyword.name != "BAYERPAT"));
+
+        // The Bayer pattern, resolution and range of the camera's FITS keywords are kept
+        // instead of the derived ones
+        let xml = br#"<xisf version="1.0"><Image geometry="8:8:1" sampleFormat="Float32" bounds="0:1" cfaPattern="RGGB" xResolution="254" yResolution="127">
+            <FITSKeyword name="BAYERPAT" value="'GRBG'" comment=""/>
+            <FITSKeyword name="XBAYROFF" value="1" comment=""/>
+            <FITSKeyword name="YBAYROFF" value="0" comment=""/>
+            <FITSKeyword name="XRESOL" value="300" comment=""/>
+            <FITSKeyword name="YRESOL" value="300" comment=""/>
+            <FITSKeyword name="DATAMIN" value="0" comment=""/>
+            <FITSKeyword name="DATAMAX" value="65535" comment=""/>
+            </Image></xisf>"#;
+        let file = read_xisf_xml(xml).unwrap();
+        let names: Vec<_> = file
+            .header()
+            .fits_keywords()
+            .into_iter()
+            .map(|keyword| keyword.name)
+            .collect();
+        assert!(names.contains(&"BAYERPAT".to_owned()));
+        assert!(names.contains(&"DATAMIN".to_owned()));
+        let fits_hd = xisfits::fits_header_data(&file, Box::new([]), -32, 1);
+        let names: Vec<_> = fits_hd
+            .extra_keywords
+            .iter()
+            .map(|keyword| keyword.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["XPIXSZ", "YPIXSZ"]);
+    }
+
+    #[test]
+    fn test_xisf_read_properties() {
+        init();
+
+        // Test that XISF properties are parsed and documented as HISTORY lines
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-16bits-zlib.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().properties().len(), 6);
+        assert_eq!(
+            file.header().property("XISF:CreationTime"),
+            Some("2019-10-28T23:24:17Z")
+        );
+        assert_eq!(file.header().property("XISF:CompressionLevel"), Some("0"));
+        assert_eq!(file.header().properties()[5].property_type(), "Int32");
+        assert_eq!(
+            file.header().history(),
+            vec![
+                "Converted from XISF created by PixInsight 01.08.06.1457".to_owned(),
+                "XISF creation time: 2019-10-28T23:24:17Z".to_owned(),
+            ]
+        );
+
+        // Empty elements are properties with an empty value, not the ones of the next text
+        let xml = br#"<xisf version="1.0"><Image geometry="1:1:1" sampleFormat="UInt8">
+            <Property id="Observation:Object:Name" type="String"/>
+            <ICCProfile location="inline:base64">AAECAw==</ICCProfile>
+            </Image></xisf>"#;
+        let file = read_xisf_xml(xml).unwrap();
+        assert_eq!(file.header().properties().len(), 1);
+        assert_eq!(file.header().property("Observation:Object:Name"), Some(""));
+        assert_eq!(file.icc_profile(), Some(&[0, 1, 2, 3][..]));
+
+        // Files without properties still document the conversion
+        let xisf_filename = Path::new("tests/images/xisf-image-cfa-8x8-16bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.header().properties().is_empty());
+        assert_eq!(
+            file.header().history(),
+            vec!["Converted from XISF".to_owned()]
+        );
+    }
+
+    #[test]
+    fn test_xisf_read_escaped_attributes() {
+        init();
+
+        // Test that XML entities in attribute values are decoded before writing them to FITS
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-escaped.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(
+            file.keywords()[0],
+            FITSKeyword::new("OBJECT", "'M&M''s <galaxy>'", "Name \"quoted\"")
+        );
+        assert_eq!(file.keywords()[1].comment, "a < b && b > c");
+        assert_eq!(
+            file.header().property("Observation:Object:Name"),
+            Some("Smith & Wesson")
+        );
+
+        let fits_hd = fitswriter::FitsHeaderData {
+            bitpix: 8,
+            naxis: 2,
+            naxis_vec: vec![8, 8],
+            bzero: 0,
+            bscale: 1,
+            datamin: 0,
+            datamax: 0,
+            history: Vec::new(),
+            comment: Vec::new(),
+            extra_keywords: Vec::new(),
+            data_bytes: Vec::new().into_boxed_slice(),
+        };
+        let mut fits = Vec::new();
+        fitswriter::fits_write_header_keywords_to(&mut fits, &fits_hd, file.keywords()).unwrap();
+        // After the 8 mandatory cards (SIMPLE to BSCALE)
+        let cards: Vec<_> = fits
+            .chunks(80)
+            .skip(8)
+            .map(|card| String::from_utf8_lossy(card).trim_end().to_owned())
+            .collect();
+        assert_eq!(cards[0], "OBJECT  = 'M&M''s <galaxy>'    / Name \"quoted\"");
+        assert_eq!(cards[1], "HISTORY a < b && b > c");
+
+        // Decoded entities out of printable ASCII are replaced, and long strings are
+        // continued in CONTINUE cards, so that each card is 80 ASCII bytes
+        let long = format!("{}'s {}", "M".repeat(66), "é".repeat(80));
+        let keywords = [
+            FITSKeyword::string("OBSERVER", &format!("{}é", "a".repeat(66)), ""),
+            FITSKeyword::string("OBJECT", &long, "Long name"),
+            FITSKeyword::new("COMMENT", "", &format!("{}Ω", "c".repeat(71))),
+        ];
+        let mut fits = Vec::new();
+        fitswriter::fits_write_header_keywords_to(&mut fits, &fits_hd, &keywords).unwrap();
+        assert_eq!(fits.len(), 2880);
+        assert!(fits
+            .iter()
+            .all(|&byte| byte == b' ' || byte.is_ascii_graphic()));
+        let cards: Vec<_> = fits
+            .chunks(80)
+            .skip(8)
+            .map(|card| String::from_utf8_lossy(card).trim_end().to_owned())
+            .collect();
+        assert_eq!(cards[0], format!("OBSERVER= '{}?'", "a".repeat(66)));
+        assert_eq!(cards[1], format!("OBJECT  = '{}&'", "M".repeat(66)));
+        assert_eq!(cards[2], format!("CONTINUE  '''s {}&'", "?".repeat(63)));
+        assert_eq!(
+            cards[3],
+            format!("CONTINUE  '{}' / Long name", "?".repeat(17))
+        );
+        assert_eq!(cards[4], format!("COMMENT {}?", "c".repeat(71)));
+    }
+
+    #[test]
+    fn test_fits_write_xisf_keywords() {
+        init();
+
+        // Test that the FITS keywords of the XISF file follow the mandatory cards
+        let xisf = fs::read("tests/images/xisf-image-gray-8x8-8bits-bom.xisf").unwrap();
+        let fits = xisfits::convert_xisf_to_fits(&xisf).unwrap();
+        let (cards, _) = read_fits_values(&fits);
+        let names: Vec<_> = cards.iter().map(|card| card[..8].trim_end()).collect();
+        assert_eq!(
+            &names[..8],
+            &["SIMPLE", "BITPIX", "NAXIS", "NAXIS1", "NAXIS2", "EXTEND", "BZERO", "BSCALE"]
+        );
+        assert!(names.contains(&"TELESCOP"));
+
+        // Their structural keywords describe the original data, not the converted one
+        let xml = br#"<xisf version="1.0"><Image geometry="2:2:1" sampleFormat="UInt16" colorSpace="Gray" location="attachment:4096:8"><FITSKeyword name="SIMPLE" value="T" comment=""/><FITSKeyword name="BITPIX" value="8" comment=""/><FITSKeyword name="NAXIS" value="3" comment=""/><FITSKeyword name="NAXIS3" value="1" comment=""/><FITSKeyword name="BZERO" value="0" comment=""/><FITSKeyword name="TELESCOP" value="'Newton'" comment=""/></Image></xisf>"#;
+        let mut xisf = b"XISF0100".to_vec();
+        xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        xisf.extend_from_slice(&[0; 4]);
+        xisf.extend_from_slice(xml);
+        xisf.resize(4096, 0);
+        for value in &[0_u16, 1, 32768, 65535] {
+            xisf.extend_from_slice(&value.to_le_bytes());
+        }
+        let fits = xisfits::convert_xisf_to_fits(&xisf).unwrap();
+        let (cards, values) = read_fits_values(&fits);
+        assert_eq!(values, vec![0.0, 1.0, 32768.0, 65535.0]);
+        let names: Vec<_> = cards.iter().map(|card| card[..8].trim_end()).collect();
+        assert_eq!(
+            names,
+            vec!["SIMPLE", "BITPIX", "NAXIS", "NAXIS1", "NAXIS2", "EXTEND", "BZERO", "BSCALE"]
+                .into_iter()
+                .chain(Some("TELESCOP"))
+                .chain(if cfg!(feature = "date") {
+                    Some("DATE")
+                } else {
+                    None
+                })
+                .chain(Some("HISTORY"))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_fits_keyword_from_record() {
+        init();
+
+        // Test that the cards written are parsed back as the keywords of the XISF file
+        let xisf_filename = "tests/images/xisf-image-gray-8x8-8bits-escaped.xisf";
+        let file = XISFile::read_file(Path::new(xisf_filename)).unwrap();
+        let fits = xisfits::convert_xisf_to_fits(&fs::read(xisf_filename).unwrap()).unwrap();
+        let keywords: Vec<_> = fits
+            .chunks(80)
+            .take_while(|card| !card.starts_with(b"END "))
+            .map(|card| FITSKeyword::from_fits_record(card).unwrap())
+            .collect();
+        assert_eq!(keywords[0], FITSKeyword::new("SIMPLE", "T", ""));
+        assert_eq!(keywords[1], FITSKeyword::new("BITPIX", "8", ""));
+        for keyword in file.keywords().iter() {
+            assert!(keywords.contains(keyword), "{:?}", keyword);
+        }
+
+        // Values and comments are trimmed, slashes in strings aren't comments
+        let record = |card: &str| FITSKeyword::from_fits_record(format!("{:80}", card).as_bytes());
+        assert_eq!(
+            record("TELESCOP= 'Newton 200/1000  '     / Telescope / OTA"),
+            Ok(FITSKeyword::new(
+                "TELESCOP",
+                "'Newton 200/1000  '",
+                "Telescope / OTA"
+            ))
+        );
+        assert_eq!(
+            record("EXPTIME =                300.5"),
+            Ok(FITSKeyword::new("EXPTIME", "300.5", ""))
+        );
+        assert_eq!(
+            record("COMMENT   = not a value"),
+            Ok(FITSKeyword::new("COMMENT", "", "  = not a value"))
+        );
+
+        // Only whole records of printable ASCII characters, with closed strings
+        assert_eq!(
+            FITSKeyword::from_fits_record(b"SIMPLE  = T"),
+            Err(FitsRecordParseError::Length { length: 11 })
+        );
+        let mut latin1 = format!("{:80}", "OBJECT  = 'M31?'").into_bytes();
+        latin1[14] = 0xe9;
+        assert_eq!(
+            FITSKeyword::from_fits_record(&latin1),
+            Err(FitsRecordParseError::InvalidByte {
+                byte: 0xe9,
+                column: 15
+            })
+        );
+        assert_eq!(
+            record("OBJECT  = 'M31''s"),
+            Err(FitsRecordParseError::UnterminatedString)
+        );
+    }
+
+    #[test]
+    fn test_fits_write_history_and_comment() {
+        init();
+
+        // Test that history and comment lines are written as FITS cards
+        let fits_filename = std::env::temp_dir().join("xisfits-test-history-comment.fits");
+        let fits_hd = fitswriter::FitsHeaderData {
+            bitpix: 8,
+            naxis: 2,
+            naxis_vec: vec![2, 2],
+            bzero: 0,
+            bscale: 1,
+            datamin: 0,
+            datamax: 0,
+            history: vec!["First history line".to_owned(), String::new()],
+            comment: vec!["A comment".to_owned()],
+            extra_keywords: Vec::new(),
+            data_bytes: vec![0, 1, 2, 3].into_boxed_slice(),
+        };
+        fitswriter::fits_write_data(&fits_filename, &fits_hd).unwrap();
+        let fits = fs::read(&fits_filename).unwrap();
+        fs::remove_file(&fits_filename).unwrap();
+
+        assert_eq!(fits.len(), 2 * 2880);
+        let cards: Vec<_> = fits[..2880]
+            .chunks(80)
+            .map(|card| String::from_utf8_lossy(card).trim_end().to_owned())
+            .collect();
+        // The DATE keyword (if enabled) is written before them
+        let date = usize::from(cfg!(feature = "date"));
+        assert_eq!(cards[8 + date], "HISTORY First history line");
+        assert_eq!(cards[9 + date], "COMMENT A comment");
+        assert_eq!(cards[10 + date], "END");
+        assert_eq!(&fits[2880..2884], &[0, 1, 2, 3]);
+    }
+
+    #[cfg(feature = "date")]
+    #[test]
+    fn test_fits_write_date() {
+        init();
+
+        // Test that the date of the conversion is written as DATE
+        let fits_hd = fitswriter::FitsHeaderData {
+            bitpix: 8,
+            naxis: 2,
+            naxis_vec: vec![2, 2],
+            bzero: 0,
+            bscale: 1,
+            datamin: 0,
+            datamax: 0,
+            history: Vec::new(),
+            comment: Vec::new(),
+            extra_keywords: Vec::new(),
+            data_bytes: vec![0, 1, 2, 3].into_boxed_slice(),
+        };
+        let mut fits = Vec::new();
+        fitswriter::fits_write_data_to(&mut fits, &fits_hd).unwrap();
+        let dates: Vec<_> = fits[..2880]
+            .chunks(80)
+            .map(String::from_utf8_lossy)
+            .filter(|card| card.starts_with("DATE "))
+            .collect();
+        assert_eq!(dates.len(), 1);
+        // ISO-8601 UTC date and time, e.g. '2020-08-30T18:24:05'
+        let value = dates[0][10..].split('/').next().unwrap().trim();
+        assert_eq!(value.len(), 21);
+        let bytes = value.as_bytes();
+        assert_eq!((bytes[0], bytes[5], bytes[8]), (b'\'', b'-', b'-'));
+        assert_eq!(
+            (bytes[11], bytes[14], bytes[17], bytes[20]),
+            (b'T', b':', b':', b'\'')
+        );
+
+        // The DATE of the XISF keywords is kept
+        let keywords = vec![
+            FITSKeyword::new("SIMPLE", "T", ""),
+            FITSKeyword::new("BITPIX", "8", ""),
+            FITSKeyword::new("NAXIS", "2", ""),
+            FITSKeyword::new("NAXIS1", "2", ""),
+            FITSKeyword::new("NAXIS2", "2", ""),
+            FITSKeyword::string("DATE", "2020-08-30T18:24:05", "Creation date"),
+        ];
+        let mut fits = Vec::new();
+        fitswriter::fits_write_data_keywords_to(&mut fits, &fits_hd, &keywords).unwrap();
+        let dates: Vec<_> = fits[..2880]
+            .chunks(80)
+            .map(String::from_utf8_lossy)
+            .filter(|card| card.starts_with("DATE "))
+            .collect();
+        assert_eq!(dates.len(), 1);
+        assert!(dates[0].contains("'2020-08-30T18:24:05'"));
+    }
+
+    #[test]
+    fn test_xisf_read_icc_profile() {
+        init();
+
+        // Test that inline (base64) ICC profiles are decoded
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-8bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().icc_profile_location(), "inline:base64");
+        let icc_profile = file.icc_profile().unwrap();
+        // The profile starts with its size and has the "acsp" signature at byte 36
+        assert_eq!(icc_profile.len(), 3024);
+        assert_eq!(&icc_profile[..4], &[0, 0, 0x0b, 0xd0]);
+        assert_eq!(&icc_profile[36..40], b"acsp");
+
+        // Test that ICC profiles stored as attachments are read
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-icc-attachment.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().icc_profile_location(), "attachment:4160:128");
+        let icc_profile = file.icc_profile().unwrap();
+        assert_eq!(icc_profile.len(), 128);
+        assert_eq!(&icc_profile[36..40], b"acsp");
+        // The image data is still read
+        match file.data() {
+            XISFData::UInt8(data) => assert_eq!(data[0][63], 63),
+            _ => panic!("unexpected data type"),
+        }
+
+        // Files without the element don't have a profile
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.icc_profile().is_none());
+    }
+
+    #[test]
+    fn test_xisf_split_channels() {
+        init();
+
+        // Test that each channel is converted on its own
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let (data, bitpix) = xisf_data_to_fits(&file, ByteOrder::Big);
+        let channel_bytes = 256 * 256 * 2;
+        assert_eq!(data.len(), 3 * channel_bytes);
+        for channel in 0..3 {
+            let (channel_data, channel_bitpix) =
+                xisf_channels_to_fits(&file, ByteOrder::Big, channel..channel + 1);
+            assert_eq!(channel_bitpix, bitpix);
+            assert_eq!(
+                &channel_data[..],
+                &data[channel * channel_bytes..(channel + 1) * channel_bytes]
+            );
+        }
+
+        // Test the names of the FITS files of each channel
+        let cli = Cli::from_iter(&["xisfits", "--split-channels", "in.xisf", "out/image.fits"]);
+        assert_eq!(cli.rgb_mode(), RgbMode::Split);
+        assert_eq!(
+            cli.channel_output(0, XISFColorSpace::Rgb),
+            Path::new("out/image_red.fits")
+        );
+        assert_eq!(
+            cli.channel_output(2, XISFColorSpace::Rgb),
+            Path::new("out/image_blue.fits")
+        );
+        assert_eq!(
+            cli.channel_output(1, XISFColorSpace::Gray),
+            Path::new("out/image_1.fits")
+        );
+        let cli = Cli::from_iter(&["xisfits", "--rgb-mode", "split", "in.xisf", "out.fits"]);
+        assert_eq!(cli.rgb_mode(), RgbMode::Split);
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        assert_eq!(cli.rgb_mode(), RgbMode::Cube);
+        let args = [
+            "xisfits",
+            "--split-channels",
+            "--rgb-mode",
+            "cube",
+            "in.xisf",
+            "out.fits",
+        ];
+        assert!(Cli::from_iter_safe(&args).is_err());
+    }
+
+    #[test]
+    fn test_fits_rgb_extensions() {
+        init();
+
+        // Test that the first channel is the primary HDU and the others IMAGE extensions
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let output = std::env::temp_dir().join("xisfits-test-rgb-extensions.fits");
+        let cli = Cli::from_iter(&[
+            "xisfits",
+            "--rgb-mode",
+            "extensions",
+            "--checksum",
+            xisf_filename.to_str().unwrap(),
+            output.to_str().unwrap(),
+        ]);
+        assert_eq!(cli.rgb_mode(), RgbMode::Extensions);
+        run(&cli).unwrap();
+        let fits = fs::read(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        let channel_bytes = 256 * 256 * 2;
+        let (data, _) = xisf_data_to_fits(&file, ByteOrder::Big);
+        let mut hdus = Vec::new();
+        let mut rest = &fits[..];
+        while !rest.is_empty() {
+            let end = rest
+                .chunks(80)
+                .position(|card| card.starts_with(b"END "))
+                .unwrap();
+            let header_length = ((end + 1) * 80 + 2879) / 2880 * 2880;
+            let hdu_length = header_length + (channel_bytes + 2879) / 2880 * 2880;
+            hdus.push((&rest[..header_length], &rest[header_length..hdu_length]));
+            rest = &rest[hdu_length..];
+        }
+        assert_eq!(hdus.len(), 3);
+        for (channel, (header, hdu_data)) in hdus.iter().enumerate() {
+            let cards: Vec<_> = header
+                .chunks(80)
+                .map(|card| String::from_utf8_lossy(card).trim_end().to_owned())
+                .collect();
+            let first = if channel == 0 { "SIMPLE" } else { "XTENSION" };
+            assert!(cards[0].starts_with(first));
+            assert!(cards.iter().any(|card| card.starts_with("NAXIS   = 2 ")));
+            assert!(cards.iter().any(|card| card.starts_with("CHECKSUM= '")));
+            let extname = ["", "'GREEN'", "'BLUE'"][channel];
+            assert_eq!(
+                cards
+                    .iter()
+                    .any(|card| card.contains("EXTNAME = ") && card.contains(extname)),
+                channel > 0
+            );
+            assert_eq!(
+                &hdu_data[..channel_bytes],
+                &data[channel * channel_bytes..(channel + 1) * channel_bytes]
+            );
+        }
+    }
+
+    #[test]
+    fn test_xisf_write_fits() {
+        init();
+
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let output = std::env::temp_dir().join("xisfits-test-write-fits.fits");
+        let values = xisf_values(file.data());
+        let channel_length = 256 * 256;
+
+        // Test that the default options convert the whole image to a single HDU
+        file.write_fits(&output, &XisfWriteOptions::default())
+            .unwrap();
+        let (cards, fits_values) = read_fits_values(&fs::read(&output).unwrap());
+        assert!(cards.iter().any(|card| card.starts_with("NAXIS3  = 3 ")));
+        assert!(cards
+            .iter()
+            .any(|card| card.starts_with("BZERO   = 32768 ")));
+        assert_eq!(fits_values, values);
+
+        // Test that a single channel is written, with its values as they are
+        let options = XisfWriteOptions {
+            bzero_correction: false,
+            channel_filter: Some(1),
+            ..XisfWriteOptions::default()
+        };
+        file.write_fits(&output, &options).unwrap();
+        let (cards, fits_values) = read_fits_values(&fs::read(&output).unwrap());
+        assert!(cards.iter().any(|card| card.starts_with("NAXIS   = 2 ")));
+        assert!(cards.iter().any(|card| card.starts_with("BITPIX  = 32 ")));
+        assert!(cards.iter().any(|card| card.starts_with("BZERO   = 0 ")));
+        assert_eq!(fits_values, &values[channel_length..2 * channel_length]);
+
+        // Test that each channel is written to its own HDU, and the checksum of each HDU
+        // is -0 (so is the ones' complement sum of the whole file)
+        let options = XisfWriteOptions {
+            add_checksum: true,
+            split_hdus: true,
+            ..XisfWriteOptions::default()
+        };
+        file.write_fits(&output, &options).unwrap();
+        let fits = fs::read(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        let extensions: Vec<_> = fits
+            .chunks(2880)
+            .filter(|block| block.starts_with(b"XTENSION"))
+            .collect();
+        assert_eq!(extensions.len(), 2);
+        assert!(String::from_utf8_lossy(extensions[1]).contains("EXTNAME = 'BLUE'"));
+        let mut sum: u64 = fits
+            .chunks(4)
+            .map(|word| u64::from(u32::from_be_bytes(word.try_into().unwrap())))
+            .sum();
+        while sum > 0xffff_ffff {
+            sum = (sum & 0xffff_ffff) + (sum >> 32);
+        }
+        assert_eq!(sum, 0xffff_ffff);
+
+        // Test that channels out of range are rejected
+        let options = XisfWriteOptions {
+            channel_filter: Some(3),
+            ..XisfWriteOptions::default()
+        };
+        match file.write_fits(&output, &options) {
+            Err(XisfError::ChannelOutOfRange {
+                channel: 3,
+                channel_count: 3,
+            }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_xisf_select_channels() {
+        init();
+
+        // Test that only the selected channels are kept, in the order given
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let values = xisf_values(file.data());
+        let channel_size = 256 * 256;
+        let mut selected = XISFile::read_file(xisf_filename).unwrap();
+        selected.select_channels(&[2, 0]).unwrap();
+        assert_eq!(selected.header().geometry().channel_count(), 2);
+        assert_eq!(selected.header().color_space(), XISFColorSpace::Gray);
+        let expected: Vec<f64> = values[2 * channel_size..]
+            .iter()
+            .chain(&values[..channel_size])
+            .copied()
+            .collect();
+        assert_eq!(xisf_values(selected.data()), expected);
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        let fits_hd = fits_header_data(&cli, &selected, Box::new([]), 8, 2);
+        assert_eq!(fits_hd.naxis_vec, vec![256, 256, 2]);
+
+        // A channel can be selected more than once, and a single one makes a 2-D image
+        let mut selected = XISFile::read_file(xisf_filename).unwrap();
+        selected.select_channels(&[1, 1]).unwrap();
+        let green = &values[channel_size..2 * channel_size];
+        assert_eq!(xisf_values(selected.data()), [green, green].concat());
+        let mut selected = XISFile::read_file(xisf_filename).unwrap();
+        selected.select_channels(&[1]).unwrap();
+        let fits_hd = fits_header_data(&cli, &selected, Box::new([]), 8, 1);
+        assert_eq!(fits_hd.naxis_vec, vec![256, 256]);
+
+        // Test that a channel out of the image is rejected
+        let mut selected = XISFile::read_file(xisf_filename).unwrap();
+        match selected.select_channels(&[0, 3]) {
+            Err(XisfError::ChannelOutOfRange {
+                channel,
+                channel_count,
+            }) => assert_eq!((channel, channel_count), (3, 3)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Test the parsing of the selection
+        let cli = Cli::from_iter(&["xisfits", "--channels", "0,2", "in.xisf", "out.fits"]);
+        assert_eq!(cli.channels().unwrap().indices(), &[0, 2]);
+        let cli = Cli::from_iter(&["xisfits", "--channels", "0:1,2", "in.xisf", "out.fits"]);
+        assert_eq!(cli.channels().unwrap().indices(), &[0, 1, 2]);
+        for selection in &["", "1:0", "a", "0,", "0:1:2"] {
+            assert!(selection.parse::<ChannelSelection>().is_err());
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_lazy() {
+        init();
+
+        // Test that channels read on demand are the same as the ones read at once
+        let directory = std::env::temp_dir().join("xisfits-test-lazy");
+        fs::create_dir_all(&directory).unwrap();
+        for name in &[
+            "xisf-image-rgb-256x256-16bits.xisf",
+            "xisf-image-gray-256x256-16bits-zlib.xisf",
+        ] {
+            let xisf_filename = directory.join(name);
+            let _ = fs::copy(Path::new("tests/images").join(name), &xisf_filename).unwrap();
+            let file = XISFile::read_file(&xisf_filename).unwrap();
+            let mut lazy = XISFileLazy::open(&xisf_filename).unwrap();
+            assert_eq!(lazy.file().data(), &XISFData::Empty);
+            assert_eq!(
+                format!("{:?}", lazy.header()),
+                format!("{:?}", file.header())
+            );
+            let channel_count = file.header().geometry().channel_count();
+            for channel in 0..channel_count {
+                assert_eq!(
+                    Some(lazy.channel(channel).unwrap().clone()),
+                    file.data().channel(channel)
+                );
+            }
+            match lazy.channel(channel_count) {
+                Err(XisfError::ChannelOutOfRange { channel, .. }) => {
+                    assert_eq!(channel, channel_count);
+                }
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+
+            // Channels already read aren't read again
+            fs::remove_file(&xisf_filename).unwrap();
+            assert_eq!(
+                Some(lazy.channel(0).unwrap().clone()),
+                file.data().channel(0)
+            );
+            if channel_count == 1 {
+                // The compressed data was read as a whole
+                assert_eq!(lazy.data().unwrap(), file.data());
+            } else {
+                assert!(lazy.data().is_err());
+            }
+        }
+
+        // Channels are read one by one: in lenient mode, the first channel of a file
+        // truncated after it can be read, and the others are zero
+        let rgb_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+        let file = XISFile::read_file(rgb_filename).unwrap();
+        let channel_end = file.header().location_start() as usize + file.header().channel_size();
+        let xisf_filename = directory.join("truncated.xisf");
+        fs::write(
+            &xisf_filename,
+            &fs::read(rgb_filename).unwrap()[..channel_end],
+        )
+        .unwrap();
+        let options = XisfReadOptions {
+            parse_mode: ParseMode::Lenient,
+            ..XisfReadOptions::default()
+        };
+        let mut lazy = XISFileLazy::open_with_options(&xisf_filename, options).unwrap();
+        assert_eq!(
+            Some(lazy.channel(0).unwrap().clone()),
+            file.data().channel(0)
+        );
+        assert_eq!(xisf_values(lazy.channel(1).unwrap()), vec![0.0; 256 * 256]);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn test_unshuffle() {
+        init();
+
+        // Test that the bytes of shuffled items are put back together. The first version
+        // of `unshuffle` wrote every byte to the same position, so byte shuffled images
+        // (zlib+sh, lz4+sh...) were converted to zeros.
+        let items: Vec<u8> = (0..12).collect();
+        let shuffled = vec![0, 4, 8, 1, 5, 9, 2, 6, 10, 3, 7, 11];
+        assert_eq!(convert::unshuffle(&shuffled, 4), items);
+        let shuffled = vec![0, 2, 4, 6, 8, 10, 1, 3, 5, 7, 9, 11];
+        assert_eq!(convert::unshuffle(&shuffled, 2), items);
+        // The bytes beyond the last item are left as they are
+        assert_eq!(convert::unshuffle(&[0, 2, 1, 3, 4], 2), vec![0, 1, 2, 3, 4]);
+        assert_eq!(convert::unshuffle(&items, 1), items);
+        assert_eq!(convert::unshuffle(&[], 8), Vec::<u8>::new());
+        assert_eq!(convert::shuffle(&items, 2), shuffled);
+    }
+
+    /// Byte arrays of up to 4 KiB, and the size of the items shuffled (1 to 16 bytes).
+    fn bytes_and_item_size() -> impl Strategy<Value = (Vec<u8>, usize)> {
+        (prop::collection::vec(any::<u8>(), 0..4096), 1..=16_usize)
+    }
+
+    proptest! {
+        #[test]
+        fn test_fits_record_parse(record in "[ -~]{80}") {
+            // Printable ASCII records are keywords, unless a string isn't closed
+            match FITSKeyword::from_fits_record(record.as_bytes()) {
+                Ok(keyword) => prop_assert_eq!(keyword.name, record[..8].trim_end()),
+                Err(e) => prop_assert_eq!(e, FitsRecordParseError::UnterminatedString),
+            }
+        }
+
+        #[test]
+        fn test_unshuffle_shuffled((bytes, byte_size) in bytes_and_item_size()) {
+            let shuffled = convert::shuffle(&bytes, byte_size);
+            prop_assert_eq!(shuffled.len(), bytes.len());
+            prop_assert_eq!(convert::unshuffle(&shuffled, byte_size), bytes);
+        }
+
+        #[test]
+        fn test_convert_length(bytes in prop::collection::vec(any::<u8>(), 0..4096)) {
+            // Each whole sample is converted, the bytes of a partial sample are dropped
+            let u16_bytes = convert::u16_to_i16_to_v_u8_be(&convert::u8_to_v_u16(&bytes));
+            prop_assert_eq!(u16_bytes.len(), bytes.len() / 2 * 2);
+            let u32_bytes = convert::u32_to_i32_to_v_u8_be(&convert::u8_to_v_u32(&bytes));
+            prop_assert_eq!(u32_bytes.len(), bytes.len() / 4 * 4);
+            let i16_bytes = convert::i16_to_v_u8_be(&convert::u8_to_v_i16(&bytes));
+            prop_assert_eq!(i16_bytes.len(), bytes.len() / 2 * 2);
+            let f64_bytes = convert::f64_to_v_u8_be(&convert::u8_to_v_f64(&bytes));
+            prop_assert_eq!(f64_bytes.len(), bytes.len() / 8 * 8);
+        }
+
+        #[test]
+        fn test_convert_unsigned_offset(values in prop::collection::vec(any::<u16>(), 0..1024)) {
+            // Unsigned values are stored as signed big endian values minus 32768 (BZERO)
+            let fits = convert::u16_to_i16_to_v_u8_be(&values);
+            let restored: Vec<u16> = fits
+                .chunks(2)
+                .map(|bytes| (i32::from(i16::from_be_bytes([bytes[0], bytes[1]])) + 32768) as u16)
+                .collect();
+            prop_assert_eq!(&restored, &values);
+            // The little endian conversion is the same, with the bytes of each value swapped
+            let mut swapped = convert::u16_to_i16_to_v_u8_le(&values);
+            swapped.chunks_mut(2).for_each(<[u8]>::reverse);
+            prop_assert_eq!(swapped, fits);
+        }
+
+        #[test]
+        fn test_convert_unsigned_offset_u32(values in prop::collection::vec(any::<u32>(), 0..1024)) {
+            // As for u16, minus 2147483648 (BZERO)
+            let fits = convert::u32_to_i32_to_v_u8_be(&values);
+            let restored: Vec<u32> = fits
+                .chunks(4)
+                .map(|bytes| (i64::from(i32::from_be_bytes(bytes.try_into().unwrap())) + 2_147_483_648) as u32)
+                .collect();
+            prop_assert_eq!(&restored, &values);
+            let mut swapped = convert::u32_to_i32_to_v_u8_le(&values);
+            swapped.chunks_mut(4).for_each(<[u8]>::reverse);
+            prop_assert_eq!(swapped, fits);
+        }
+
+        #[test]
+        fn test_convert_float_round_trip(values in prop::collection::vec(any::<f32>(), 0..1024)) {
+            // The bytes of IEEE 754 values are kept, in the byte order requested
+            let bytes = convert::f32_to_v_u8_le(&values);
+            prop_assert_eq!(convert::f32_to_v_u8_le(&convert::u8_to_v_f32(&bytes)), bytes.clone());
+            let mut swapped = convert::f32_to_v_u8_be(&convert::u8_to_v_f32(&bytes));
+            swapped.chunks_mut(4).for_each(<[u8]>::reverse);
+            prop_assert_eq!(swapped, bytes);
+        }
+    }
+
+    #[test]
+    fn test_xisf_data_interleaved() {
+        init();
+
+        // Test that planar channels are interleaved and back
+        let data = XISFData::UInt8(
+            vec![
+                vec![1, 2, 3].into_boxed_slice(),
+                vec![4, 5, 6].into_boxed_slice(),
+                vec![7, 8, 9].into_boxed_slice(),
+            ]
+            .into_boxed_slice(),
+        );
+        let interleaved = data.to_interleaved_u8().unwrap();
+        assert_eq!(interleaved, vec![1, 4, 7, 2, 5, 8, 3, 6, 9]);
+        assert_eq!(XISFData::from_interleaved_u8(&interleaved, 3), data);
+        assert_eq!(data.to_interleaved_u16(), None);
+        assert_eq!(
+            XISFData::from_interleaved_u8(&interleaved, 0),
+            XISFData::Empty
+        );
+
+        // Test that the FITS data is written with the channels as the first axis
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let (data, bitpix) = xisf_data_to_fits(&file, ByteOrder::Big);
+        let mut fits_hd = xisfits::fits_header_data(&file, data.clone(), bitpix, 3);
+        xisfits::fits_interleave_channels(&mut fits_hd, 3, XISFSampleFormat::UInt16);
+        assert_eq!(fits_hd.naxis_vec, vec![3, 256, 256]);
+        assert_eq!(fits_hd.data_bytes.len(), data.len());
+        let channel_bytes = 256 * 256 * 2;
+        for (pixel, channel) in &[(0, 0), (1, 2), (1000, 1), (256 * 256 - 1, 2)] {
+            let interleaved = (pixel * 3 + channel) * 2;
+            let planar = channel * channel_bytes + pixel * 2;
+            assert_eq!(
+                &fits_hd.data_bytes[interleaved..interleaved + 2],
+                &data[planar..planar + 2]
+            );
+        }
+        let cli = Cli::from_iter(&["xisfits", "--interleaved", "in.xisf", "out.fits"]);
+        assert!(cli.interleaved());
+        assert_eq!(stream_bitpix(&cli, &file), None);
+    }
+
+    #[test]
+    fn test_cli_verbose() {
+        init();
+
+        // Test that each -v prints more log messages
+        let levels = [
+            LevelFilter::Error,
+            LevelFilter::Warn,
+            LevelFilter::Info,
+            LevelFilter::Debug,
+            LevelFilter::Debug,
+        ];
+        for (verbose, level) in levels.iter().enumerate() {
+            let mut args = vec!["xisfits".to_owned()];
+            if verbose > 0 {
+                args.push(format!("-{}", "v".repeat(verbose)));
+            }
+            args.extend_from_slice(&["in.xisf".to_owned(), "out.fits".to_owned()]);
+            assert_eq!(Cli::from_iter(&args).log_level(), *level);
+        }
+    }
+
+    #[test]
+    fn test_cli_quiet() {
+        init();
+
+        // Test that --quiet conflicts with --verbose
+        let cli = Cli::from_iter(&["xisfits", "-q", "in.xisf", "out.fits"]);
+        assert!(cli.quiet);
+        assert_eq!(cli.log_level(), LevelFilter::Error);
+        assert!(!cli.progress());
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        assert!(!cli.quiet);
+        assert_eq!(cli.log_level(), LevelFilter::Error);
+        assert!(cli.progress());
+        let result = Cli::from_iter_safe(&["xisfits", "--quiet", "-v", "in.xisf", "out.fits"]);
+        assert_eq!(
+            result.unwrap_err().kind,
+            structopt::clap::ErrorKind::ArgumentConflict
+        );
+    }
+
+    #[test]
+    fn test_xisf_read_non_xisf_file() {
+        init();
+
+        // Test that other files are rejected with the bytes found instead of the signature
+        let xisf_filename = std::env::temp_dir().join("xisfits-test-not-xisf.fits");
+        let mut fits = Vec::new();
+        fitswriter::fits_write_data_to(
+            &mut fits,
+            &fitswriter::FitsHeaderData {
+                bitpix: 8,
+                naxis: 2,
+                naxis_vec: vec![2, 2],
+                bzero: 0,
+                bscale: 1,
+                datamin: 0,
+                datamax: 0,
+                history: Vec::new(),
+                comment: Vec::new(),
+                extra_keywords: Vec::new(),
+                data_bytes: vec![0, 1, 2, 3].into_boxed_slice(),
+            },
+        )
+        .unwrap();
+        fs::write(&xisf_filename, &fits).unwrap();
+        let result = XISFile::read_file(&xisf_filename);
+        fs::remove_file(&xisf_filename).unwrap();
+        match result {
+            Err(XisfError::InvalidSignature { found }) => assert_eq!(found, "SIMPLE  "),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Signatures are compared byte by byte, even with null bytes
+        let xisf_filename = std::env::temp_dir().join("xisfits-test-null-signature.xisf");
+        fs::write(&xisf_filename, b"XISF\0\0\0\0\0\0\0\0\0\0\0\0").unwrap();
+        let result = XISFile::read_file(&xisf_filename);
+        fs::remove_file(&xisf_filename).unwrap();
+        match result {
+            Err(XisfError::InvalidSignature { found }) => assert_eq!(found, "XISF\0\0\0\0"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Garbage signatures followed by more data
+        let mut xisf = b"\x89PNG\r\n\x1a\n".to_vec();
+        xisf.extend_from_slice(&[0xff; 100]);
+        match XISFile::read_from(Cursor::new(xisf)) {
+            Err(XisfError::InvalidSignature { found }) => {
+                assert_eq!(found, String::from_utf8_lossy(b"\x89PNG\r\n\x1a\n"));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Header lengths beyond any realistic XML header
+        let xisf_filename = std::env::temp_dir().join("xisfits-test-header-too-large.xisf");
+        fs::write(&xisf_filename, b"XISF0100\xff\xff\xff\xff\0\0\0\0<?xml").unwrap();
+        let result = XISFile::read_file(&xisf_filename);
+        fs::remove_file(&xisf_filename).unwrap();
+        match result {
+            Err(XisfError::HeaderTooLarge { length }) => {
+                assert_eq!(length, u32::max_value());
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        // The limit can be lowered
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let length = XISFile::read_file(xisf_filename).unwrap().header().length();
+        let options = XisfReadOptions {
+            max_header_bytes: length - 1,
+            ..XisfReadOptions::default()
+        };
+        match XISFile::read_file_with_options(xisf_filename, options) {
+            Err(XisfError::HeaderTooLarge { length: found }) => assert_eq!(found, length),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Files shorter than the fixed header
+        let xisf_filename = std::env::temp_dir().join("xisfits-test-short.xisf");
+        fs::write(&xisf_filename, b"XISF0100").unwrap();
+        let result = XISFile::read_file(&xisf_filename);
+        fs::remove_file(&xisf_filename).unwrap();
+        match result {
+            Err(XisfError::TooShort { size }) => assert_eq!(size, 8),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Missing files are I/O errors
+        let xisf_filename = Path::new("tests/images/missing.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_version() {
+        init();
+
+        // Test that the version is read from the signature
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().version(), (1, 0));
+
+        // Newer versions are read (with a warning)
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-version-0110.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().signature(), "XISF0110");
+        assert_eq!(file.header().version(), (1, 10));
+        assert_eq!(file.header().geometry().to_string(), "8:8:1");
+        // Unless the signature is checked strictly
+        let options = XisfReadOptions {
+            strict_signature: true,
+            ..XisfReadOptions::default()
+        };
+        match XISFile::read_file_with_options(xisf_filename, options) {
+            Err(XisfError::InvalidSignature { found }) => assert_eq!(found, "XISF0110"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Versions must be digits
+        let xisf_filename = std::env::temp_dir().join("xisfits-test-bad-version.xisf");
+        fs::write(&xisf_filename, b"XISF01a0\0\0\0\0\0\0\0\0").unwrap();
+        let result = XISFile::read_file(&xisf_filename);
+        fs::remove_file(&xisf_filename).unwrap();
+        match result {
+            Err(XisfError::InvalidSignature { found }) => assert_eq!(found, "XISF01a0"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_header_only() {
+        init();
+
+        // Test that the header and keywords are read as in a full read, without the data
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-32bits.xisf");
+        let (header, keywords) = XISFile::read_header(xisf_filename).unwrap();
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(format!("{:?}", header), format!("{:?}", file.header()));
+        assert_eq!(keywords, file.keywords().into());
+        assert_eq!(header.geometry().dimensions(), &[256, 256]);
+        assert_eq!(header.sample_format(), XISFSampleFormat::UInt32);
+        let header_file = XISFile::read_file_header(xisf_filename).unwrap();
+        assert_eq!(header_file.data(), &XISFData::Empty);
+    }
+
+    #[test]
+    fn test_xisf_read_attachment_out_of_bounds() {
+        init();
+
+        // Test that image data beyond the end of the file is an error
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-out-of-bounds-location.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::AttachmentOutOfBounds {
+                start,
+                length,
+                file_size,
+            }) => assert_eq!((start, length, file_size), (4096, 1000, 4096 + 64)),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        // Also when reading the header only (before streaming the image data)
+        match XISFile::read_file_header(xisf_filename) {
+            Err(XisfError::AttachmentOutOfBounds { .. }) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Positions that would overflow don't wrap around
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-overflow-location.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::AttachmentOutOfBounds { start, length, .. }) => {
+                assert_eq!((start, length), (u64::max_value(), 64));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_adversarial_locations() {
+        init();
+
+        // Test that crafted attachment positions are errors, not panics nor wrong reads
+        let xisf = |location: &str| {
+            let xml = format!(
+                r#"<xisf version="1.0"><Image geometry="8:8:1" sampleFormat="UInt8" location="{}"/></xisf>"#,
+                location
+            );
+            let mut xisf = b"XISF0100".to_vec();
+            xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+            xisf.extend_from_slice(&[0; 4]);
+            xisf.extend_from_slice(xml.as_bytes());
+            xisf.resize(4096, 0);
+            xisf.extend((0..64).map(|value: u8| value + 1));
+            xisf
+        };
+        let max = u64::max_value();
+        for &(start, length) in &[
+            (0, 64),
+            (15, 64),
+            (100, 64),
+            (4096, max),
+            (4096, max - 4095),
+            (max, 1),
+            (max - 10, 20),
+            (1 << 63, 1 << 63),
+        ] {
+            let location = format!("attachment:{}:{}", start, length);
+            let length_bytes = xisf(&location)[8..12].try_into().unwrap();
+            let header_end = 16 + u64::from(u32::from_le_bytes(length_bytes));
+            assert!(header_end > 100);
+            for parse_mode in &[ParseMode::Normal, ParseMode::Lenient] {
+                let options = XisfReadOptions {
+                    parse_mode: *parse_mode,
+                    ..XisfReadOptions::default()
+                };
+                let result = XISFile::read_from_with_options(Cursor::new(xisf(&location)), options);
+                match result {
+                    Err(XisfError::AttachmentOverlapsHeader {
+                        start: s,
+                        header_end: end,
+                    }) if start < header_end => {
+                        assert_eq!((s, end), (start, header_end));
+                    }
+                    Err(XisfError::AttachmentOutOfBounds {
+                        start: s,
+                        length: l,
+                        file_size,
+                    }) if start >= header_end && *parse_mode != ParseMode::Lenient => {
+                        assert_eq!((s, l, file_size), (start, length, 4096 + 64));
+                    }
+                    // The data is read up to the end of the file, if it starts in the file
+                    Ok(file) if *parse_mode == ParseMode::Lenient => {
+                        let expected: Vec<f64> = if start == 4096 {
+                            (1..=64).map(f64::from).collect()
+                        } else {
+                            vec![0.0; 64]
+                        };
+                        assert_eq!(xisf_values(file.data()), expected);
+                    }
+                    other => panic!("{}: unexpected result: {:?}", location, other.map(|_| ())),
+                }
+            }
+        }
+
+        // Attachments in bounds are read
+        let file = XISFile::read_from(Cursor::new(xisf("attachment:4096:64"))).unwrap();
+        assert_eq!(xisf_values(file.data())[63], 64.0);
+
+        // Images without data are errors instead of empty images
+        match XISFile::read_from(Cursor::new(xisf("embedded"))) {
+            Err(XisfError::UnsupportedLocationMethod { method }) => assert_eq!(method, "embedded"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let xml = br#"<xisf version="1.0"><Image geometry="8:8:1" sampleFormat="UInt8"/></xisf>"#;
+        let mut no_location = b"XISF0100".to_vec();
+        no_location.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        no_location.extend_from_slice(&[0; 4]);
+        no_location.extend_from_slice(xml);
+        match XISFile::read_from(Cursor::new(no_location)) {
+            Err(XisfError::MissingLocation) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_reserved() {
+        init();
+
+        // Test that a non-zero reserved field is read (with a warning), but fails in strict mode
+        let xml = br#"<xisf version="1.0"><Image geometry="1:1:1" sampleFormat="UInt8"/></xisf>"#;
+        let mut xisf = b"XISF0100".to_vec();
+        xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        xisf.extend_from_slice(&1_u32.to_le_bytes());
+        xisf.extend_from_slice(xml);
+        let options = XisfReadOptions {
+            read_data: false,
+            ..XisfReadOptions::default()
+        };
+        let file = XISFile::read_from_with_options(Cursor::new(&xisf), options).unwrap();
+        assert_eq!(file.header().reserved(), 1);
+        let options = XisfReadOptions {
+            parse_mode: ParseMode::Strict,
+            ..options
+        };
+        match XISFile::read_from_with_options(Cursor::new(&xisf), options) {
+            Err(XisfError::SpecDeviation { message }) => {
+                assert!(message.starts_with("Reserved header field is 0x00000001"));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert_eq!(read_xisf_xml(xml).unwrap().header().reserved(), 0);
+    }
+
+    #[test]
+    fn test_xisf_read_truncated_data() {
+        init();
+
+        // Test that image data shorter than the geometry is an error instead of a panic
+        // (the RGB image only has data for two channels)
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-4x4-8bits-truncated.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::TruncatedData { channels, expected }) => {
+                assert_eq!((channels, expected), (2, 3));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_parse_modes() {
+        init();
+
+        let read = |name: &str, parse_mode| {
+            let options = XisfReadOptions {
+                parse_mode,
+                ..XisfReadOptions::default()
+            };
+            XISFile::read_file_with_options(&Path::new("tests/images").join(name), options)
+        };
+
+        // Test that valid files are read in strict mode
+        let file = read("xisf-image-gray-8x8-8bits-checksum.xisf", ParseMode::Strict).unwrap();
+        assert!(file.header().checksum().starts_with("sha-256:"));
+
+        // Test that deviations from the specification are only errors in strict mode
+        let xisf_filename = "xisf-image-gray-8x8-8bits-unknown-attributes.xisf";
+        assert!(read(xisf_filename, ParseMode::Normal).is_ok());
+        match read(xisf_filename, ParseMode::Strict) {
+            Err(XisfError::UnknownAttributes { .. }) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let xisf_filename = "xisf-image-gray-8x8-8bits-rgb-mismatch.xisf";
+        assert!(read(xisf_filename, ParseMode::Normal).is_ok());
+        match read(xisf_filename, ParseMode::Strict) {
+            Err(XisfError::ColorSpaceMismatch { channels, .. }) => assert_eq!(channels, 1),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let xisf_filename = "xisf-image-gray-8x8-8bits-namespace.xisf";
+        assert!(read(xisf_filename, ParseMode::Normal).is_ok());
+        match read(xisf_filename, ParseMode::Strict) {
+            Err(XisfError::MissingChecksum) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let xml = br#"<xisf version="1.0" xmlns="http://www.pixinsight.com/xisf"><Image geometry="1:1:1" sampleFormat="UInt8" colorSpace="Grey"/></xisf>"#;
+        assert!(read_xisf_xml(xml).is_ok());
+        let mut xisf = b"XISF0100".to_vec();
+        xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        xisf.extend_from_slice(&[0; 4]);
+        xisf.extend_from_slice(xml);
+        let options = XisfReadOptions {
+            parse_mode: ParseMode::Strict,
+            ..XisfReadOptions::default()
+        };
+        match XISFile::read_from_with_options(Cursor::new(xisf), options) {
+            Err(XisfError::SpecDeviation { message }) => {
+                assert_eq!(message, "Unknown color space: Grey");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Test that damaged image data is recovered in lenient mode
+        let xisf_filename = "xisf-image-gray-8x8-8bits-compression-size.xisf";
+        assert!(read(xisf_filename, ParseMode::Normal).is_err());
+        let file = read(xisf_filename, ParseMode::Lenient).unwrap();
+        assert_eq!(xisf_values(file.data()).len(), 64);
+        let xisf_filename = "xisf-image-gray-8x8-8bits-out-of-bounds-location.xisf";
+        assert!(read(xisf_filename, ParseMode::Normal).is_err());
+        let file = read(xisf_filename, ParseMode::Lenient).unwrap();
+        assert_eq!(
+            xisf_values(file.data()),
+            (0..64).map(f64::from).collect::<Vec<_>>()
+        );
+        let xisf_filename = "xisf-image-rgb-4x4-8bits-truncated.xisf";
+        assert!(read(xisf_filename, ParseMode::Normal).is_err());
+        let file = read(xisf_filename, ParseMode::Lenient).unwrap();
+        let values = xisf_values(file.data());
+        assert_eq!(values.len(), 3 * 16);
+        assert!(values[40..].iter().all(|&value| value == 0.0));
+        let xisf_filename = "xisf-image-gray-8x8-8bits-checksum-mismatch.xisf";
+        assert!(read(xisf_filename, ParseMode::Normal).is_err());
+        let file = read(xisf_filename, ParseMode::Lenient).unwrap();
+        assert_eq!(xisf_values(file.data())[63], 252.0);
+
+        // Test that lenient mode doesn't fill in more image data than the file could hold
+        // (a 10 GB image with 72 bytes of data, uncompressed and compressed)
+        let zlib_data = fs::read("tests/images/xisf-image-gray-8x8-8bits-compression-size.xisf")
+            .unwrap()[4096..]
+            .to_vec();
+        let read_lenient = |compression: &str| {
+            let xml = format!(
+                r#"<xisf version="1.0"><Image geometry="100000:100000:1" sampleFormat="UInt8" colorSpace="Gray" {}location="attachment:4096:72"/></xisf>"#,
+                compression
+            );
+            let mut xisf = b"XISF0100".to_vec();
+            xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+            xisf.extend_from_slice(&[0; 4]);
+            xisf.extend_from_slice(xml.as_bytes());
+            xisf.resize(4096, 0);
+            xisf.extend_from_slice(&zlib_data);
+            let options = XisfReadOptions {
+                parse_mode: ParseMode::Lenient,
+                ..XisfReadOptions::default()
+            };
+            XISFile::read_from_with_options(Cursor::new(xisf), options)
+        };
+        match read_lenient("") {
+            Err(XisfError::ImplausibleDataSize { max_size }) => assert_eq!(max_size, 4096 + 72),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        match read_lenient(r#"compression="zlib:10000000000" "#) {
+            Err(XisfError::ImplausibleDataSize { max_size }) => assert_eq!(max_size, 72 * 1032),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Test the command line options
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        assert_eq!(cli.parse_mode(), ParseMode::Normal);
+        let cli = Cli::from_iter(&["xisfits", "--strict", "in.xisf", "out.fits"]);
+        assert_eq!(cli.parse_mode(), ParseMode::Strict);
+        let cli = Cli::from_iter(&["xisfits", "--lenient", "in.xisf", "out.fits"]);
+        assert_eq!(cli.parse_mode(), ParseMode::Lenient);
+        assert!(Cli::from_iter_safe(&["xisfits", "--strict", "--lenient", "in", "out"]).is_err());
+    }
+
+    /// Reads a XISF file in memory with the XML header `xml` (and no image data).
+    fn read_xisf_xml(xml: &[u8]) -> Result<XISFile, XisfError> {
+        let mut xisf = b"XISF0100".to_vec();
+        xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        xisf.extend_from_slice(&[0; 4]);
+        xisf.extend_from_slice(xml);
+        let options = XisfReadOptions {
+            read_data: false,
+            ..XisfReadOptions::default()
+        };
+        XISFile::read_from_with_options(Cursor::new(xisf), options)
+    }
+
+    #[test]
+    fn test_xisf_read_invalid_attributes() {
+        init();
+
+        let image = |attributes: &[u8]| {
+            let mut xml = b"<xisf version=\"1.0\"><Image ".to_vec();
+            xml.extend_from_slice(attributes);
+            xml.extend_from_slice(b"/></xisf>");
+            read_xisf_xml(&xml)
+        };
+
+        // Malformed attributes (a value without quotes)
+        match image(b"geometry=8:8:1 sampleFormat=\"UInt8\"") {
+            Err(XisfError::Parse(XisfParseError::Attribute { element, .. })) => {
+                assert_eq!(&*element, "Image");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Values that aren't UTF-8 or have unknown escape sequences
+        for value in &[&b"UInt8\xff"[..], &b"UInt8&unknown;"[..]] {
+            let mut attributes = b"geometry=\"8:8:1\" sampleFormat=\"".to_vec();
+            attributes.extend_from_slice(value);
+            attributes.push(b'"');
+            match image(&attributes) {
+                Err(XisfError::Parse(XisfParseError::Decode {
+                    element,
+                    attribute,
+                    value: found,
+                    ..
+                })) => {
+                    assert_eq!((&*element, &*attribute), ("Image", "sampleFormat"));
+                    assert_eq!(found, String::from_utf8_lossy(value));
+                }
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+
+        // Integers that can't be parsed
+        let attributes: [(&[u8], &str, &str); 3] = [
+            (b"location=\"attachment:x:64\"", "location", "x"),
+            (b"location=\"attachment:4096:-1\"", "location", "-1"),
+            (b"compression=\"zlib\"", "compression", ""),
+        ];
+        for (attribute, name, raw) in &attributes {
+            let mut attributes = b"geometry=\"8:8:1\" sampleFormat=\"UInt8\" ".to_vec();
+            attributes.extend_from_slice(attribute);
+            match image(&attributes) {
+                Err(XisfError::Parse(XisfParseError::Integer {
+                    element,
+                    attribute,
+                    value,
+                    ..
+                })) => assert_eq!((&*element, &*attribute, &*value), ("Image", *name, *raw)),
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+
+        // Invalid geometries
+        match image(b"geometry=\"256:abc:1\" sampleFormat=\"UInt8\"") {
+            Err(e @ XisfError::Parse(XisfParseError::Geometry { .. })) => assert_eq!(
+                e.to_string(),
+                "invalid XML header: invalid image geometry in Image.geometry attribute: \"256:abc:1\""
+            ),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Malformed text of elements
+        match read_xisf_xml(b"<xisf><Property id=\"a\" type=\"String\">&unknown;</Property></xisf>")
+        {
+            Err(XisfError::Xml { .. }) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Unknown image types are ignored
+        let file = image(b"geometry=\"8:8:1\" sampleFormat=\"UInt8\" imageType=\"Other\"");
+        assert!(file.is_ok());
+    }
+
+    #[test]
+    fn test_xisf_read_compression_errors() {
+        init();
+
+        // Test that unknown compression codecs are errors
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-unknown-codec.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::UnsupportedCompression { codec }) => assert_eq!(codec, "lzma"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Test that the uncompressed data must have the declared size
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-compression-size.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::UncompressedSizeMismatch { size, expected }) => {
+                assert_eq!((size, expected), (64, 100));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Test that data uncompressing to more than the declared size (16 MiB of zeros
+        // declared as 64 bytes) is an error even in lenient mode, without uncompressing it
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-zlib-bomb.xisf");
+        for parse_mode in &[ParseMode::Normal, ParseMode::Lenient] {
+            let options = XisfReadOptions {
+                parse_mode: *parse_mode,
+                ..XisfReadOptions::default()
+            };
+            match XISFile::read_file_with_options(xisf_filename, options) {
+                Err(XisfError::UncompressedSizeExceeded { expected }) => assert_eq!(expected, 64),
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_malformed_xml() {
+        init();
+
+        // Test that a malformed XML header (a <Property> element not closed) is an error
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-malformed.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::Xml { position, .. }) => assert!(position > 0),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_truncated_header() {
+        init();
+
+        // Test a file cut in the middle of the XML header, whose length exceeds the file size
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-truncated-file.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::HeaderOutOfBounds { length, file_size }) => {
+                assert_eq!((length, file_size), (551, 291));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Test a XML header without the end of the root element
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-truncated-xml.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::TruncatedHeader { read, length }) => {
+                assert_eq!((read, length), (327, 327));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_dump_xml() {
+        init();
+
+        // Test that the dumped XML header is parsed as the original one
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.raw_xml().contains("<Image "));
+
+        let xml_filename = std::env::temp_dir().join("xisfits-test-dump-xml.xml");
+        dump_xml(&file, Some(&xml_filename)).unwrap();
+        let xml = fs::read(&xml_filename).unwrap();
+        fs::remove_file(&xml_filename).unwrap();
+        let pretty = pretty_xml(file.raw_xml()).unwrap();
+        let xisf = fs::read(xisf_filename).unwrap();
+        for xml in &[xml, pretty.into_bytes()] {
+            // Replace the XML header of the file, keeping the attachments in place
+            let attachments = usize::try_from(file.header().location_start()).unwrap();
+            assert!(16 + xml.len() <= attachments);
+            let mut dumped = b"XISF0100".to_vec();
+            dumped.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+            dumped.extend_from_slice(&[0; 4]);
+            dumped.extend_from_slice(xml);
+            dumped.resize(attachments, 0);
+            dumped.extend_from_slice(&xisf[attachments..]);
+            let dumped = XISFile::read_from(Cursor::new(dumped)).unwrap();
+            let (header, dumped_header) = (file.header(), dumped.header());
+            assert_eq!(
+                dumped_header.geometry().dimensions(),
+                header.geometry().dimensions()
+            );
+            assert_eq!(
+                dumped_header.geometry().channel_count(),
+                header.geometry().channel_count()
+            );
+            assert_eq!(dumped_header.sample_format(), header.sample_format());
+            assert_eq!(dumped_header.color_space(), header.color_space());
+            assert_eq!(dumped_header.location(), header.location());
+            assert_eq!(
+                dumped_header.icc_profile_location(),
+                header.icc_profile_location()
+            );
+            assert_eq!(dumped_header.properties(), header.properties());
+            assert_eq!(dumped.keywords(), file.keywords());
+            assert_eq!(dumped.icc_profile(), file.icc_profile());
+            assert_eq!(xisf_values(dumped.data()), xisf_values(file.data()));
+        }
+
+        // Test that the copy of the XML header can be skipped
+        let options = XisfReadOptions {
+            keep_raw_xml: false,
+            ..Default::default()
+        };
+        let file = XISFile::read_file_with_options(xisf_filename, options).unwrap();
+        assert_eq!(file.raw_xml(), "");
+        assert_eq!(file.header().geometry().dimensions(), &[256, 256]);
+    }
+
+    #[test]
+    fn test_fits_checksum() {
+        init();
+
+        // Ones' complement sum of the 32 bits words of FITS bytes
+        let checksum = |bytes: &[u8]| {
+            let mut sum: u64 = bytes
+                .chunks(4)
+                .map(|word| u64::from(u32::from_be_bytes(word.try_into().unwrap())))
+                .sum();
+            while sum > 0xffff_ffff {
+                sum = (sum & 0xffff_ffff) + (sum >> 32);
+            }
+            sum
+        };
+        let header_length = |hdu: &[u8]| {
+            let end = hdu
+                .chunks(80)
+                .position(|card| card.starts_with(b"END "))
+                .unwrap();
+            ((end + 1) * 80 + 2879) / 2880 * 2880
+        };
+        let card_value = |header: &[u8], name: &str| {
+            let card = header
+                .chunks(80)
+                .find(|card| card.starts_with(format!("{:8}=", name).as_bytes()))
+                .unwrap_or_else(|| panic!("missing {} card", name));
+            String::from_utf8_lossy(&card[10..30]).trim().to_owned()
+        };
+
+        // Test that the checksum of each HDU (the image and its thumbnail) is -0, and
+        // the DATASUM is the checksum of the data unit
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-16bits-thumbnail.xisf");
+        let output = std::env::temp_dir().join("xisfits-test-checksum.fits");
+        let cli = Cli::from_iter(&[
+            "xisfits",
+            "--checksum",
+            "--save-thumbnail",
+            xisf_filename.to_str().unwrap(),
+            output.to_str().unwrap(),
+        ]);
+        run(&cli).unwrap();
+        let fits = fs::read(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        let primary_length = header_length(&fits) + 2880;
+        for hdu in &[&fits[..primary_length], &fits[primary_length..]] {
+            let (header, data) = hdu.split_at(header_length(hdu));
+            assert_eq!(checksum(hdu), 0xffff_ffff);
+            assert_eq!(
+                card_value(header, "DATASUM"),
+                format!("'{}'", checksum(data))
+            );
+            let encoded = card_value(header, "CHECKSUM");
+            assert_eq!(encoded.len(), 18);
+            assert!(encoded[1..17].chars().all(|c| c.is_ascii_alphanumeric()));
+        }
+
+        // Checksum keywords of the XISF file don't apply to the converted data
+        let xml = br#"<xisf version="1.0"><Image geometry="2:1:1" sampleFormat="UInt16">
+            <FITSKeyword name="CHECKSUM" value="'Z9aAd7ZAZ7aAd7ZA'" comment="HDU checksum"/>
+            <FITSKeyword name="DATASUM" value="'1234'" comment="Data unit checksum"/>
+            </Image></xisf>"#;
+        let file = read_xisf_xml(xml).unwrap();
+        let fits_hd = fits_header_data(&cli, &file, Box::new([0, 1, 2, 3]), 16, 1);
+        let mut fits = Vec::new();
+        let primary = FitsHdu::Primary(file.keywords());
+        fitswriter::fits_write_hdu_to(&mut fits, fits_hd, primary, true).unwrap();
+        assert!(!String::from_utf8_lossy(&fits).contains("Z9aAd7ZAZ7aAd7ZA"));
+        assert_eq!(card_value(&fits, "DATASUM"), "'66051'");
+        assert_eq!(checksum(&fits), 0xffff_ffff);
+
+        // Nor are they written without checksums, which must be in the header to be
+        // filled in
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        let fits_hd = fits_header_data(&cli, &file, Box::new([]), 16, 1);
+        let mut fits = Vec::new();
+        fitswriter::fits_write_hdu_to(&mut fits, fits_hd, primary, false).unwrap();
+        assert!(!String::from_utf8_lossy(&fits).contains("Z9aAd7ZAZ7aAd7ZA"));
+        assert!(fitswriter::fits_update_checksum(&mut fits, &[]).is_err());
+    }
+
+    #[test]
+    fn test_fits_embed_xml() {
+        init();
+
+        // Test that the XML header is embedded as COMMENT cards and can be rebuilt
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let cli = Cli::from_iter(&["xisfits", "--embed-xml", "in.xisf", "out.fits"]);
+        assert!(cli.embed_xml());
+        let (data_bytes, bitpix) = xisf_data_to_fits(&file, ByteOrder::Big);
+        let fits_hd = fits_header_data(&cli, &file, data_bytes, bitpix, 3);
+        let mut fits = Vec::new();
+        fitswriter::fits_write_data_to(&mut fits, &fits_hd).unwrap();
+        let comments: Vec<String> = fits
+            .chunks(80)
+            .map(|card| String::from_utf8_lossy(card).into_owned())
+            .take_while(|card| !card.starts_with("END "))
+            .filter(|card| card.starts_with("COMMENT "))
+            .map(|card| card[8..].trim_end().to_owned())
+            .skip_while(|comment| comment != "BEGIN XISF XML HEADER")
+            .collect();
+        assert_eq!(comments.last().unwrap(), "END XISF XML HEADER");
+        let mut xml = String::new();
+        for comment in &comments[1..comments.len() - 1] {
+            if comment.ends_with('&') {
+                xml.push_str(&comment[..comment.len() - 1]);
+            } else {
+                xml.push_str(comment);
+                xml.push('\n');
+            }
+        }
+        let expected: Vec<&str> = file
+            .raw_xml()
+            .lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .collect();
+        assert_eq!(xml.lines().collect::<Vec<_>>(), expected);
+
+        // Long lines are continued and non-ASCII characters escaped, without splitting
+        // the character references
+        let xml = format!(
+            r#"<xisf version="1.0"><Image geometry="1:1:1" sampleFormat="UInt8"><Property id="Instrument:Filter:Name" type="String" value="{}{}"/></Image></xisf>"#,
+            "\u{c5}ngstr\u{f6}m",
+            "\u{c5}".repeat(40)
+        );
+        let file = read_xisf_xml(xml.as_bytes()).unwrap();
+        let lines = file.xml_comment_lines();
+        let (last, continued) = lines[1..lines.len() - 1].split_last().unwrap();
+        assert!(continued.len() > 2);
+        assert!(continued
+            .iter()
+            .all(|line| line.len() <= 72 && line.ends_with('&')));
+        assert!(!last.ends_with('&'));
+        let parts: Vec<&str> = continued
+            .iter()
+            .map(|line| &line[..line.len() - 1])
+            .chain(std::iter::once(last.as_str()))
+            .collect();
+        let references: usize = parts
+            .iter()
+            .map(|part| part.matches("&#xC5;").count())
+            .sum();
+        assert_eq!(references, 41);
+        assert!(parts
+            .concat()
+            .contains("value=\"&#xC5;ngstr&#xF6;m&#xC5;&#xC5;"));
+        assert!(lines.iter().all(|line| line.is_ascii()));
+
+        // A line ending with '&' isn't taken as continued
+        let xml = "<xisf version=\"1.0\"><!-- R&\nD --><Image geometry=\"1:1:1\" \
+                   sampleFormat=\"UInt8\"/></xisf>";
+        let file = read_xisf_xml(xml.as_bytes()).unwrap();
+        let lines = file.xml_comment_lines();
+        assert_eq!(lines[1], "<xisf version=\"1.0\"><!-- R&#x26;");
+        assert_eq!(
+            lines[2],
+            "D --><Image geometry=\"1:1:1\" sampleFormat=\"UInt8\"/></xisf>"
+        );
+    }
+
+    #[test]
+    fn test_xisf_read_mmap() {
+        init();
+
+        // Test that files mapped in memory are read as the ones read to buffers
+        let options = XisfReadOptions {
+            use_mmap: true,
+            ..XisfReadOptions::default()
+        };
+        for xisf_filename in &[
+            "xisf-image-rgb-256x256-16bits.xisf",
+            "xisf-image-gray-256x256-16bits-zlib.xisf",
+            "xisf-image-gray-8x8-8bits-checksum.xisf",
+        ] {
+            let xisf_filename = Path::new("tests/images").join(xisf_filename);
+            let file = XISFile::read_file(&xisf_filename).unwrap();
+            let mapped = XISFile::read_file_with_options(&xisf_filename, options).unwrap();
+            assert_eq!(mapped.raw_xml(), file.raw_xml());
+            assert_eq!(xisf_values(mapped.data()), xisf_values(file.data()));
+        }
+
+        // Also when recovering data beyond the end of the file
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-out-of-bounds-location.xisf");
+        let options = XisfReadOptions {
+            parse_mode: ParseMode::Lenient,
+            ..options
+        };
+        let file = XISFile::read_file_with_options(xisf_filename, options).unwrap();
+        assert_eq!(
+            xisf_values(file.data()),
+            (0..64).map(f64::from).collect::<Vec<_>>()
+        );
+        match XISFile::read_file_with_options(
+            xisf_filename,
+            XisfReadOptions {
+                use_mmap: true,
+                ..XisfReadOptions::default()
+            },
+        ) {
+            Err(XisfError::AttachmentOutOfBounds { .. }) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_channel_streaming() {
+        init();
+
+        // Test that images read channel by channel are read as the ones read at once
+        let options = XisfReadOptions {
+            channel_streaming_threshold: 0,
+            ..XisfReadOptions::default()
+        };
+        let read = |name: &str, options| {
+            XISFile::read_file_with_options(&Path::new("tests/images").join(name), options)
+        };
+        for xisf_filename in &[
+            "xisf-image-rgb-256x256-8bits.xisf",
+            "xisf-image-rgb-256x256-16bits.xisf",
+            "xisf-image-gray-256x256-float-32bits.xisf",
+            "xisf-image-gray-8x8-int16.xisf",
+            "xisf-image-gray-8x8-8bits-checksum.xisf",
+            "xisf-image-gray-256x256-16bits-zlib.xisf",
+        ] {
+            let file = read(xisf_filename, XisfReadOptions::default()).unwrap();
+            let streamed = read(xisf_filename, options).unwrap();
+            assert_eq!(streamed.data(), file.data());
+        }
+
+        // The data is verified while it's read
+        let xisf_filename = "xisf-image-gray-8x8-8bits-checksum-mismatch.xisf";
+        match read(xisf_filename, options) {
+            Err(XisfError::ChecksumMismatch { got, .. }) => {
+                assert_eq!(got, "0814ecf9bd459d952268e27467aaacf5fd72698e");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Missing channels are errors, or zero in lenient mode
+        let xisf_filename = "xisf-image-rgb-4x4-8bits-truncated.xisf";
+        match read(xisf_filename, options) {
+            Err(XisfError::TruncatedData { channels, expected }) => {
+                assert_eq!((channels, expected), (2, 3));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let lenient = XisfReadOptions {
+            parse_mode: ParseMode::Lenient,
+            ..options
+        };
+        let file = read(xisf_filename, lenient).unwrap();
+        let expected = read(
+            xisf_filename,
+            XisfReadOptions {
+                parse_mode: ParseMode::Lenient,
+                ..XisfReadOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(file.data(), expected.data());
+        assert!(xisf_values(file.data())[40..]
+            .iter()
+            .all(|&value| value == 0.0));
+
+        // A huge image with a short attachment fails before the channels are allocated
+        let xml = r#"<xisf version="1.0"><Image geometry="100000:100000:3" sampleFormat="UInt8" colorSpace="RGB" location="attachment:4096:72"/></xisf>"#;
+        let mut xisf = b"XISF0100".to_vec();
+        xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        xisf.extend_from_slice(&[0; 4]);
+        xisf.extend_from_slice(xml.as_bytes());
+        xisf.resize(4096 + 72, 0);
+        match XISFile::read_from_with_options(Cursor::new(&xisf), options) {
+            Err(XisfError::TruncatedData { channels, expected }) => {
+                assert_eq!((channels, expected), (0, 3));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        match XISFile::read_from_with_options(Cursor::new(&xisf), lenient) {
+            Err(XisfError::ImplausibleDataSize { max_size }) => assert_eq!(max_size, 4096 + 72),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_bounds() {
+        init();
+
+        // Test a float image whose values are in the declared bounds
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-float-32bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().bounds(), Some((0.0, 1.0)));
+        assert_eq!(file.data().count_out_of_bounds((0.0, 1.0)), 0);
+        let fits_data =
+            xisf_channels_to_fits_bounded(&file, ByteOrder::Big, 0..1, (0.0, 1.0)).unwrap();
+        assert_eq!(fits_data.len(), 255 * 255 * 2);
+
+        // Test a float image with values out of the bounds, which are clipped
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-float-32bits-out-of-bounds.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().bounds(), Some((0.0, 1.0)));
+        assert_eq!(file.data().count_out_of_bounds((0.0, 1.0)), 2);
+        let fits_data =
+            xisf_channels_to_fits_bounded(&file, ByteOrder::Big, 0..1, (0.0, 1.0)).unwrap();
+        let values: Vec<_> = fits_data
+            .chunks_exact(2)
+            .map(|bytes| i16::from_be_bytes([bytes[0], bytes[1]]))
+            .collect();
+        // FITS value = stored value + 32768
+        assert_eq!(values[0], -32768);
+        assert_eq!(values[63], 32767);
+        assert_eq!(values[10], 32767);
+        assert_eq!(values[20], -32768);
+        // 21 / 63 * 65535 - 32768 = 21845 - 32768
+        assert_eq!(values[21], -10923);
+
+        // Integer images aren't rescaled
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().bounds(), None);
+        assert!(xisf_channels_to_fits_bounded(&file, ByteOrder::Big, 0..1, (0.0, 1.0)).is_none());
+
+        // Infinite bounds (or ranges) are invalid
+        for bounds in &["-inf:inf", "0:inf", "NaN:1", "-1e308:1e308"] {
+            let xml = format!(
+                r#"<xisf version="1.0"><Image geometry="1:1:1" sampleFormat="Float32" bounds="{}"/></xisf>"#,
+                bounds
+            );
+            let file = read_xisf_xml(xml.as_bytes()).unwrap();
+            assert_eq!(file.header().bounds(), None, "{}", bounds);
+            assert!(file
+                .header()
+                .fits_keywords()
+                .iter()
+                .all(|keyword| keyword.name != "DATAMIN"));
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_id() {
+        init();
+
+        // Test that the image id is written as OBJECT, the uuid as COMMENT and the
+        // bounds of float data as DATAMIN and DATAMAX
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-float-32bits-id.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().id(), Some("M31"));
+        assert_eq!(
+            file.header().uuid(),
+            Some("5f1b8ad0-2c3e-4b8e-9d61-3a4f0f6c7e21")
+        );
+        let keywords = file.header().fits_keywords();
+        assert!(keywords.contains(&FITSKeyword::string("OBJECT", "M31", "XISF image id")));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "COMMENT",
+            "",
+            "XISF image uuid 5f1b8ad0-2c3e-4b8e-9d61-3a4f0f6c7e21"
+        )));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "DATAMIN",
+            "0.0",
+            "Minimum data value (XISF bounds)"
+        )));
+        assert!(keywords.contains(&FITSKeyword::new(
+            "DATAMAX",
+            "1.0",
+            "Maximum data value (XISF bounds)"
+        )));
+
+        // Integer images don't have bounds, and files without id don't have OBJECT
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().id(), None);
+        assert_eq!(file.header().uuid(), None);
+        assert!(file.header().fits_keywords().iter().all(|keyword| {
+            keyword.name != "OBJECT" && keyword.name != "DATAMIN" && keyword.name != "DATAMAX"
+        }));
+
+        // Ids not following the XISF identifier grammar are kept (with a warning)
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-invalid-id.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().id(), Some("1st image"));
+    }
+
+    #[test]
+    fn test_xisf_read_namespace_prefix() {
+        init();
+
+        // Test that elements with a namespace prefix (<xisf:Image>) are read as
+        // the unprefixed ones
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-namespace.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-namespace-prefixed.xisf");
+        let prefixed = XISFile::read_file(xisf_filename).unwrap();
+
+        assert_eq!(prefixed.header().geometry().to_string(), "8:8:1");
+        assert_eq!(prefixed.header().image_type(), &XISFImageType::Flat);
+        assert_eq!(
+            prefixed.header().property("Observation:Object:Name"),
+            Some("M42")
+        );
+        assert_eq!(prefixed.keywords(), file.keywords());
+        assert_eq!(
+            prefixed.header().fits_keywords(),
+            file.header().fits_keywords()
+        );
+        assert_eq!(prefixed.header().properties(), file.header().properties());
+        assert_eq!(
+            prefixed.header().display_function(),
+            file.header().display_function()
+        );
+        assert_eq!(prefixed.header().resolution(), file.header().resolution());
+        assert_eq!(prefixed.data(), file.data());
+    }
+
+    #[test]
+    fn test_xisf_read_xml_prolog() {
+        init();
+
+        // Test that XML comments and processing instructions, a byte order mark and
+        // padding after the root element (zeros, or non-text bytes up to a block boundary)
+        // don't change the conversion
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-namespace.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let cards = convert_fits_cards(xisf_filename);
+
+        for xisf_filename in &[
+            "tests/images/xisf-image-gray-8x8-8bits-prolog.xisf",
+            "tests/images/xisf-image-gray-8x8-8bits-bom.xisf",
+            "tests/images/xisf-image-gray-8x8-8bits-padding.xisf",
+            "tests/images/xisf-image-gray-8x8-8bits-padding-block.xisf",
+        ] {
+            let xisf_filename = Path::new(xisf_filename);
+            let variant = XISFile::read_file(xisf_filename).unwrap();
+            assert_eq!(variant.keywords(), file.keywords());
+            assert_eq!(variant.header().properties(), file.header().properties());
+            assert_eq!(variant.data(), file.data());
+            assert_eq!(convert_fits_cards(xisf_filename), cards);
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_complex() {
+        init();
+
+        // Test that complex data is written with the real and imaginary parts along
+        // an extra first axis
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-4x4-complex-32bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().sample_format(), XISFSampleFormat::Complex32);
+        match file.data() {
+            XISFData::Complex32(data) => {
+                assert_eq!(data[0].len(), 16);
+                assert_eq!(data[0][3], Complex32 { re: 1.5, im: -3.0 });
+            }
+            _ => panic!("unexpected data type"),
+        }
+
+        let (data_bytes, bitpix) = xisf_data_to_fits(&file, ByteOrder::Big);
+        assert_eq!(bitpix, -32);
+        assert_eq!(&data_bytes[24..32], &[0x3f, 0xc0, 0, 0, 0xc0, 0x40, 0, 0]);
+
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        let fits_hd = fits_header_data(&cli, &file, data_bytes, bitpix, 1);
+        assert_eq!(fits_hd.naxis, 3);
+        assert_eq!(fits_hd.naxis_vec, vec![2, 4, 4]);
+        assert!(fits_hd.comment.contains(&COMPLEX_LAYOUT_COMMENT.to_owned()));
+        assert!(stream_bitpix(&cli, &file).is_none());
+    }
+
+    #[test]
+    fn test_xisf_read_sample_format() {
+        init();
+
+        // Test that sample formats are parsed ignoring case
+        assert_eq!("uint16".parse(), Ok(XISFSampleFormat::UInt16));
+        assert_eq!("FLOAT32".parse(), Ok(XISFSampleFormat::Float32));
+        assert_eq!("complex64".parse(), Ok(XISFSampleFormat::Complex64));
+        assert_eq!(
+            "xyz".parse::<XISFSampleFormat>(),
+            Err(SampleFormatParseError {
+                format: "xyz".to_owned()
+            })
+        );
+
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-lowercase-format.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().sample_format(), XISFSampleFormat::UInt8);
+
+        // Unknown sample formats are an error
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-invalid-format.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(e @ XisfError::InvalidSampleFormat { .. }) => assert_eq!(
+                e.to_string(),
+                "unsupported sample format 'xyz' at Image element"
+            ),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // As well as images without sample format, or files without images
+        for xisf_filename in &[
+            "tests/images/xisf-image-gray-8x8-no-format.xisf",
+            "tests/images/xisf-no-image.xisf",
+        ] {
+            match XISFile::read_file(Path::new(xisf_filename)) {
+                Err(XisfError::MissingSampleFormat) => {}
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_geometry() {
+        init();
+
+        // Test that geometries are parsed, and malformed ones are errors
+        let geometry = XISFGeometry::try_from(&b"256:128:3"[..]).unwrap();
+        assert_eq!(geometry.dimensions(), &[256, 128]);
+        assert_eq!(geometry.channel_count(), 3);
+        // Zero dimensions or channel counts and missing channel counts are invalid
+        for geometry in &[
+            "", "8", "8:x:1", "8:8:", "8:0:1", "0:8:1", "8:8:0", "8::1", "-8:8:1",
+        ] {
+            match XISFGeometry::try_from(geometry.as_bytes()) {
+                Err(XisfError::InvalidGeometry { geometry: found }) => {
+                    assert_eq!(&found, geometry);
+                }
+                other => panic!("unexpected result for {:?}: {:?}", geometry, other),
+            }
+        }
+
+        // The number of samples must fit in a usize
+        let geometry = format!("{}:2:1", usize::MAX);
+        match XISFGeometry::try_from(geometry.as_bytes()) {
+            Err(XisfError::GeometryTooLarge { geometry: found }) => assert_eq!(found, geometry),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let geometry = XISFGeometry::try_from(format!("{}:1", usize::MAX / 2).as_bytes()).unwrap();
+        assert_eq!(geometry.checked_size(1), Some(usize::MAX / 2));
+        assert_eq!(geometry.checked_size(4), None);
+
+        // So must the size in bytes of the image data
+        let image = |attributes: &str| {
+            let xml = format!("<xisf version=\"1.0\"><Image {}/></xisf>", attributes);
+            read_xisf_xml(xml.as_bytes())
+        };
+        let geometry = format!("{}:1", usize::MAX / 2);
+        match image(&format!(
+            "geometry=\"{}\" sampleFormat=\"Float32\"",
+            geometry
+        )) {
+            Err(XisfError::GeometryTooLarge { geometry: found }) => assert_eq!(found, geometry),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let geometry = format!("{}:3:1", usize::MAX / 2);
+        match image(&format!("geometry=\"{}\" sampleFormat=\"UInt8\"", geometry)) {
+            Err(XisfError::GeometryTooLarge { geometry: found }) => assert_eq!(found, geometry),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        // Images without geometry are errors too
+        match image("sampleFormat=\"UInt8\"") {
+            Err(XisfError::MissingGeometry) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-invalid-geometry.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::Parse(XisfParseError::Geometry {
+                element,
+                attribute,
+                value,
+            })) => assert_eq!(
+                (&*element, &*attribute, &*value),
+                ("Image", "geometry", "8:x:1")
+            ),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // A 1-D geometry with the channels of its color space is valid (e.g. a spectrum),
+        // but "256:256" most likely lacks its channel count: an error in strict mode, as
+        // image data that doesn't have the size of the geometry
+        let read = |geometry: &str, length: usize, parse_mode| {
+            let xml = format!(
+                r#"<xisf version="1.0"><Image geometry="{}" sampleFormat="UInt8" colorSpace="Gray" location="attachment:4096:{}"/></xisf>"#,
+                geometry, length
+            );
+            let mut xisf = b"XISF0100".to_vec();
+            xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+            xisf.extend_from_slice(&[0; 4]);
+            xisf.extend_from_slice(xml.as_bytes());
+            xisf.resize(4096 + length, 0);
+            let options = XisfReadOptions {
+                parse_mode,
+                ..XisfReadOptions::default()
+            };
+            XISFile::read_from_with_options(Cursor::new(xisf), options)
+        };
+        let file = read("4096:1", 4096, ParseMode::Normal).unwrap();
+        assert!(file.header().check_geometry().is_ok());
+        match read("4096:1", 4096, ParseMode::Strict) {
+            Err(XisfError::MissingChecksum) => {}
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let file = read("256:256", 65536, ParseMode::Normal).unwrap();
+        assert_eq!(file.header().geometry().channel_count(), 256);
+        match read("256:256", 65536, ParseMode::Strict) {
+            Err(XisfError::InvalidGeometry { geometry }) => assert_eq!(geometry, "256:256"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        match read("16:16:1", 512, ParseMode::Strict) {
+            Err(XisfError::DataSizeMismatch { length, expected }) => {
+                assert_eq!((length, expected), (512, 256));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // The FITS axis sizes don't include the channel count
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().geometry_sizes(), vec![256, 256]);
+        assert_eq!(file.header().geometry_channels(), 3);
+        // The channels are the last FITS axis, unless they are written one by one
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        let fits_hd = fits_header_data(&cli, &file, Box::new([]), 8, 3);
+        assert_eq!(fits_hd.naxis_vec, vec![256, 256, 3]);
+        let fits_hd = fits_header_data(&cli, &file, Box::new([]), 8, 1);
+        assert_eq!(fits_hd.naxis_vec, vec![256, 256]);
+        let fits = xisfits::convert_xisf_to_fits(&fs::read(xisf_filename).unwrap()).unwrap();
+        let (_, values) = read_fits_values(&fits);
+        assert_eq!(values, xisf_values(file.data()));
+    }
+
+    #[test]
+    fn test_convert_raw_output() {
+        init();
+
+        // Test that raw output is the FITS data unit, without header nor padding
+        let cli = Cli::from_iter(&["xisfits", "--output-format", "raw", "in.xisf", "out.fits"]);
+        assert!(cli.raw());
+        assert_eq!(cli.output_format(), None);
+        assert_eq!(cli.raw_output(), Path::new("out.raw"));
+        assert_eq!(cli.rawinfo_output(), Path::new("out.rawinfo"));
+        let cli = Cli::from_iter(&["xisfits", "--output-format", "raw", "in.xisf", "out.bin"]);
+        assert_eq!(cli.raw_output(), Path::new("out.bin"));
+        assert_eq!(cli.rawinfo_output(), Path::new("out.rawinfo"));
+
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+        let output = std::env::temp_dir().join("xisfits-test-raw.fits");
+        let cli = Cli::from_iter(&[
+            "xisfits",
+            "--output-format",
+            "raw",
+            xisf_filename.to_str().unwrap(),
+            output.to_str().unwrap(),
+        ]);
+        run(&cli).unwrap();
+        assert!(!output.exists());
+        let raw = fs::read(cli.raw_output()).unwrap();
+        let raw_info = fs::read_to_string(cli.rawinfo_output()).unwrap();
+        fs::remove_file(cli.raw_output()).unwrap();
+        fs::remove_file(cli.rawinfo_output()).unwrap();
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let (data, _) = xisf_data_to_fits(&file, ByteOrder::Big);
+        assert_eq!(&raw[..], &data[..]);
+        assert_eq!(
+            raw_info,
+            "{\n  \"sample_type\": \"int16\",\n  \"bzero\": 32768,\n  \"byte_order\": \"big\",\n  \
+             \"axes\": [256, 256, 3],\n  \"channel_count\": 3,\n  \"interleaved\": false\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_convert_output_format() {
+        init();
+
+        let cli = Cli::from_iter(&["xisfits", "--output-format", "f32", "in.xisf", "out.fits"]);
+        assert_eq!(cli.output_format(), Some(OutputFormat::F32));
+        assert_eq!(OutputFormat::I16.bitpix(), 16);
+        assert!("u16".parse::<OutputFormat>().is_err());
+
+        // Test that UInt16 values are kept as they are in floating point
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-16bits-thumbnail.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let values = xisf_values(file.data());
+        let fits_data =
+            xisf_channels_to_fits_as(&file, ByteOrder::Big, 0..1, OutputFormat::F32).unwrap();
+        let floats: Vec<f64> = fits_data
+            .chunks_exact(4)
+            .map(|bytes| {
+                f64::from(f32::from_bits(u32::from_be_bytes(
+                    bytes.try_into().unwrap(),
+                )))
+            })
+            .collect();
+        assert_eq!(floats, values);
+
+        // Narrowing integer conversions saturate
+        let fits_data =
+            xisf_channels_to_fits_as(&file, ByteOrder::Little, 0..1, OutputFormat::I16).unwrap();
+        let integers: Vec<i16> = fits_data
+            .chunks_exact(2)
+            .map(|bytes| i16::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        assert_eq!(integers.len(), 64);
+        assert_eq!(integers[63], i16::max_value());
+        for (integer, value) in integers.iter().zip(&values) {
+            assert_eq!(f64::from(*integer), value.min(32767.0));
+        }
+
+        // Float values are rounded to integers
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-float-32bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let fits_data =
+            xisf_channels_to_fits_as(&file, ByteOrder::Big, 0..1, OutputFormat::I32).unwrap();
+        let integers: Vec<f64> = fits_data
+            .chunks_exact(4)
+            .map(|bytes| f64::from(i32::from_be_bytes(bytes.try_into().unwrap())))
+            .collect();
+        let values: Vec<f64> = xisf_values(file.data()).iter().map(|v| v.round()).collect();
+        assert_eq!(integers, values);
+
+        // Complex images keep their format
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-4x4-complex-32bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(xisf_channels_to_fits_as(&file, ByteOrder::Big, 0..1, OutputFormat::F64).is_none());
+    }
+
+    #[test]
+    fn test_xisf_data_channel_stats() {
+        init();
+
+        // Test the statistics of each channel
+        let data = XISFData::UInt16(
+            vec![
+                vec![4, 1, 3, 2].into_boxed_slice(),
+                vec![7, 7, 1].into_boxed_slice(),
+            ]
+            .into_boxed_slice(),
+        );
+        let stats = data.channel_stats();
+        assert_eq!(
+            stats,
+            vec![
+                Some(XISFChannelStats {
+                    min: 1.0,
+                    max: 4.0,
+                    mean: 2.5,
+                    median: 2.5,
+                    stddev: 1.25_f64.sqrt(),
+                }),
+                Some(XISFChannelStats {
+                    min: 1.0,
+                    max: 7.0,
+                    mean: 5.0,
+                    median: 7.0,
+                    stddev: 8.0_f64.sqrt(),
+                }),
+            ]
+        );
+        assert_eq!(
+            stats[0].unwrap().to_string(),
+            "min 1, max 4, mean 2.5000, median 2.5000, stddev 1.1180"
+        );
+
+        // NaN samples are skipped, and channels without samples have no statistics
+        let data = XISFData::Float32(
+            vec![
+                vec![0.5, f32::NAN, 0.25].into_boxed_slice(),
+                vec![f32::NAN].into_boxed_slice(),
+            ]
+            .into_boxed_slice(),
+        );
+        let stats = data.channel_stats();
+        assert_eq!(
+            stats[0].map(|stats| (stats.min, stats.max)),
+            Some((0.25, 0.5))
+        );
+        assert_eq!(stats[1], None);
+
+        // Complex samples use their magnitude
+        let data = XISFData::Complex32(
+            vec![vec![Complex32 { re: 3.0, im: -4.0 }].into_boxed_slice()].into_boxed_slice(),
+        );
+        assert_eq!(data.channel_stats()[0].map(|stats| stats.max), Some(5.0));
+        assert!(XISFData::Empty.channel_stats().is_empty());
+    }
+
+    #[test]
+    fn test_xisf_read_unknown_attributes() {
+        init();
+
+        // Test that unrecognized Image attributes are collected
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-unknown-attributes.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let attributes: Vec<_> = file
+            .header()
+            .unknown_attributes()
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                ("pixelStorage", "Normal"),
+                ("orientation", "flip"),
+                ("imageFilter", "Ha & OIII"),
+            ]
+        );
+        // They are reported, and fail with --strict
+        match file.header().check_unknown_attributes() {
+            Err(e) => assert_eq!(
+                e.to_string(),
+                "3 unrecognized Image attributes: pixelStorage, orientation, imageFilter"
+            ),
+            Ok(()) => panic!("unknown attributes not reported"),
+        }
+
+        // Images with known attributes only
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-namespace.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.header().unknown_attributes().is_empty());
+        assert!(file.header().check_unknown_attributes().is_ok());
+    }
+
+    #[test]
+    fn test_xisf_read_color_space() {
+        init();
+
+        // Test that images without color space are grayscale
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-no-color-space.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().color_space(), XISFColorSpace::Gray);
+        assert!(file.header().check_color_space().is_ok());
+
+        // Color spaces matching the number of channels
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().color_space(), XISFColorSpace::Rgb);
+        assert!(file.header().check_color_space().is_ok());
+
+        // RGB images with a single channel are read (with a warning), but fail with --strict
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-rgb-mismatch.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().color_space(), XISFColorSpace::Rgb);
+        match file.header().check_color_space() {
+            Err(XisfError::ColorSpaceMismatch {
+                color_space,
+                channels,
+            }) => assert_eq!((color_space.as_str(), channels), ("RGB", 1)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let cli = Cli::from_iter(&["xisfits", "--strict", "in.xisf", "out.fits"]);
+        assert_eq!(cli.parse_mode(), ParseMode::Strict);
+    }
+
+    #[test]
+    fn test_xisf_read_signed_integers() {
+        init();
+
+        // Test that signed integers are written as FITS integers without BZERO (Int8 is
+        // widened to 16 bits, as FITS 8 bits integers are unsigned)
+        for (xisf_filename, sample_format, bitpix) in &[
+            ("xisf-image-gray-8x8-int8.xisf", XISFSampleFormat::Int8, 16),
+            (
+                "xisf-image-gray-8x8-int16.xisf",
+                XISFSampleFormat::Int16,
+                16,
+            ),
+            (
+                "xisf-image-gray-8x8-int32.xisf",
+                XISFSampleFormat::Int32,
+                32,
+            ),
+        ] {
+            let xisf_filename = Path::new("tests/images").join(xisf_filename);
+            let file = XISFile::read_file(&xisf_filename).unwrap();
+            assert_eq!(file.header().sample_format(), *sample_format);
+            assert_eq!(fits_bzero(*sample_format), 0);
+
+            let (data_bytes, fits_bitpix) = xisf_data_to_fits(&file, ByteOrder::Big);
+            assert_eq!(fits_bitpix, *bitpix);
+            assert_eq!(data_bytes.len(), 64 * usize::try_from(bitpix / 8).unwrap());
+        }
+
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-int8.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        match file.data() {
+            XISFData::Int8(data) => assert_eq!((data[0][0], data[0][63]), (-128, 124)),
+            _ => panic!("unexpected data type"),
+        }
+        let (data_bytes, _) = xisf_data_to_fits(&file, ByteOrder::Big);
+        assert_eq!(&data_bytes[..4], &[0xff, 0x80, 0xff, 0x84]);
+    }
+
+    #[test]
+    fn test_xisf_read_thumbnail() {
+        init();
+
+        // Test that the thumbnail is read and written as a FITS IMAGE extension
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-16bits-thumbnail.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let thumbnail = file.thumbnail().unwrap();
+        assert_eq!(thumbnail.geometry().to_string(), "4:4:1");
+        assert_eq!(thumbnail.sample_format(), XISFSampleFormat::UInt8);
+        assert_eq!(thumbnail.color_space(), XISFColorSpace::Gray);
+        match thumbnail.data() {
+            XISFData::UInt8(data) => assert_eq!(data[0][15], 150),
+            _ => panic!("unexpected thumbnail data type"),
+        }
+        // The main image is still read
+        match file.data() {
+            XISFData::UInt16(data) => assert_eq!(data[0][63], 63000),
+            _ => panic!("unexpected data type"),
+        }
+
+        let mut fits = Vec::new();
+        write_thumbnail(&mut fits, thumbnail, ByteOrder::Big, false).unwrap();
+        assert_eq!(fits.len(), 2 * 2880);
+        let cards: Vec<_> = fits[..2880]
+            .chunks(80)
+            .map(|card| String::from_utf8_lossy(card).trim_end().to_owned())
+            .collect();
+        assert_eq!(cards[0], "XTENSION= 'IMAGE   '           /");
+        assert_eq!(cards[1], "BITPIX  = 8                    /");
+        assert_eq!(cards[3], "NAXIS1  = 4                    /");
+        assert!(cards.contains(&"END".to_owned()));
+        assert_eq!(&fits[2880..2884], &[0, 10, 20, 30]);
+
+        // Files without thumbnail
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.thumbnail().is_none());
+    }
+
+    #[test]
+    fn test_xisf_stream_to_fits() {
+        init();
+
+        // Test that streaming the image data gives the same FITS data as converting it in memory
+        for xisf_filename in &[
+            "tests/images/xisf-image-gray-256x256-8bits.xisf",
+            "tests/images/xisf-image-rgb-256x256-16bits.xisf",
+            "tests/images/xisf-image-rgb-256x256-32bits.xisf",
+            "tests/images/xisf-image-gray-256x256-float-32bits.xisf",
+            "tests/images/xisf-image-gray-256x256-float-64bits.xisf",
+        ] {
+            let xisf_filename = Path::new(xisf_filename);
+            let header_file = XISFile::read_file_header(xisf_filename).unwrap();
+            let header = header_file.header();
+            match header_file.data() {
+                XISFData::Empty => {}
+                _ => panic!("image data read with the header"),
+            }
+
+            for byte_order in &[ByteOrder::Big, ByteOrder::Little] {
+                let mut xisf = BufReader::new(File::open(xisf_filename).unwrap());
+                let _ = xisf.seek(SeekFrom::Start(header.location_start())).unwrap();
+                let mut streamed = Vec::new();
+                let total = xisf_stream_to_fits(
+                    &mut xisf,
+                    &mut streamed,
+                    header.sample_format(),
+                    header.location_length(),
+                    *byte_order,
+                    &NullProgress,
+                )
+                .unwrap();
+
+                let file = XISFile::read_file(xisf_filename).unwrap();
+                let (fits_data, _) = xisf_data_to_fits(&file, *byte_order);
+                assert_eq!(total, fits_data.len() as u64);
+                assert_eq!(&streamed[..], &fits_data[..]);
+            }
+        }
+    }
+
+    /// Minimal FITS reader: gets the header cards and the physical values
+    /// (BZERO + BSCALE * stored value) of the primary data unit.
+    fn read_fits_values(fits: &[u8]) -> (Vec<String>, Vec<f64>) {
+        let cards: Vec<_> = fits
+            .chunks(80)
+            .map(|card| String::from_utf8_lossy(card).into_owned())
+            .take_while(|card| !card.starts_with("END "))
+            .collect();
+        let card_value = |name: &str| -> String {
+            let card = cards
+                .iter()
+                .find(|card| card[..8].trim_end() == name)
+                .unwrap_or_else(|| panic!("missing {} card", name));
+            card[10..].split('/').next().unwrap().trim().to_owned()
+        };
+        let bitpix: i64 = card_value("BITPIX").parse().unwrap();
+        let naxis: usize = card_value("NAXIS").parse().unwrap();
+        let samples: usize = (1..=naxis)
+            .map(|i| card_value(&format!("NAXIS{}", i)).parse::<usize>().unwrap())
+            .product();
+        let bzero: f64 = card_value("BZERO").parse().unwrap();
+        let bscale: f64 = card_value("BSCALE").parse().unwrap();
+
+        // The data unit starts in the 2880 byte-block after the END card
+        let header_size = (cards.len() + 1) * 80;
+        let data_start = header_size + (2880 - header_size % 2880) % 2880;
+        let sample_size = usize::try_from(bitpix.abs() / 8).unwrap();
+        let data = &fits[data_start..data_start + samples * sample_size];
+        let values = data
+            .chunks_exact(sample_size)
+            .map(|bytes| {
+                let value = match bitpix {
+                    8 => f64::from(bytes[0]),
+                    16 => f64::from(i16::from_be_bytes(bytes.try_into().unwrap())),
+                    32 => f64::from(i32::from_be_bytes(bytes.try_into().unwrap())),
+                    -32 => f64::from(f32::from_bits(u32::from_be_bytes(
+                        bytes.try_into().unwrap(),
+                    ))),
+                    -64 => f64::from_bits(u64::from_be_bytes(bytes.try_into().unwrap())),
+                    _ => panic!("unexpected BITPIX {}", bitpix),
+                };
+                bzero + bscale * value
+            })
+            .collect();
+        (cards, values)
+    }
+
+    /// Gets the samples of XISF image data as f64, channel after channel.
+    fn xisf_values(data: &XISFData) -> Vec<f64> {
+        match data {
+            XISFData::UInt8(data) => data.iter().flatten().map(|v| f64::from(*v)).collect(),
+            XISFData::UInt16(data) => data.iter().flatten().map(|v| f64::from(*v)).collect(),
+            XISFData::UInt32(data) => data.iter().flatten().map(|v| f64::from(*v)).collect(),
+            XISFData::Int8(data) => data.iter().flatten().map(|v| f64::from(*v)).collect(),
+            XISFData::Int16(data) => data.iter().flatten().map(|v| f64::from(*v)).collect(),
+            XISFData::Int32(data) => data.iter().flatten().map(|v| f64::from(*v)).collect(),
+            XISFData::Float32(data) => data.iter().flatten().map(|v| f64::from(*v)).collect(),
+            XISFData::Float64(data) => data.iter().flatten().copied().collect(),
+            XISFData::Complex32(data) => data
+                .iter()
+                .flatten()
+                .flat_map(|v| vec![f64::from(v.re), f64::from(v.im)])
+                .collect(),
+            XISFData::Complex64(data) => data
+                .iter()
+                .flatten()
+                .flat_map(|v| vec![v.re, v.im])
+                .collect(),
+            XISFData::Empty => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_conversion_strategy() {
+        init();
+
+        // Test that the conversion fits in --max-memory, with synthetic headers (the image
+        // data isn't read, nor is there any)
+        let image = |attributes: &str| {
+            let xml = format!("<xisf version=\"1.0\"><Image {}/></xisf>", attributes);
+            let mut xisf = b"XISF0100".to_vec();
+            xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+            xisf.extend_from_slice(&[0; 4]);
+            xisf.extend_from_slice(xml.as_bytes());
+            let options = XisfReadOptions {
+                read_data: false,
+                parse_mode: ParseMode::Lenient,
+                ..XisfReadOptions::default()
+            };
+            XISFile::read_from_with_options(Cursor::new(xisf), options).unwrap()
+        };
+        let cli = |args: &[&str]| {
+            let mut cli_args = vec!["xisfits"];
+            cli_args.extend_from_slice(args);
+            cli_args.extend_from_slice(&["in.xisf", "out.fits"]);
+            Cli::from_iter(&cli_args)
+        };
+        let float = image(
+            "geometry=\"2000:2000:3\" sampleFormat=\"Float32\" bounds=\"0:1\" \
+             location=\"attachment:4096:48000000\"",
+        );
+        let cli_max = cli(&["--max-memory", "4000000"]);
+        assert_eq!(cli_max.max_memory(), Some(4_000_000));
+        assert_eq!(
+            conversion_strategy(&cli_max, &float).unwrap(),
+            ConversionStrategy::Streaming
+        );
+
+        // Rescaled to 16 bits, the FITS data is half the size of the samples: if the whole
+        // image data doesn't fit, it's read channel by channel
+        let estimate =
+            |strategy| memory_estimate(&cli(&["--apply-bounds"]), float.header(), strategy);
+        assert_eq!(estimate(ConversionStrategy::InMemory), 96_000_000);
+        assert_eq!(estimate(ConversionStrategy::ChannelByChannel), 72_000_000);
+        for &(max_memory, strategy) in &[
+            ("100000000", ConversionStrategy::InMemory),
+            ("80000000", ConversionStrategy::ChannelByChannel),
+        ] {
+            let cli = cli(&["--apply-bounds", "--max-memory", max_memory]);
+            assert_eq!(conversion_strategy(&cli, &float).unwrap(), strategy);
+        }
+        // Otherwise, the conversion fails before reading the image data
+        let cli_max = cli(&["--apply-bounds", "--max-memory", "50000000"]);
+        let e = conversion_strategy(&cli_max, &float).unwrap_err();
+        assert!(e.to_string().contains("about 72000000 bytes"), "{}", e);
+
+        // Compressed image data is decompressed in memory
+        let compressed = image(
+            "geometry=\"1000:1000:1\" sampleFormat=\"UInt16\" compression=\"zlib:2000000\" \
+             location=\"attachment:4096:500000\"",
+        );
+        let estimate = |args| {
+            memory_estimate(
+                &cli(args),
+                compressed.header(),
+                ConversionStrategy::InMemory,
+            )
+        };
+        assert_eq!(estimate(&[]), 4_000_000);
+        assert_eq!(estimate(&["--output-format", "f64"]), 10_000_000);
+        let cli_max = cli(&["--max-memory", "5000000"]);
+        assert_eq!(
+            conversion_strategy(&cli_max, &compressed).unwrap(),
+            ConversionStrategy::InMemory
+        );
+        let cli_max = cli(&["--max-memory", "3000000"]);
+        let e = conversion_strategy(&cli_max, &compressed).unwrap_err();
+        assert!(e.to_string().contains("can't be streamed"), "{}", e);
+    }
+
+    #[test]
+    fn test_xisf_fits_round_trip() {
+        init();
+
+        // Test that the physical values of the FITS data are the XISF samples, both
+        // converting the image in memory and streaming it
+        let fits_filename = std::env::temp_dir().join("xisfits-test-round-trip.fits");
+        for xisf_filename in &[
+            "tests/images/xisf-image-gray-256x256-8bits.xisf",
+            "tests/images/xisf-image-gray-8x8-16bits-thumbnail.xisf",
+            "tests/images/xisf-image-gray-256x256-16bits-zlib_sh.xisf",
+            "tests/images/xisf-image-gray-256x256-float-32bits.xisf",
+            "tests/images/xisf-image-gray-4x4-complex-32bits.xisf",
+            "tests/images/xisf-image-gray-8x8-int8.xisf",
+            "tests/images/xisf-image-gray-8x8-int16.xisf",
+            "tests/images/xisf-image-gray-8x8-int32.xisf",
+            // With FITS keywords (the second one describing the structure of the data)
+            "tests/images/xisf-image-gray-8x8-8bits-bom.xisf",
+            "tests/images/xisf-image-gray-8x8-16bits-keywords.xisf",
+        ] {
+            let cli = Cli::from_iter(&["xisfits", xisf_filename, fits_filename.to_str().unwrap()]);
+            let file = XISFile::read_file(cli.input()).unwrap();
+            let expected = xisf_values(file.data());
+            assert!(!expected.is_empty());
+            let keywords: Vec<_> = file
+                .keywords()
+                .iter()
+                .filter(|keyword| keyword.name == "TELESCOP")
+                .collect();
+            assert_eq!(keywords.is_empty(), file.keywords().is_empty());
+            let check_cards = |cards: &[String], name| {
+                assert!(cards[0].starts_with("SIMPLE  ="), "{}", name);
+                for keyword in &["BITPIX", "NAXIS", "BZERO"] {
+                    let count = cards
+                        .iter()
+                        .filter(|card| card[..8].trim_end() == *keyword)
+                        .count();
+                    assert_eq!(count, 1, "{}: {}", name, keyword);
+                }
+                for keyword in &keywords {
+                    assert!(cards
+                        .iter()
+                        .any(|card| card.starts_with(&format!("TELESCOP= {}", keyword.value))));
+                }
+            };
+
+            convert_fits(&cli, &file, &NullProgress).unwrap();
+            let (cards, values) = read_fits_values(&fs::read(&fits_filename).unwrap());
+            assert_eq!(values, expected, "{}", xisf_filename);
+            check_cards(&cards, xisf_filename);
+
+            if let Some(bitpix) = stream_bitpix(&cli, &file) {
+                stream_fits(&cli, &file, bitpix, &NullProgress).unwrap();
+                let (cards, values) = read_fits_values(&fs::read(&fits_filename).unwrap());
+                assert_eq!(values, expected, "{} (streamed)", xisf_filename);
+                check_cards(&cards, xisf_filename);
+            }
+        }
+
+        // The pixel values of the byte shuffled image are the ones of its unshuffled data
+        // (and not only the ones read back by this reader)
+        let xisf_filename = "tests/images/xisf-image-gray-256x256-16bits-zlib_sh.xisf";
+        let cli = Cli::from_iter(&["xisfits", xisf_filename, fits_filename.to_str().unwrap()]);
+        let file = XISFile::read_file(cli.input()).unwrap();
+        convert_fits(&cli, &file, &NullProgress).unwrap();
+        let (_, values) = read_fits_values(&fs::read(&fits_filename).unwrap());
+        assert_eq!(
+            &values[..8],
+            &[1520.0, 1568.0, 1392.0, 1696.0, 1552.0, 1680.0, 1632.0, 1552.0]
+        );
+        fs::remove_file(&fits_filename).unwrap();
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_xisf_read_async() {
+        init();
+
+        // Test that images read asynchronously are the same as read synchronously
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for xisf_filename in &[
+            "tests/images/xisf-image-gray-256x256-16bits-zlib_sh.xisf",
+            "tests/images/xisf-image-rgb-256x256-16bits.xisf",
+        ] {
+            let xisf_filename = Path::new(xisf_filename);
+            let file = XISFile::read_file(xisf_filename).unwrap();
+            let xisf: Vec<u8> = fs::read(xisf_filename).unwrap();
+            let read = runtime
+                .block_on(XISFile::read_from_async(Cursor::new(xisf)))
+                .unwrap();
+            assert_eq!(read.data(), file.data());
+            assert_eq!(read.keywords(), file.keywords());
+            let read = runtime
+                .block_on(XISFile::read_file_async(xisf_filename))
+                .unwrap();
+            assert_eq!(read.data(), file.data());
+        }
+
+        // As for read_file, errors are reported
+        let result = runtime.block_on(XISFile::read_from_async(Cursor::new(b"XISF0100")));
+        match result {
+            Err(XisfError::TooShort { size }) => assert_eq!(size, 8),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-truncated-xml.xisf");
+        let result = runtime.block_on(XISFile::read_file_async(xisf_filename));
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().to_string(),
+            XISFile::read_file(xisf_filename)
+                .map(|_| ())
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_json_meta() {
+        init();
+
+        // Test that the header, FITS keywords and properties are written as JSON
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-master-dark-keyword.xisf");
+        let output = std::env::temp_dir().join("xisfits-test-json-meta.json");
+        let cli = Cli::from_iter(&[
+            "xisfits",
+            "--json-meta",
+            output.to_str().unwrap(),
+            "--dry-run",
+            xisf_filename.to_str().unwrap(),
+            "out.fits",
+        ]);
+        assert_eq!(cli.json_meta(), Some(output.as_path()));
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        write_json_meta(&cli, &file, &output).unwrap();
+        assert!(!output.exists());
+        let cli = Cli::from_iter(&[
+            "xisfits",
+            "--json-meta",
+            output.to_str().unwrap(),
+            xisf_filename.to_str().unwrap(),
+            "out.fits",
+        ]);
+        write_json_meta(&cli, &file, &output).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&output).unwrap()).unwrap();
+        fs::remove_file(&output).unwrap();
+
+        let header = &json["header"];
+        assert_eq!(header["version"], "1.0");
+        assert_eq!(header["geometry"]["dimensions"], serde_json::json!([8, 8]));
+        assert_eq!(header["geometry"]["channel_count"], 1);
+        assert_eq!(header["sample_format"], "UInt8");
+        assert_eq!(header["color_space"], "Gray");
+        assert_eq!(header["image_type"], file.header().image_type().to_string());
+        assert_eq!(header["location"], "attachment");
+        assert!(header["compression"].is_null());
+        let keywords = json["keywords"].as_array().unwrap();
+        assert_eq!(keywords.len(), file.keywords().len());
+        for (keyword, expected) in keywords.iter().zip(file.keywords()) {
+            assert_eq!(keyword["name"], expected.name.as_str());
+            assert_eq!(keyword["value"], expected.value.as_str());
+            assert_eq!(keyword["comment"], expected.comment.as_str());
+        }
+        let properties = json["properties"].as_array().unwrap();
+        assert_eq!(properties.len(), file.header().properties().len());
+        for (property, expected) in properties.iter().zip(file.header().properties()) {
+            assert_eq!(property["id"], expected.id());
+            assert_eq!(property["type"], expected.property_type());
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_from_cursor() {
+        init();
+
+        // Test that images read from a buffer in memory are the same as read from the file
+        for xisf_filename in &[
+            "tests/images/xisf-image-gray-256x256-8bits.xisf",
+            "tests/images/xisf-image-gray-256x256-16bits-zlib_sh.xisf",
+            "tests/images/xisf-image-rgb-256x256-16bits.xisf",
+            "tests/images/xisf-image-gray-8x8-16bits-thumbnail.xisf",
+        ] {
+            let xisf_filename = Path::new(xisf_filename);
+            let file = XISFile::read_file(xisf_filename).unwrap();
+            let xisf: Vec<u8> = fs::read(xisf_filename).unwrap();
+            let read = XISFile::read_from(Cursor::new(xisf)).unwrap();
+            assert_eq!(read.data(), file.data());
+            assert_eq!(read.keywords(), file.keywords());
+            assert_eq!(
+                format!("{:?}", read.header()),
+                format!("{:?}", file.header())
+            );
+            assert_eq!(
+                read.thumbnail().map(XISFImage::data),
+                file.thumbnail().map(XISFImage::data)
+            );
+        }
+    }
+
+    #[test]
+    fn test_xisf_from_bytes() {
+        init();
+
+        // Test that every monolithic image read from memory is the same as read from the file
+        let mut xisf_filenames: Vec<_> = fs::read_dir("tests/images")
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "xisf")
+            })
+            .collect();
+        xisf_filenames.sort();
+        assert!(!xisf_filenames.is_empty());
+        for xisf_filename in &xisf_filenames {
+            let xisf = fs::read(xisf_filename).unwrap();
+            if let Ok(header) = XISFile::read_file_header(xisf_filename) {
+                // Data stored in other files isn't read from memory
+                let location_method = header.header().location_method();
+                if location_method == "url" || location_method == "path" {
+                    match XISFile::from_bytes(&xisf) {
+                        Err(XisfError::ExternalDataDisabled { .. }) => continue,
+                        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+                    }
+                }
+            }
+            let file = XISFile::read_file(xisf_filename);
+            match (file, XISFile::from_bytes(&xisf)) {
+                (Ok(file), Ok(read)) => {
+                    assert_eq!(read.data(), file.data(), "{}", xisf_filename.display());
+                    assert_eq!(read.keywords(), file.keywords());
+                    assert_eq!(
+                        format!("{:?}", read.header()),
+                        format!("{:?}", file.header())
+                    );
+                }
+                (Err(e), Err(read_error)) => assert_eq!(read_error.to_string(), e.to_string()),
+                (file, read) => panic!(
+                    "{}: {:?} and {:?}",
+                    xisf_filename.display(),
+                    file.map(|_| ()),
+                    read.map(|_| ())
+                ),
+            }
+        }
+
+        // Buffers without the signature and header length are too short
+        match XISFile::from_bytes(b"XISF0100\x10\0\0\0") {
+            Err(XisfError::TooShort { size }) => assert_eq!(size, 12),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_fits_pixel_values() {
+        init();
+
+        // Test the pixels of the FITS data unit against reference values of the images,
+        // as (x, y, value), parsing the FITS bytes by hand
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        for (xisf_filename, pixels) in &[
+            (
+                "tests/images/xisf-image-gray-256x256-8bits.xisf",
+                [(0, 0, 255), (255, 0, 255), (128, 128, 255), (255, 255, 255)],
+            ),
+            (
+                "tests/images/xisf-image-gray-256x256-16bits-zlib.xisf",
+                [(0, 0, 74), (255, 0, 70), (10, 200, 73), (255, 255, 73)],
+            ),
+        ] {
+            let file = XISFile::read_file(Path::new(xisf_filename)).unwrap();
+            let (data_bytes, bitpix) = xisf_data_to_fits(&file, ByteOrder::Big);
+            let fits_hd = fits_header_data(&cli, &file, data_bytes, bitpix, 2);
+            let mut fits = Vec::new();
+            fitswriter::fits_write_data_to(&mut fits, &fits_hd).unwrap();
+
+            // The data unit starts in the 2880 byte-block after the END card
+            let end = fits
+                .chunks(80)
+                .position(|card| card.starts_with(b"END "))
+                .unwrap();
+            let data = &fits[((end + 1) * 80 + 2879) / 2880 * 2880..];
+            for &(x, y, value) in pixels {
+                let i = y * 256 + x;
+                let pixel = match bitpix {
+                    8 => i64::from(data[i]),
+                    // Unsigned 16 bits are stored as signed, with BZERO = 32768
+                    16 => i64::from(i16::from_be_bytes([data[2 * i], data[2 * i + 1]])) + 32768,
+                    _ => panic!("unexpected BITPIX {}", bitpix),
+                };
+                assert_eq!(pixel, value, "{} ({}, {})", xisf_filename, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_convert_xisf_to_fits_in_memory() {
+        init();
+
+        // Test that XISF images in memory are converted to FITS files in memory
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-16bits-zlib.xisf");
+        let xisf = fs::read(xisf_filename).unwrap();
+        let fits = xisfits::convert_xisf_to_fits(&xisf).unwrap();
+        assert!(fits.starts_with(b"SIMPLE  ="));
+        assert_eq!(fits.len() % 2880, 0);
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let (_, values) = read_fits_values(&fits);
+        assert_eq!(values, xisf_values(file.data()));
+
+        // Invalid images are errors
+        match xisfits::convert_xisf_to_fits(b"SIMPLE  = T") {
+            Err(XisfError::TooShort { size }) => assert_eq!(size, 11),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    /// Records the steps reported by a conversion.
+    #[derive(Default)]
+    struct RecordProgress(std::cell::RefCell<Vec<(ConversionStep, u64, u64)>>);
+
+    impl ConversionProgress for RecordProgress {
+        fn on_step(&self, step: ConversionStep, bytes_done: u64, bytes_total: u64) {
+            self.0.borrow_mut().push((step, bytes_done, bytes_total));
+        }
+    }
+
+    #[test]
+    fn test_convert_xisf_progress() {
+        init();
+
+        // Test that each step of the conversion is reported
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-16bits-zlib.xisf");
+        let xisf = fs::read(xisf_filename).unwrap();
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let progress = RecordProgress::default();
+        let fits = xisfits::convert_xisf_to_fits_with_progress(&xisf, Some(&progress)).unwrap();
+        assert_eq!(fits, xisfits::convert_xisf_to_fits(&xisf).unwrap());
+        let header = file.header();
+        assert_eq!(
+            progress.0.into_inner(),
+            vec![
+                (
+                    ConversionStep::ReadHeader,
+                    16 + u64::from(header.length()),
+                    xisf.len() as u64
+                ),
+                (
+                    ConversionStep::ReadData,
+                    header.location_length(),
+                    header.location_length()
+                ),
+                (
+                    ConversionStep::Decompress,
+                    header.location_length(),
+                    2 * 256 * 256
+                ),
+                (ConversionStep::ConvertData, 2 * 256 * 256, 2 * 256 * 256),
+                (
+                    ConversionStep::WriteFits,
+                    fits.len() as u64,
+                    fits.len() as u64
+                ),
+            ]
+        );
+
+        // Without a progress receiver, nothing is reported
+        let progress = NullProgress;
+        progress.on_step(ConversionStep::ReadHeader, 0, 0);
+        assert!(xisfits::convert_xisf_to_fits_with_progress(&xisf, None).is_ok());
+
+        // Test that the image data read channel by channel is reported after each channel
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+        let options = XisfReadOptions {
+            channel_streaming_threshold: 0,
+            ..XisfReadOptions::default()
+        };
+        let progress = RecordProgress::default();
+        let _ = XISFile::read_file_with_progress(xisf_filename, options, &progress).unwrap();
+        let channel_size = 2 * 256 * 256;
+        let steps: Vec<_> = progress
+            .0
+            .into_inner()
+            .into_iter()
+            .filter(|(step, ..)| *step == ConversionStep::ReadData)
+            .collect();
+        assert_eq!(
+            steps,
+            (1..=3)
+                .map(|channels| {
+                    let done = channels * channel_size;
+                    (ConversionStep::ReadData, done, 3 * channel_size)
+                })
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(ConversionStep::ReadData.to_string(), "read data");
+
+        // Errors are returned to `main` (which clears the progress bar before reporting
+        // them), instead of exiting while the progress is shown
+        let fits_filename = std::env::temp_dir().join("xisfits-test-progress-error.fits");
+        let output = fits_filename.to_str().unwrap();
+        let cli = Cli::from_iter(&["xisfits", "tests/images/missing.xisf", output]);
+        let progress = RecordProgress::default();
+        match run_with_progress(&cli, &progress) {
+            Err(XisfError::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::NotFound),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        let xisf_filename = "tests/images/xisf-image-gray-8x8-8bits-checksum-mismatch.xisf";
+        let cli = Cli::from_iter(&["xisfits", xisf_filename, output]);
+        match run_with_progress(&cli, &progress) {
+            Err(XisfError::ChecksumMismatch { .. }) => {}
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!fits_filename.exists());
+    }
+
+    #[test]
+    fn test_convert_xisf_datacube() {
+        init();
+
+        // Test that a 3D spectral cube keeps its 3 spatial dimensions
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-100x100x50-8bits-cube.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().geometry().dimensions(), &[100, 100, 50]);
+        assert_eq!(file.header().geometry().channel_count(), 1);
+        assert_eq!(file.header().geometry_sizes(), vec![100, 100, 50]);
+        assert_eq!(file.header().geometry_channels(), 1);
+
+        let fits = xisfits::convert_xisf_to_fits(&fs::read(xisf_filename).unwrap()).unwrap();
+        let (cards, values) = read_fits_values(&fits);
+        for (name, value) in &[
+            ("NAXIS", 3),
+            ("NAXIS1", 100),
+            ("NAXIS2", 100),
+            ("NAXIS3", 50),
+        ] {
+            let card = format!("{:8}= {:<20} /", name, value);
+            assert!(
+                cards.iter().any(|c| c.starts_with(&card)),
+                "missing {}",
+                card
+            );
+        }
+        // NAXIS1 varies fastest, then NAXIS2, then NAXIS3
+        assert_eq!(values.len(), 100 * 100 * 50);
+        assert_eq!(values, xisf_values(file.data()));
+        assert_eq!(
+            values[100 * 100 * 49 + 100 * 2 + 3],
+            f64::from((49 * 5 + 2 + 3) % 256)
+        );
+
+        // Test that the channels of cubes are the last axis, after the spatial ones
+        for (name, channels, naxis) in &[
+            ("gray", 1, &[64, 64, 10][..]),
+            ("rgb", 3, &[64, 64, 10, 3][..]),
+        ] {
+            let xisf_filename =
+                format!("tests/images/xisf-image-{}-64x64x10-8bits-cube.xisf", name);
+            let xisf_filename = Path::new(&xisf_filename);
+            let file = XISFile::read_file(xisf_filename).unwrap();
+            assert_eq!(file.header().geometry().dimensions(), &[64, 64, 10]);
+            assert_eq!(file.header().geometry().channel_count(), *channels);
+            assert_eq!(
+                xisfits::fits_axes(&[64, 64, 10], *channels, XISFSampleFormat::UInt8),
+                naxis.to_vec()
+            );
+
+            let fits = xisfits::convert_xisf_to_fits(&fs::read(xisf_filename).unwrap()).unwrap();
+            let (cards, values) = read_fits_values(&fits);
+            let card = format!("{:8}= {:<20} /", "NAXIS", naxis.len());
+            assert!(
+                cards.iter().any(|c| c.starts_with(&card)),
+                "missing {}",
+                card
+            );
+            for (axis, size) in naxis.iter().enumerate() {
+                let card = format!("{:8}= {:<20} /", format!("NAXIS{}", axis + 1), size);
+                assert!(
+                    cards.iter().any(|c| c.starts_with(&card)),
+                    "missing {}",
+                    card
+                );
+            }
+            let card = format!("NAXIS{} ", naxis.len() + 1);
+            assert!(!cards.iter().any(|c| c.starts_with(&card)));
+            assert_eq!(values.len(), 64 * 64 * 10 * channels);
+            // Pixel (x, y, z) of channel c is (x + 2 y + 3 z + 50 c) % 256
+            let (x, y, z, c) = (5, 7, 9, channels - 1);
+            assert_eq!(
+                values[((c * 10 + z) * 64 + y) * 64 + x],
+                f64::from(u8::try_from((x + 2 * y + 3 * z + 50 * c) % 256).unwrap())
+            );
+        }
+
+        // Complex data has an extra first axis
+        assert_eq!(
+            xisfits::fits_axes(&[64, 64, 10, 2], 3, XISFSampleFormat::Complex32),
+            vec![2, 64, 64, 10, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_convert_xisf_spectrum() {
+        init();
+
+        // Test that a 1-D image (a spectrum) is converted with a single FITS axis
+        let xisf_filename = Path::new("tests/images/xisf-image-spectrum-4096-float-32bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().geometry().dimensions(), &[4096]);
+        assert_eq!(file.header().geometry().channel_count(), 1);
+        assert_eq!(file.header().channel_size(), 4096 * 4);
+
+        let fits = xisfits::convert_xisf_to_fits(&fs::read(xisf_filename).unwrap()).unwrap();
+        let (cards, values) = read_fits_values(&fits);
+        for card in &[
+            "BITPIX  = -32 ",
+            "NAXIS   = 1 ",
+            "NAXIS1  = 4096 ",
+            "DATAMIN = 0.0 ",
+            "DATAMAX = 2.0 ",
+        ] {
+            assert!(
+                cards.iter().any(|c| c.starts_with(card)),
+                "missing {}",
+                card
+            );
+        }
+        assert!(!cards.iter().any(|c| c.starts_with("NAXIS2 ")));
+        assert_eq!(values.len(), 4096);
+        assert_eq!(values, xisf_values(file.data()));
+        assert_eq!(fits.len(), 2880 + 4096 * 4 + (2880 - 4096 * 4 % 2880));
+
+        // Streaming converts it as a whole too
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        let header = XISFile::read_file_header(xisf_filename).unwrap();
+        assert_eq!(stream_bitpix(&cli, &header), Some(-32));
+
+        // Keywords of 2-D images (the pixel height and the Bayer pattern) are skipped
+        let xml = b"<xisf version=\"1.0\"><Image geometry=\"4096:1\" sampleFormat=\"Float32\" \
+                    xResolution=\"100\" yResolution=\"100\" resolutionUnit=\"cm\" \
+                    cfaPattern=\"RGGB\"/></xisf>";
+        let file = read_xisf_xml(xml).unwrap();
+        let keywords = file.header().fits_keywords();
+        assert!(keywords.iter().any(|keyword| keyword.name == "XPIXSZ"));
+        assert!(!keywords.iter().any(|keyword| keyword.name == "YPIXSZ"
+            || keyword.name == "YRESOL"
+            || keyword.name == "BAYERPAT"));
+    }
+
+    /// Converts a XISF file to FITS (as `main` does) and returns the header cards, but
+    /// the DATE card of the conversion.
+    fn convert_fits_cards(xisf_filename: &Path) -> Vec<String> {
+        let stem = xisf_filename.file_stem().unwrap().to_str().unwrap();
+        let fits_filename = std::env::temp_dir().join(format!("xisfits-test-cards-{}.fits", stem));
+        let cli = Cli::from_iter(&[
+            "xisfits",
+            xisf_filename.to_str().unwrap(),
+            fits_filename.to_str().unwrap(),
+        ]);
+        run(&cli).unwrap();
+        let fits = fs::read(&fits_filename).unwrap();
+        fs::remove_file(&fits_filename).unwrap();
+        assert_eq!(fits.len() % 2880, 0);
+
+        let mut cards = Vec::new();
+        for card in fits.chunks(80) {
+            let card = String::from_utf8_lossy(card).into_owned();
+            let end = card.starts_with("END ");
+            if !card.starts_with("DATE    =") {
+                cards.push(card);
+            }
+            if end {
+                break;
+            }
+        }
+        cards
+    }
+
+    #[test]
+    fn test_xisf_read_color_filter_array() {
+        init();
+
+        // Test that the ColorFilterArray element is parsed and written as Bayer keywords
+        let xisf_filename = Path::new("tests/images/xisf-image-cfa-element-8x8-16bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let cfa = file.header().color_filter_array().unwrap();
+        assert_eq!(cfa.pattern(), "GBRG");
+        assert_eq!(cfa.width(), 2);
+        assert_eq!(cfa.height(), 2);
+        assert_eq!(cfa.name(), "GBRG Bayer Filter");
+
+        let cards = convert_fits_cards(xisf_filename);
+        assert!(cards.contains(&format!(
+            "{:80}",
+            "BAYERPAT= 'GBRG'               / Bayer color pattern"
+        )));
+        assert!(cards.contains(&format!(
+            "{:80}",
+            "XBAYROFF= 0                    / X offset of Bayer array"
+        )));
+        assert!(cards.contains(&format!(
+            "{:80}",
+            "YBAYROFF= 0                    / Y offset of Bayer array"
+        )));
+
+        // Non 2x2 patterns are written as comments
+        let xisf_filename = Path::new("tests/images/xisf-image-cfa-xtrans-12x12-16bits.xisf");
+        let cards = convert_fits_cards(xisf_filename);
+        assert!(cards.contains(&format!(
+            "{:80}",
+            "COMMENT XISF color filter array 6x6: GGRGGBGGBGGRBRGRBGGGBGGRGGRGGBRBGBRG"
+        )));
+        assert!(cards.iter().all(|card| !card.starts_with("BAYERPAT")));
+    }
+
+    #[test]
+    fn test_xisf_read_image_type() {
+        init();
+
+        // Test that the image type is parsed and written as IMAGETYP
+        let xisf_filename = Path::new("tests/images/xisf-image-dark-8x8-16bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().image_type(), &XISFImageType::Dark);
+        assert!(file.header().fits_keywords().contains(&FITSKeyword::string(
+            "IMAGETYP",
+            "Dark Frame",
+            "Type of image"
+        )));
+
+        // Without the attribute, the type is unknown and no IMAGETYP is written
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().image_type(), &XISFImageType::Unknown);
+        assert!(file
+            .header()
+            .fits_keywords()
+            .iter()
+            .all(|keyword| keyword.name != "IMAGETYP"));
+
+        // IMAGETYP is not written twice if the file already has the FITS keyword
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-master-dark-keyword.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().image_type(), &XISFImageType::MasterDark);
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        let fits_hd = fits_header_data(&cli, &file, Box::new([]), 8, 1);
+        assert!(fits_hd
+            .extra_keywords
+            .iter()
+            .all(|keyword| keyword.name != "IMAGETYP"));
+
+        // Each image type of the XISF specification, and its IMAGETYP value
+        let image_types = [
+            ("Light", XISFImageType::Light, Some("Light Frame")),
+            ("Dark", XISFImageType::Dark, Some("Dark Frame")),
+            ("Flat", XISFImageType::Flat, Some("Flat Field")),
+            ("Bias", XISFImageType::Bias, Some("Bias Frame")),
+            (
+                "MasterLight",
+                XISFImageType::MasterLight,
+                Some("Master Light"),
+            ),
+            ("MasterDark", XISFImageType::MasterDark, Some("Master Dark")),
+            ("MasterFlat", XISFImageType::MasterFlat, Some("Master Flat")),
+            ("MasterBias", XISFImageType::MasterBias, Some("Master Bias")),
+            ("DefectMap", XISFImageType::DefectMap, None),
+            ("RejectionMapHigh", XISFImageType::RejectionMapHigh, None),
+            ("RejectionMapLow", XISFImageType::RejectionMapLow, None),
+            (
+                "BinaryRejectionMapHigh",
+                XISFImageType::BinaryRejectionMapHigh,
+                None,
+            ),
+            (
+                "BinaryRejectionMapLow",
+                XISFImageType::BinaryRejectionMapLow,
+                None,
+            ),
+            ("SlopeMap", XISFImageType::SlopeMap, None),
+            ("WeightMap", XISFImageType::WeightMap, None),
+        ];
+        for (name, image_type, frame) in &image_types {
+            assert_eq!(name.parse().as_ref(), Ok(image_type));
+            assert_eq!(image_type.as_str(), *name);
+            assert_eq!(image_type.fits_frame(), *frame);
+        }
+
+        // Unrecognized types are kept, but not written as IMAGETYP, and are errors in
+        // strict mode
+        assert!("Twilight".parse::<XISFImageType>().is_err());
+        let xml = r#"<xisf version="1.0"><Image geometry="8:8:1" sampleFormat="UInt8" colorSpace="Gray" imageType="Twilight" location="attachment:4096:64"/></xisf>"#;
+        let mut xisf = b"XISF0100".to_vec();
+        xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        xisf.extend_from_slice(&[0; 4]);
+        xisf.extend_from_slice(xml.as_bytes());
+        xisf.resize(4096 + 64, 0);
+        let file = XISFile::read_from(Cursor::new(&xisf)).unwrap();
+        let image_type = file.header().image_type();
+        assert_eq!(image_type, &XISFImageType::Other("Twilight".to_owned()));
+        assert_eq!(image_type.to_string(), "Twilight");
+        assert_eq!(image_type.fits_frame(), None);
+        let options = XisfReadOptions {
+            parse_mode: ParseMode::Strict,
+            ..XisfReadOptions::default()
+        };
+        match XISFile::read_from_with_options(Cursor::new(&xisf), options) {
+            Err(XisfError::SpecDeviation { message }) => {
+                assert_eq!(message, "unknown image type: Twilight");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_url_location() {
+        init();
+
+        // Test that image data stored in another file is read from the given offset
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-url.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().location_method(), "url");
+        assert_eq!(
+            file.header().location_url(),
+            "xisf-image-gray-8x8-8bits-url.bin"
+        );
+        assert_eq!(file.header().location_start(), 16);
+        assert_eq!(file.header().location_length(), 64);
+        match file.data() {
+            XISFData::UInt8(data) => {
+                assert_eq!(data[0][0], 0);
+                assert_eq!(data[0][63], 252);
+            }
+            _ => panic!("unexpected data type"),
+        }
+
+        // Absolute file:// URLs, of files in the directory of the XISF file
+        let xisf = fs::read(xisf_filename).unwrap();
+        let length = u32::from_le_bytes(xisf[8..12].try_into().unwrap()) as usize;
+        let directory = std::env::temp_dir().join("xisfits-test-file-url");
+        fs::create_dir_all(directory.join("data")).unwrap();
+        let directory = fs::canonicalize(directory).unwrap();
+        let file_url = |data_filename: &Path| {
+            let xml = String::from_utf8_lossy(&xisf[16..16 + length]).replace(
+                "url(xisf-image-gray-8x8-8bits-url.bin)",
+                &format!("url(file://{})", data_filename.display()),
+            );
+            let mut xisf = b"XISF0100".to_vec();
+            xisf.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+            xisf.extend_from_slice(&[0; 4]);
+            xisf.extend_from_slice(xml.as_bytes());
+            let xisf_filename = directory.join("image.xisf");
+            fs::write(&xisf_filename, &xisf).unwrap();
+            XISFile::read_file(&xisf_filename)
+        };
+        let data_filename = directory.join("data").join("image.bin");
+        let _ = fs::copy(
+            "tests/images/xisf-image-gray-8x8-8bits-url.bin",
+            &data_filename,
+        )
+        .unwrap();
+        let result = file_url(&data_filename);
+        assert_eq!(result.unwrap().data(), file.data());
+
+        // Files outside the directory of the XISF file can't be read
+        let outside_filename = directory.with_file_name("xisfits-test-file-url.bin");
+        let _ = fs::copy(&data_filename, &outside_filename).unwrap();
+        let outside = [
+            fs::canonicalize("tests/images/xisf-image-gray-8x8-8bits-url.bin").unwrap(),
+            directory
+                .join("data")
+                .join("..")
+                .join("..")
+                .join("xisfits-test-file-url.bin"),
+        ];
+        for data_filename in &outside {
+            match file_url(data_filename) {
+                Err(XisfError::UrlOutsideDirectory { url }) => {
+                    assert_eq!(url, format!("file://{}", data_filename.display()));
+                }
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+        fs::remove_dir_all(&directory).unwrap();
+        fs::remove_file(&outside_filename).unwrap();
+
+        // The timeout of remote requests can be set
+        let cli = Cli::from_iter(&["xisfits", "--timeout", "5", "in.xisf", "out.fits"]);
+        assert_eq!(cli.read_options().remote_timeout, Duration::from_secs(5));
+
+        // Remote URLs need the remote feature (other schemes aren't supported)
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-url-http.xisf");
+        match XISFile::read_file(xisf_filename) {
+            #[cfg(not(feature = "remote"))]
+            Err(XisfError::RemoteUnsupported { url }) => {
+                assert_eq!(url, "http://example.com/image.bin");
+            }
+            #[cfg(feature = "remote")]
+            Err(XisfError::Remote { url, .. }) => assert_eq!(url, "http://example.com/image.bin"),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_distributed() {
+        init();
+
+        // Test that image data stored in a block of a XISB file is read and converted
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-distributed.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.header().location_method(), "path");
+        assert_eq!(
+            file.header().location_url(),
+            "@header_dir/xisf-image-gray-8x8-8bits-distributed.xisb"
+        );
+        assert_eq!(file.header().location_index(), 0x1a);
+        let url_file =
+            XISFile::read_file(Path::new("tests/images/xisf-image-gray-8x8-8bits-url.xisf"))
+                .unwrap();
+        assert_eq!(file.data(), url_file.data());
+        assert_eq!(
+            xisf_data_to_fits(&file, ByteOrder::Big),
+            xisf_data_to_fits(&url_file, ByteOrder::Big)
+        );
+        let cards = convert_fits_cards(xisf_filename);
+        assert!(cards
+            .iter()
+            .any(|card| card.starts_with("NAXIS1  =") && card[10..30].trim() == "8"));
+
+        // Missing, invalid or undersized XISB files are reported with their path
+        let directory = std::env::temp_dir().join("xisfits-test-distributed");
+        fs::create_dir_all(&directory).unwrap();
+        let directory = fs::canonicalize(directory).unwrap();
+        let xisf = fs::read(xisf_filename).unwrap();
+        let xisb = fs::read("tests/images/xisf-image-gray-8x8-8bits-distributed.xisb").unwrap();
+        let block_filename = directory.join("xisf-image-gray-8x8-8bits-distributed.xisb");
+        let read_distributed = |xisf: &[u8], xisb: Option<&[u8]>| {
+            let _ = fs::remove_file(&block_filename);
+            if let Some(xisb) = xisb {
+                fs::write(&block_filename, xisb).unwrap();
+            }
+            let xisf_filename = directory.join("image.xisf");
+            fs::write(&xisf_filename, xisf).unwrap();
+            XISFile::read_file(&xisf_filename)
+        };
+        let block_path = block_filename.display().to_string();
+        match read_distributed(&xisf, None) {
+            Err(XisfError::BlockFile { path, source }) => {
+                assert_eq!(path, block_path);
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        match read_distributed(&xisf, Some(&xisb[..150])) {
+            Err(XisfError::BlockOutOfBounds {
+                path,
+                start,
+                length,
+                file_size,
+            }) => {
+                assert_eq!(path, block_path);
+                assert_eq!((start, length, file_size), (128, 64, 150));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        match read_distributed(&xisf, Some(&xisb[..40])) {
+            Err(XisfError::InvalidBlockFile { path }) => assert_eq!(path, block_path),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let mut unknown_id = xisf.clone();
+        let id_position = xisf.windows(4).position(|id| id == b"):1a").unwrap();
+        unknown_id[id_position + 2..id_position + 4].copy_from_slice(b"2b");
+        match read_distributed(&unknown_id, Some(&xisb)) {
+            Err(XisfError::BlockNotFound { path, id }) => {
+                assert_eq!((&*path, id), (&*block_path, 0x2b));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(read_distributed(&xisf, Some(&xisb)).is_ok());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "remote")]
+    #[test]
+    fn test_xisf_read_remote() {
+        use std::net::TcpListener;
+        init();
+
+        // Test that image data stored in remote files is downloaded (from a local server
+        // ignoring the requested range)
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let data = fs::read("tests/images/xisf-image-gray-8x8-8bits-url.bin").unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+                data.len()
+            )
+            .unwrap();
+            stream.write_all(&data).unwrap();
+        });
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-url.xisf");
+        let xisf = fs::read(xisf_filename).unwrap();
+        let length = u32::from_le_bytes(xisf[8..12].try_into().unwrap()) as usize;
+        let xml = String::from_utf8_lossy(&xisf[16..16 + length]).replace(
+            "url(xisf-image-gray-8x8-8bits-url.bin)",
+            &format!("url(http://{}/image.bin)", address),
+        );
+        let mut remote = b"XISF0100".to_vec();
+        remote.extend_from_slice(&u32::try_from(xml.len()).unwrap().to_le_bytes());
+        remote.extend_from_slice(&[0; 4]);
+        remote.extend_from_slice(xml.as_bytes());
+        // Only if external data is enabled, as it's read from memory
+        match XISFile::from_bytes(&remote) {
+            Err(XisfError::ExternalDataDisabled { location }) => {
+                assert_eq!(location, format!("url(http://{}/image.bin)", address));
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let options = XisfReadOptions {
+            external_data: true,
+            ..XisfReadOptions::default()
+        };
+        let file = XISFile::read_from_with_options(Cursor::new(&remote), options).unwrap();
+        server.join().unwrap();
+        let expected = XISFile::read_file(xisf_filename).unwrap();
+        assert_eq!(file.data(), expected.data());
+
+        // The size downloaded is limited
+        let options = XisfReadOptions {
+            max_remote_size: 64,
+            ..options
+        };
+        match XISFile::read_from_with_options(Cursor::new(&remote), options) {
+            Err(XisfError::RemoteTooLarge { length, limit, .. }) => {
+                assert_eq!((length, limit), (64, 64))
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_xisf_read_offset() {
+        init();
+
+        // Test that the offset is written as PEDESTAL, or subtracted from the data
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-16bits-offset.xisf");
+
+        let mut file = XISFile::read_file(xisf_filename).unwrap();
+        assert!((file.header().offset() - 100.0).abs() < f64::EPSILON);
+        let cli = Cli::from_iter(&["xisfits", "in.xisf", "out.fits"]);
+        let fits_hd = fits_header_data(&cli, &file, Box::new([]), 16, 1);
+        assert!(fits_hd.extra_keywords.contains(&FITSKeyword::new(
+            "PEDESTAL",
+            "100",
+            "Value added to the data (XISF offset)"
+        )));
+
+        let cli = Cli::from_iter(&["xisfits", "--subtract-offset", "in.xisf", "out.fits"]);
+        let fits_hd = fits_header_data(&cli, &file, Box::new([]), 16, 1);
+        assert!(fits_hd
+            .extra_keywords
+            .iter()
+            .all(|keyword| keyword.name != "PEDESTAL"));
+        assert!(stream_bitpix(&cli, &file).is_none());
+        file.subtract_offset();
+        match file.data() {
+            XISFData::UInt16(data) => {
+                // Values below the offset are clipped to 0
+                assert_eq!(data[0][0], 0);
+                assert_eq!(data[0][1], 900);
+                assert_eq!(data[0][63], 62900);
+            }
+            _ => panic!("unexpected data type"),
+        }
+
+        // Without the attribute, the offset is 0
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-256x256-8bits.xisf");
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.header().offset().abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_xisf_read_checksum() {
+        init();
+
+        // Test that the image data is verified against the checksum attribute
+        let xisf_filename = Path::new("tests/images/xisf-image-gray-8x8-8bits-checksum.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        assert!(file.header().checksum().starts_with("sha-256:"));
+        let file = XISFile::read_file_header(xisf_filename).unwrap();
+        assert!(file.verify_checksum(xisf_filename).is_ok());
+
+        // Files whose data doesn't match the checksum
+        let xisf_filename =
+            Path::new("tests/images/xisf-image-gray-8x8-8bits-checksum-mismatch.xisf");
+        match XISFile::read_file(xisf_filename) {
+            Err(XisfError::ChecksumMismatch { expected, got }) => {
+                assert_eq!(expected, "ddd9d41363a535aeb9a8178ed03ede5ca69fd438");
+                assert_eq!(got, "0814ecf9bd459d952268e27467aaacf5fd72698e");
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        let file = XISFile::read_file_header(xisf_filename).unwrap();
+        assert!(file.verify_checksum(xisf_filename).is_err());
+
+        // The verification can be skipped
+        let file = XISFile::read_file_unverified(xisf_filename).unwrap();
+        match file.data() {
+            XISFData::UInt8(data) => assert_eq!(data[0][63], 252),
+            _ => panic!("unexpected data type"),
+        }
+    }
+
+    #[test]
+    fn test_xisf_data_to_fits_byte_order() {
+        init();
+
+        // Test that image data is written in the requested byte order
+        let xisf_filename = Path::new("tests/images/xisf-image-rgb-256x256-16bits.xisf");
+
+        let file = XISFile::read_file(xisf_filename).unwrap();
+        let (big, bitpix_big) = xisf_data_to_fits(&file, ByteOrder::Big);
+        let (little, bitpix_little) = xisf_data_to_fits(&file, ByteOrder::Little);
+        assert_eq!(bitpix_big, 16);
+        assert_eq!(bitpix_little, 16);
+        assert_eq!(big.len(), little.len());
+        for (b, l) in big.chunks_exact(2).zip(little.chunks_exact(2)) {
+            assert_eq!([b[1], b[0]], [l[0], l[1]]);
+        }
+
+        let (native, _) = xisf_data_to_fits(&file, ByteOrder::Native);
+        if cfg!(target_endian = "little") {
+            assert_eq!(native, little);
+        } else {
+            assert_eq!(native, big);
+        }
+    }
+
     #[test]
     #[ignore] // LZ4 uncompression currently fails
     fn test_xisf_read_lz4_file() {
@@ -1,93 +1,577 @@
-use crate::{convert, fitswriter::FITSKeyword};
+//! XISF file reader.
+
+use crate::{
+    convert,
+    error::{SampleFormatParseError, XisfError, XisfParseError},
+    fitswriter::FITSKeyword,
+    ConversionProgress, ConversionStep, NullProgress,
+};
 use compress::{lz4, zlib};
 use getset::{CopyGetters, Getters};
-use log::{debug, info};
-use quick_xml::{events::Event, Reader};
+use log::{debug, info, warn};
+use quick_xml::{
+    events::{BytesStart, Event},
+    Reader,
+};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+#[cfg(feature = "json")]
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use sha1::Sha1;
+use sha2::{digest::DynDigest, Digest, Sha256, Sha512};
 use std::{
+    borrow::Cow,
+    cmp,
     convert::{TryFrom, TryInto},
-    fmt,
-    fs::File,
+    fmt::{self, Write},
+    fs::{self, File},
     io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
-    path::Path,
-    process, str,
+    mem,
+    num::ParseIntError,
+    path::{Path, PathBuf},
+    str,
+    time::Duration,
 };
 
+/// Version of the XISF specification (major, minor) supported by the reader.
+const XISF_VERSION: (u8, u8) = (1, 0);
+
+/// Namespace of the XISF XML header.
+const XISF_NAMESPACE: &str = "http://www.pixinsight.com/xisf";
+
+/// Byte order mark of UTF-8 text, allowed at the start of the XML header.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Size of the image data (in bytes) above which uncompressed attachments are read
+/// channel by channel by default.
+const CHANNEL_STREAMING_THRESHOLD: u64 = 64 << 20;
+
+/// Default maximum length of the XML header section, in bytes.
+const MAX_HEADER_LENGTH: u32 = 1 << 20;
+
+/// How strictly the XISF specification is enforced while reading a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Deviations from the specification are errors instead of warnings: unknown XISF
+    /// versions, XML namespaces and encodings, a non-zero reserved header field,
+    /// unrecognized attributes of `Image`, invalid attribute values (color space, bounds,
+    /// offset, id, image type and resolution), color spaces that don't match the number
+    /// of channels, image data of another size than the geometry and image data without
+    /// a checksum.
+    Strict,
+    /// Deviations from the specification are warnings, errors in the image data are errors.
+    Normal,
+    /// Recoverable errors in the image data are warnings, recovering as much data as
+    /// possible: attachments beyond the end of the file are read up to the end,
+    /// uncompressed data of another size than declared and data with missing channels
+    /// are padded with zeros (or cut), and checksum mismatches are ignored.
+    Lenient,
+}
+
+impl Default for ParseMode {
+    fn default() -> Self {
+        ParseMode::Normal
+    }
+}
+
+/// Options of the lower-level read API (`XISFile::read_file_with_options` and
+/// `XISFile::read_from_with_options`).
+#[derive(Debug, Clone, Copy)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct XisfReadOptions {
+    /// Read the image data (otherwise only the headers, ICC profile and thumbnail are read).
+    pub read_data: bool,
+    /// Verify the image data if the image declares a checksum.
+    pub verify_checksum: bool,
+    /// Keep a copy of the XML header, returned by `XISFile::raw_xml`.
+    pub keep_raw_xml: bool,
+    /// How strictly the XISF specification is enforced.
+    pub parse_mode: ParseMode,
+    /// Map the file in memory (with the `mmap` feature) instead of reading it, so the
+    /// image data of attachments is decoded from the map without copying it to a buffer.
+    /// Only `XISFile::read_file_with_options` maps files.
+    pub use_mmap: bool,
+    /// Uncompressed attachments larger than this size (in bytes) are read channel by
+    /// channel, buffering a single channel at a time instead of the whole image data
+    /// (0 reads all of them channel by channel, `u64::max_value()` none).
+    pub channel_streaming_threshold: u64,
+    /// Timeout of the requests of image data stored in remote files (`url(http://...)`
+    /// locations, with the `remote` feature).
+    pub remote_timeout: Duration,
+    /// Maximum size (in bytes) downloaded from remote files.
+    pub max_remote_size: u64,
+    /// Fail on XISF versions other than 1.0 whatever the parse mode, instead of reading
+    /// them as XISF 1.0.
+    pub strict_signature: bool,
+    /// Maximum length (in bytes) of the XML header. Longer headers are rejected before
+    /// they're read, as they're more likely corrupt lengths than real headers.
+    pub max_header_bytes: u32,
+    /// Read the image data stored in other files (`url(...)` and `path(...)` locations)
+    /// of images read from a reader, relative to the current directory, or downloaded
+    /// from remote files. Off by default, as images held in memory often come from
+    /// untrusted sources (e.g. uploads). Images read from files always read them.
+    pub external_data: bool,
+}
+
+impl Default for XisfReadOptions {
+    fn default() -> Self {
+        XisfReadOptions {
+            read_data: true,
+            verify_checksum: true,
+            keep_raw_xml: true,
+            parse_mode: ParseMode::Normal,
+            use_mmap: false,
+            channel_streaming_threshold: CHANNEL_STREAMING_THRESHOLD,
+            remote_timeout: Duration::from_secs(30),
+            max_remote_size: 1 << 30,
+            strict_signature: false,
+            max_header_bytes: MAX_HEADER_LENGTH,
+            external_data: false,
+        }
+    }
+}
+
 /// XISF file information structure.
 #[derive(Debug)]
 pub struct XISFile {
     header: XISFHeader,
+    raw_xml: Box<str>,
     keywords: Box<[FITSKeyword]>,
     data: XISFData,
+    icc_profile: Option<Box<[u8]>>,
+    thumbnail: Option<XISFImage>,
 }
 
 impl XISFile {
+    /// Gets the attributes of the image.
     pub fn header(&self) -> &XISFHeader {
         &self.header
     }
 
+    /// Gets the XML header as stored in the file, or an empty string if it wasn't kept
+    /// (see `XisfReadOptions::keep_raw_xml`).
+    pub fn raw_xml(&self) -> &str {
+        &self.raw_xml
+    }
+
+    /// Builds the lines of the FITS `COMMENT` cards embedding the XML header, between
+    /// "BEGIN XISF XML HEADER" and "END XISF XML HEADER" lines. Non-ASCII characters
+    /// are written as XML character references. Lines longer than a card are continued
+    /// in the next one, ending with `&`, and are never split inside a reference (e.g.
+    /// `&#xC5;`). A line of the XML header ending with `&` is written with a trailing
+    /// `&#x26;`, so a card ending with `&` is always continued.
+    pub fn xml_comment_lines(&self) -> Vec<String> {
+        // Characters of a COMMENT card, leaving room for the continuation mark
+        const WIDTH: usize = 71;
+        let mut lines = vec!["BEGIN XISF XML HEADER".to_owned()];
+        for line in self.raw_xml.lines() {
+            let mut escaped = String::with_capacity(line.len());
+            for c in line.chars() {
+                match c {
+                    ' '..='~' => escaped.push(c),
+                    '\t' | '\r' => escaped.push(' '),
+                    _ => escaped.push_str(&format!("&#x{:X};", u32::from(c))),
+                }
+            }
+            escaped.truncate(escaped.trim_end().len());
+            if escaped.ends_with('&') {
+                let _ = escaped.pop();
+                escaped.push_str("&#x26;");
+            }
+            let mut rest = escaped.as_str();
+            while rest.len() > WIDTH + 1 {
+                let split = xml_reference_boundary(rest, WIDTH);
+                lines.push(format!("{}&", &rest[..split]));
+                rest = &rest[split..];
+            }
+            lines.push(rest.to_owned());
+        }
+        lines.push("END XISF XML HEADER".to_owned());
+        lines
+    }
+
+    /// Gets the FITS keywords of the image.
     pub fn keywords(&self) -> &[FITSKeyword] {
         &self.keywords
     }
 
+    /// Gets the metadata of the file (header, FITS keywords and XISF properties), which
+    /// can be serialized, e.g. as JSON.
+    #[cfg(feature = "json")]
+    pub fn metadata(&self) -> XISFMetadata<'_> {
+        XISFMetadata {
+            header: &self.header,
+            keywords: &self.keywords,
+            properties: self.header.properties(),
+        }
+    }
+
+    /// Gets the image data (`XISFData::Empty` if it wasn't read).
     pub fn data(&self) -> &XISFData {
         &self.data
     }
 
-    /// Read XISF file and decode headers and image
-    pub fn read_file(xisf_filename: &Path) -> io::Result<Self> {
-        let mut xisf_header = XISFHeaderReader::default();
+    /// Gets the ICC profile of the image, if the file embeds one.
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.icc_profile.as_ref().map(|profile| &profile[..])
+    }
+
+    /// Gets the thumbnail of the image, if the file has one.
+    pub fn thumbnail(&self) -> Option<&XISFImage> {
+        self.thumbnail.as_ref()
+    }
+
+    /// Subtracts the offset (pedestal) declared by the image from its data.
+    pub fn subtract_offset(&mut self) {
+        self.data.subtract_offset(self.header.offset());
+    }
+
+    /// Keeps only the given channels of the image, in the given order (e.g. `[0, 2]`
+    /// drops the second channel). Images whose channels no longer match their color
+    /// space become grayscale.
+    pub fn select_channels(&mut self, channels: &[usize]) -> Result<(), XisfError> {
+        let channel_count = self.header.geometry.channel_count;
+        if let Some(&channel) = channels.iter().find(|&&channel| channel >= channel_count) {
+            return Err(XisfError::ChannelOutOfRange {
+                channel,
+                channel_count,
+            });
+        }
+        self.data.select_channels(channels);
+        self.header.geometry.channel_count = channels.len();
+        if self.header.check_color_space().is_err() {
+            self.header.color_space = XISFColorSpace::Gray;
+        }
+        Ok(())
+    }
+
+    /// Read XISF file and decode headers and image. The image data is verified
+    /// if the image declares a checksum.
+    pub fn read_file(xisf_filename: &Path) -> Result<Self, XisfError> {
+        Self::read_file_with_options(xisf_filename, XisfReadOptions::default())
+    }
+
+    /// Read XISF file and decode headers and image, without verifying the checksum.
+    pub fn read_file_unverified(xisf_filename: &Path) -> Result<Self, XisfError> {
+        let options = XisfReadOptions {
+            verify_checksum: false,
+            ..XisfReadOptions::default()
+        };
+        Self::read_file_with_options(xisf_filename, options)
+    }
+
+    /// Read XISF file headers (and the ICC profile and thumbnail), but not the image data.
+    /// The image data can then be streamed from `location_start()`.
+    pub fn read_file_header(xisf_filename: &Path) -> Result<Self, XisfError> {
+        let options = XisfReadOptions {
+            read_data: false,
+            verify_checksum: false,
+            ..XisfReadOptions::default()
+        };
+        Self::read_file_with_options(xisf_filename, options)
+    }
+
+    /// Read only the XISF header and FITS keywords of a file, e.g. to catalogue many
+    /// files. The image data is never read.
+    pub fn read_header(
+        xisf_filename: &Path,
+    ) -> Result<(XISFHeader, Box<[FITSKeyword]>), XisfError> {
+        let file = Self::read_file_header(xisf_filename)?;
+        Ok((file.header, file.keywords))
+    }
+
+    /// Read a XISF image from any reader (e.g. a buffer in memory) and decode headers
+    /// and image. The image data is verified if the image declares a checksum. Image
+    /// data stored in other files (`url(...)` and `path(...)` locations) is rejected,
+    /// unless `XisfReadOptions::external_data` is set.
+    pub fn read_from<R>(reader: R) -> Result<Self, XisfError>
+    where
+        R: Read + Seek,
+    {
+        Self::read_from_with_options(reader, XisfReadOptions::default())
+    }
+
+    /// Read a monolithic XISF image held in memory (e.g. by a server or in WebAssembly),
+    /// as `read_from`. Buffers too short for the signature and the header length are
+    /// `XisfError::TooShort` errors.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, XisfError> {
+        Self::read_from(io::Cursor::new(bytes))
+    }
+
+    /// Read a XISF image from any reader, as `read_from`, with the given options.
+    pub fn read_from_with_options<R>(reader: R, options: XisfReadOptions) -> Result<Self, XisfError>
+    where
+        R: Read + Seek,
+    {
+        Self::read_from_with_progress(reader, options, &NullProgress)
+    }
+
+    /// Read a XISF image from any reader, as `read_from_with_options`, reporting the
+    /// progress of reading the header and the image data to `progress`.
+    pub fn read_from_with_progress<R>(
+        reader: R,
+        options: XisfReadOptions,
+        progress: &dyn ConversionProgress,
+    ) -> Result<Self, XisfError>
+    where
+        R: Read + Seek,
+    {
+        let mut reader = reader;
+        let file_size = reader.seek(SeekFrom::End(0))?;
+        let _ = reader.seek(SeekFrom::Start(0))?;
+        Self::read_reader(
+            BufReader::new(reader),
+            file_size,
+            Path::new(""),
+            options,
+            progress,
+            None,
+        )
+    }
+
+    /// Verify the checksum of the image data stored in `xisf_filename`, reading it
+    /// chunk by chunk. Useful when the image data hasn't been read with `read_file`.
+    pub fn verify_checksum(&self, xisf_filename: &Path) -> Result<(), XisfError> {
+        if self.header.checksum().is_empty() || self.header.location_method() != "attachment" {
+            return Ok(());
+        }
+        let mut f = BufReader::new(File::open(xisf_filename)?);
+        let _ = f.seek(SeekFrom::Start(self.header.location_start()))?;
+        xisf_verify_checksum(
+            &mut f.take(self.header.location_length()),
+            self.header.checksum(),
+        )
+    }
+
+    /// Read XISF file with the given options.
+    pub fn read_file_with_options(
+        xisf_filename: &Path,
+        options: XisfReadOptions,
+    ) -> Result<Self, XisfError> {
+        Self::read_file_with_progress(xisf_filename, options, &NullProgress)
+    }
+
+    /// Read XISF file, as `read_file_with_options`, reporting the progress of reading
+    /// the header and the image data to `progress`.
+    pub fn read_file_with_progress(
+        xisf_filename: &Path,
+        options: XisfReadOptions,
+        progress: &dyn ConversionProgress,
+    ) -> Result<Self, XisfError> {
+        let f = File::open(xisf_filename)?;
+        let file_size = f.metadata()?.len();
+        if options.use_mmap && file_size > 0 {
+            #[cfg(feature = "mmap")]
+            {
+                let map = xisf_map_file(&f)?;
+                return Self::read_reader(
+                    BufReader::new(io::Cursor::new(&map[..])),
+                    file_size,
+                    xisf_filename,
+                    options,
+                    progress,
+                    Some(&map),
+                );
+            }
+            #[cfg(not(feature = "mmap"))]
+            warn!("Mapping files in memory needs the mmap feature, reading the file instead");
+        }
+        Self::read_reader(
+            BufReader::new(f),
+            file_size,
+            xisf_filename,
+            options,
+            progress,
+            None,
+        )
+    }
+
+    /// Read a XISF image of `file_size` bytes. `xisf_filename` is used to find the
+    /// image data stored in other files. `mapped` is the whole file when it's mapped in
+    /// memory, to borrow the image data from it.
+    fn read_reader<R>(
+        f: BufReader<R>,
+        file_size: u64,
+        xisf_filename: &Path,
+        options: XisfReadOptions,
+        progress: &dyn ConversionProgress,
+        mapped: Option<&[u8]>,
+    ) -> Result<Self, XisfError>
+    where
+        R: Read + Seek,
+    {
+        let mut f = f;
+        let mut xisf_header = XISFHeaderReader {
+            mode: options.parse_mode,
+            ..XISFHeaderReader::default()
+        };
         let mut xisf_data = XISFData::default();
         let mut xisf_fits_keywords = Vec::new();
 
         // Declare buffers
-        let mut buffer_header_signature = String::new();
+        let mut buffer_header_signature = [0; 8];
         let mut buffer_header_length = [0; 4];
         let mut buffer_header_reserved = [0; 4];
 
-        // Open XISF image file
-        let f = File::open(xisf_filename)?;
-        let file_size = f.metadata().unwrap().len();
-        let mut f = BufReader::new(f);
         info!("File size: {}", file_size);
 
+        // The fixed header is 16 bytes long (signature, length and reserved)
+        if file_size < 16 {
+            return Err(XisfError::TooShort { size: file_size });
+        }
+
         // -- Read header fields
         // Header: Signature
-        let _ = f
-            .by_ref()
-            .take(8)
-            .read_to_string(&mut buffer_header_signature)?;
+        f.read_exact(&mut buffer_header_signature)?;
+
+        // Check signature ("XISF" and the version, e.g. "0100" for XISF 1.0)
+        match xisf_parse_signature(buffer_header_signature) {
+            Some(version) if version != XISF_VERSION && options.strict_signature => {
+                return Err(XisfError::InvalidSignature {
+                    found: String::from_utf8_lossy(&buffer_header_signature).into_owned(),
+                })
+            }
+            Some(version) => {
+                if version == XISF_VERSION {
+                    info!("XISF signature: Ok");
+                } else {
+                    xisf_header.deviation(format!(
+                        "Unknown XISF version {}.{}, reading it as XISF {}.{}",
+                        version.0, version.1, XISF_VERSION.0, XISF_VERSION.1
+                    ))?;
+                }
+                xisf_header.version = version;
+            }
+            None => {
+                return Err(XisfError::InvalidSignature {
+                    found: String::from_utf8_lossy(&buffer_header_signature).into_owned(),
+                })
+            }
+        }
+
         // Header: Length of XML section
         f.read_exact(&mut buffer_header_length)?;
         // Header: Reserved for future use
         f.read_exact(&mut buffer_header_reserved)?;
 
         // Assign header values to XISF header struct
-        xisf_header.signature = buffer_header_signature;
+        xisf_header.signature = String::from_utf8_lossy(&buffer_header_signature).into_owned();
         xisf_header.length = convert::u8_to_v_u32(&buffer_header_length)[0];
         xisf_header.reserved = convert::u8_to_v_u32(&buffer_header_reserved)[0];
         // -- End of read header fields
 
+        // The reserved field is zero in XISF 1.0, other values may flag an extension of
+        // the format that this reader doesn't know
+        debug!("Reserved header field: {:#010x}", xisf_header.reserved);
+        if xisf_header.reserved != 0 {
+            xisf_header.deviation(format!(
+                "Reserved header field is {:#010x}, not 0: the file may use an unknown extension of the format",
+                xisf_header.reserved
+            ))?;
+        }
+
+        // Realistic XML headers are a few KB, don't trust lengths of corrupt files
+        if xisf_header.length > options.max_header_bytes {
+            return Err(XisfError::HeaderTooLarge {
+                length: xisf_header.length,
+            });
+        }
+
+        // The XML header must be inside the file
+        if u64::from(xisf_header.length) > file_size - 16 {
+            return Err(XisfError::HeaderOutOfBounds {
+                length: xisf_header.length,
+                file_size,
+            });
+        }
+
         // Header: XML section
-        let handle = f
-            .by_ref()
-            .take(u64::from(convert::u8_to_v_u32(&buffer_header_length)[0]));
+        let mut handle = f.by_ref().take(u64::from(xisf_header.length));
+
+        // Parse XML Header section, keeping a copy of it if requested
+        let raw_xml = if options.keep_raw_xml {
+            let mut xml = Vec::new();
+            let _ = handle.read_to_end(&mut xml)?;
+            xisf_header.fill_from_reader(xml.as_slice(), &mut xisf_fits_keywords)?;
+            let xml = if xml.starts_with(UTF8_BOM) {
+                &xml[UTF8_BOM.len()..]
+            } else {
+                &xml[..]
+            };
+            String::from_utf8_lossy(xml).into()
+        } else {
+            xisf_header.fill_from_reader(handle, &mut xisf_fits_keywords)?;
+            Box::from("")
+        };
+        progress.on_step(
+            ConversionStep::ReadHeader,
+            16 + u64::from(xisf_header.length),
+            file_size,
+        );
 
-        // Parse XML Header section
-        xisf_header.fill_from_reader(handle, &mut xisf_fits_keywords)?;
-        let xisf_header = xisf_header.build();
+        // Read the ICC profile (inline profiles are decoded while parsing the XML)
+        let icc_profile = if xisf_header.icc_profile_location.starts_with("attachment:") {
+            xisf_read_attachment(&mut f, &xisf_header.icc_profile_location, file_size)?
+        } else {
+            xisf_header.icc_profile.take()
+        };
+        if let Some(ref icc_profile) = icc_profile {
+            info!("ICC profile: {} bytes", icc_profile.len());
+        }
 
-        // Check signature
-        if xisf_header.signature() == "XISF0100" {
-            info!("XISF signature: Ok");
+        // Read the thumbnail
+        let thumbnail = if let Some(thumbnail) = xisf_header.thumbnail.take() {
+            xisf_read_image(&mut f, thumbnail, file_size)?
         } else {
-            eprintln!("Incorrect XISF signature: {}", xisf_header.signature());
-            process::exit(1);
-            // TODO: proper error handling
+            None
+        };
+        if let Some(ref thumbnail) = thumbnail {
+            info!("Thumbnail: {}", thumbnail);
+        }
+        let xisf_header = xisf_header.build()?;
+        let check_channels = || {
+            xisf_header.check_geometry()?;
+            xisf_header.check_color_space()
+        };
+        if options.parse_mode == ParseMode::Strict {
+            check_channels()?;
+            xisf_header.check_unknown_attributes()?;
+            if xisf_header.checksum().is_empty() {
+                return Err(XisfError::MissingChecksum);
+            }
+        } else if let Err(e) = check_channels() {
+            warn!("{}", e);
         }
 
         // Output parsed data
         xisf_header.print_info();
 
+        // The image data stored as an attachment must be inside the file, after the
+        // XML header (the sum of crafted positions may overflow)
+        if xisf_header.location_method() == "attachment" {
+            let start = xisf_header.location_start();
+            let length = xisf_header.location_length();
+            let header_end = 16 + u64::from(xisf_header.length());
+            if start < header_end {
+                return Err(XisfError::AttachmentOverlapsHeader { start, header_end });
+            }
+            match start.checked_add(length) {
+                Some(end) if end <= file_size => {}
+                _ => {
+                    let e = XisfError::AttachmentOutOfBounds {
+                        start,
+                        length,
+                        file_size,
+                    };
+                    // The data is read up to the end of the file
+                    if options.parse_mode != ParseMode::Lenient {
+                        return Err(e);
+                    }
+                    warn!("{}", e);
+                }
+            }
+        }
+
         // Stop if data is compressed
         if xisf_header.compression().is_empty() {
             info!("Read XISF > Data uncompressed.");
@@ -95,179 +579,747 @@ impl XISFile {
             info!("Read XISF > Data compressed.");
         }
 
-        // Interpret it as numbers and store as vector/s
-        if xisf_header.location_method() == "attachment"
-            && xisf_header.location_start() + xisf_header.location_length() <= file_size
+        // Large uncompressed attachments are read channel by channel (unless mapped)
+        let stream_channels = options.read_data
+            && mapped.is_none()
+            && xisf_header.location_method() == "attachment"
+            && xisf_header.compression().is_empty()
+            && xisf_header.location_length() > options.channel_streaming_threshold;
+        if stream_channels {
+            info!("Read XISF > Read data channel by channel");
+            xisf_data = xisf_stream_channels(&mut f, &xisf_header, file_size, options, progress)?;
+            progress.on_step(
+                ConversionStep::Decompress,
+                xisf_header.location_length(),
+                (xisf_header.channel_size() * xisf_header.geometry().channel_count()) as u64,
+            );
+        }
+
+        // Images read from a reader have no file name, so their data files would be
+        // looked up in the current directory
+        let external =
+            xisf_header.location_method() == "url" || xisf_header.location_method() == "path";
+        if options.read_data && external && xisf_filename == Path::new("") && !options.external_data
         {
-            // Goto to file position where the image begins
-            match f.seek(SeekFrom::Start(xisf_header.location_start())) {
-                Ok(v) => {
-                    info!("Read XISF > File correctly seek: {:?}", v);
-                }
-                Err(r) => {
-                    eprintln!("Read XISF > Error seeking file: {:?}", r);
-                    process::exit(1);
-                    // TODO: better error handling
-                }
+            return Err(XisfError::ExternalDataDisabled {
+                location: format!(
+                    "{}({})",
+                    xisf_header.location_method(),
+                    xisf_header.location_url()
+                ),
+            });
+        }
+
+        // Read the image data, stored in this file or in a file referenced by an URL
+        let image_data = if !options.read_data || stream_channels {
+            None
+        } else if xisf_header.location_method() == "attachment" {
+            // Goto to file position where the image begins (in lenient mode, it may be
+            // beyond the end of the file)
+            let start = cmp::min(xisf_header.location_start(), file_size);
+            if let Some(mapped) = mapped {
+                // Borrow the image data from the map instead of copying it
+                let start = usize::try_from(start).unwrap_or(mapped.len());
+                let length = usize::try_from(xisf_header.location_length()).unwrap_or(usize::MAX);
+                let end = cmp::min(start.saturating_add(length), mapped.len());
+                info!("Read XISF > Data mapped: {:?}", end - start);
+                Some(Cow::Borrowed(&mapped[start..end]))
+            } else {
+                let position = f.seek(SeekFrom::Start(start))?;
+                info!("Read XISF > File correctly seek: {:?}", position);
+
+                // Read image size bytes
+                let image_data = xisf_read_data(&mut f, xisf_header.location_length(), progress)?;
+                info!("Read XISF > Data correctly read: {:?}", image_data.len());
+                Some(Cow::Owned(image_data))
             }
+        } else if xisf_header.location_method() == "url"
+            && xisf_is_remote(xisf_header.location_url())
+        {
+            Some(Cow::Owned(xisf_read_remote(
+                xisf_header.location_url(),
+                xisf_header.location_start(),
+                xisf_header.location_length(),
+                options,
+            )?))
+        } else if xisf_header.location_method() == "url" {
+            let data_filename = xisf_url_path(xisf_filename, xisf_header.location_url())?;
+            info!("Read XISF > Data file: {}", data_filename.display());
+            let mut data_file = BufReader::new(File::open(&data_filename)?);
+            let _ = data_file.seek(SeekFrom::Start(xisf_header.location_start()))?;
+            let image_data =
+                xisf_read_data(&mut data_file, xisf_header.location_length(), progress)?;
+            Some(Cow::Owned(image_data))
+        } else if xisf_header.location_method() == "path" {
+            let block_filename = xisf_block_path(xisf_filename, xisf_header.location_url())?;
+            info!("Read XISF > Data blocks file: {}", block_filename.display());
+            Some(Cow::Owned(xisf_read_block(
+                &block_filename,
+                xisf_header.location_index(),
+            )?))
+        } else if xisf_header.location_method().is_empty() {
+            return Err(XisfError::MissingLocation);
+        } else {
+            // Don't convert an empty image instead
+            return Err(XisfError::UnsupportedLocationMethod {
+                method: xisf_header.location_method().to_owned(),
+            });
+        };
 
-            let mut image_data = Vec::new();
-            // Read image size bytes
-            match f
-                .by_ref()
-                .take(xisf_header.location_length())
-                .read_to_end(&mut image_data)
-            {
-                Ok(v) => {
-                    info!("Read XISF > Data correctly read: {:?}", v);
-                }
-                Err(r) => {
-                    eprintln!("Read XISF > Error reading image: {:?}", r);
+        // Interpret it as numbers and store as vector/s
+        if let Some(image_data) = image_data {
+            let stored_size = image_data.len() as u64;
+            progress.on_step(ConversionStep::ReadData, stored_size, stored_size);
+            // Verify the data as stored in the file (before uncompressing it)
+            if options.verify_checksum && !xisf_header.checksum().is_empty() {
+                match xisf_verify_checksum(&mut &image_data[..], xisf_header.checksum()) {
+                    Err(e @ XisfError::ChecksumMismatch { .. })
+                        if options.parse_mode == ParseMode::Lenient =>
+                    {
+                        warn!("{}", e);
+                    }
+                    result => result?,
                 }
-            };
+            }
 
             // Uncompress data
-            let image_data = if xisf_header.compression_codec().is_empty() {
-                image_data.into_boxed_slice()
+            let mut image_data = if xisf_header.compression_codec().is_empty() {
+                image_data
             } else {
-                xisf_uncompress_data(&xisf_header, image_data.as_slice())
+                let data = xisf_uncompress_data(&xisf_header, &image_data, options.parse_mode)?;
+                Cow::Owned(data.into_vec())
             };
 
-            // Read each channel
-            let channel_count = xisf_header.geometry().channel_count();
-            let chunks_iter = image_data
-                .chunks_exact(xisf_header.channel_size())
-                .take(channel_count);
-            xisf_data = match xisf_header.sample_format() {
-                XISFSampleFormat::UInt8 => {
-                    let mut data = Vec::with_capacity(channel_count);
-                    for image_channel in chunks_iter {
-                        data.push(image_channel.to_vec().into_boxed_slice());
-                    }
-
-                    XISFData::UInt8(data.into_boxed_slice())
-                }
-                XISFSampleFormat::UInt16 => {
-                    let mut data = Vec::with_capacity(channel_count);
-                    for image_channel in chunks_iter {
-                        data.push(convert::u8_to_v_u16(&image_channel).into_boxed_slice());
-                    }
-
-                    XISFData::UInt16(data.into_boxed_slice())
-                }
-                XISFSampleFormat::UInt32 => {
-                    let mut data = Vec::with_capacity(channel_count);
-                    for image_channel in chunks_iter {
-                        data.push(convert::u8_to_v_u32(&image_channel).into_boxed_slice());
-                    }
-
-                    XISFData::UInt32(data.into_boxed_slice())
+            // The data must hold all the channels declared by the geometry
+            let channels = xisf_channel_count(
+                image_data.len(),
+                xisf_header.channel_size(),
+                xisf_header.geometry().channel_count(),
+            );
+            if channels < xisf_header.geometry().channel_count() {
+                let e = XisfError::TruncatedData {
+                    channels,
+                    expected: xisf_header.geometry().channel_count(),
+                };
+                if options.parse_mode != ParseMode::Lenient {
+                    return Err(e);
                 }
-                XISFSampleFormat::Float32 => {
-                    let mut data = Vec::with_capacity(channel_count);
-                    for image_channel in chunks_iter {
-                        data.push(convert::u8_to_v_f32(&image_channel).into_boxed_slice());
-                    }
+                // The missing samples are zero
+                warn!("{}", e);
+                let size = xisf_header
+                    .geometry()
+                    .checked_size(xisf_header.sample_format().size());
+                let max_size = if xisf_header.compression_codec().is_empty() {
+                    cmp::max(file_size, stored_size)
+                } else {
+                    stored_size.saturating_mul(MAX_COMPRESSION_RATIO)
+                };
+                let mut padded = image_data.into_owned();
+                padded.resize(xisf_padded_size(size, max_size)?, 0);
+                image_data = Cow::Owned(padded);
+            }
+            progress.on_step(
+                ConversionStep::Decompress,
+                stored_size,
+                image_data.len() as u64,
+            );
 
-                    XISFData::Float32(data.into_boxed_slice())
-                }
-                XISFSampleFormat::Float64 => {
-                    let mut data = Vec::with_capacity(channel_count);
-                    for image_channel in chunks_iter {
-                        data.push(convert::u8_to_v_f64(&image_channel).into_boxed_slice());
-                    }
+            // Read each channel
+            if let Some(data) = xisf_bytes_to_data(
+                &image_data,
+                xisf_header.sample_format(),
+                xisf_header.channel_size(),
+                xisf_header.geometry().channel_count(),
+            ) {
+                xisf_data = data;
+            } else {
+                return Err(XisfError::UnsupportedSampleFormat {
+                    sample_format: xisf_header.sample_format().to_string(),
+                });
+            }
+        }
 
-                    XISFData::Float64(data.into_boxed_slice())
-                }
-                _ => {
-                    eprintln!(
-                        "Read XISF > Unsupported type > {}",
-                        xisf_header.sample_format().as_str()
-                    );
-                    process::exit(1);
-                    // TODO: better error handling
-                }
-            };
+        // Check that the pixel values are in the declared range
+        if let Some(bounds) = xisf_header.bounds() {
+            let out_of_bounds = xisf_data.count_out_of_bounds(bounds);
+            if out_of_bounds > 0 {
+                warn!(
+                    "{} pixel values out of the bounds {}:{}",
+                    out_of_bounds, bounds.0, bounds.1
+                );
+            }
         }
 
         Ok(XISFile {
             header: xisf_header,
+            raw_xml,
             keywords: xisf_fits_keywords.into_boxed_slice(),
             data: xisf_data,
+            icc_profile: icc_profile.map(Vec::into_boxed_slice),
+            thumbnail,
         })
         // -- End of read image data from file
     }
 }
 
-// Struct to read XISF header data
+/// XISF file whose image data is read on demand, e.g. to show the header of an image
+/// at once and load its pixels later. Opening the file only reads its header; the
+/// channels are read the first time they are requested, and kept.
+///
+/// Only uncompressed attachments are read channel by channel. Compressed image data, or
+/// data stored in other files, is read and decoded as a whole on the first access.
+#[derive(Debug)]
+pub struct XISFileLazy {
+    path: PathBuf,
+    options: XisfReadOptions,
+    file: XISFile,
+    data: Option<XISFData>,
+    channels: Vec<Option<XISFData>>,
+}
+
+impl XISFileLazy {
+    /// Open a XISF file, reading its headers (and the ICC profile and thumbnail) only.
+    pub fn open(xisf_filename: &Path) -> Result<Self, XisfError> {
+        Self::open_with_options(xisf_filename, XisfReadOptions::default())
+    }
+
+    /// Open a XISF file with the given options, reading its headers only. The image data
+    /// is read with the same options (but `read_data`).
+    pub fn open_with_options(
+        xisf_filename: &Path,
+        options: XisfReadOptions,
+    ) -> Result<Self, XisfError> {
+        let header_options = XisfReadOptions {
+            read_data: false,
+            verify_checksum: false,
+            ..options
+        };
+        let file = XISFile::read_file_with_options(xisf_filename, header_options)?;
+        let channel_count = file.header().geometry().channel_count();
+        Ok(Self {
+            path: xisf_filename.to_path_buf(),
+            options: XisfReadOptions {
+                read_data: true,
+                ..options
+            },
+            file,
+            data: None,
+            channels: (0..channel_count).map(|_| None).collect(),
+        })
+    }
+
+    /// Gets the file with its headers, but no image data.
+    pub fn file(&self) -> &XISFile {
+        &self.file
+    }
+
+    /// Gets the attributes of the image.
+    pub fn header(&self) -> &XISFHeader {
+        self.file.header()
+    }
+
+    /// Gets the FITS keywords of the image.
+    pub fn keywords(&self) -> &[FITSKeyword] {
+        self.file.keywords()
+    }
+
+    /// Gets the image data, reading it the first time. It's verified (if the image
+    /// declares a checksum and the options ask for it).
+    pub fn data(&mut self) -> Result<&XISFData, XisfError> {
+        if self.data.is_none() {
+            let file = XISFile::read_file_with_options(&self.path, self.options)?;
+            self.data = Some(file.data);
+        }
+        Ok(self.data.get_or_insert(XISFData::Empty))
+    }
+
+    /// Gets the data of a channel of the image (as data with a single channel), reading
+    /// it the first time. Channels read on their own aren't verified by the checksum.
+    pub fn channel(&mut self, channel: usize) -> Result<&XISFData, XisfError> {
+        let channel_count = self.channels.len();
+        if channel >= channel_count {
+            return Err(XisfError::ChannelOutOfRange {
+                channel,
+                channel_count,
+            });
+        }
+        if self.channels[channel].is_none() {
+            let header = self.file.header();
+            let data = if self.data.is_none()
+                && header.location_method() == "attachment"
+                && header.compression().is_empty()
+            {
+                self.read_channel(channel)?
+            } else {
+                self.data()?.channel(channel).unwrap_or(XISFData::Empty)
+            };
+            self.channels[channel] = Some(data);
+        }
+        Ok(self.channels[channel].get_or_insert(XISFData::Empty))
+    }
+
+    /// Read a channel of an uncompressed attachment
+    fn read_channel(&self, channel: usize) -> Result<XISFData, XisfError> {
+        let header = self.file.header();
+        let channel_size = header.channel_size();
+        let mut f = File::open(&self.path)?;
+        let file_size = f.metadata()?.len();
+        // In lenient mode, the attachment may start or end beyond the end of the file
+        let start = cmp::min(header.location_start(), file_size);
+        let offset = (channel * channel_size) as u64;
+        let _ = f.seek(SeekFrom::Start(start.saturating_add(offset)))?;
+        let length = cmp::min(
+            channel_size as u64,
+            header.location_length().saturating_sub(offset),
+        );
+        let mut bytes = Vec::with_capacity(channel_size);
+        let _ = f.take(length).read_to_end(&mut bytes)?;
+        if bytes.len() < channel_size {
+            let e = XisfError::TruncatedData {
+                channels: channel,
+                expected: self.channels.len(),
+            };
+            if self.options.parse_mode != ParseMode::Lenient {
+                return Err(e);
+            }
+            // The missing samples are zero
+            warn!("{}", e);
+            bytes.resize(channel_size, 0);
+        }
+        xisf_bytes_to_data(&bytes, header.sample_format(), channel_size, 1).ok_or_else(|| {
+            XisfError::UnsupportedSampleFormat {
+                sample_format: header.sample_format().to_string(),
+            }
+        })
+    }
+}
+
+/// Metadata of a XISF file: the header, the FITS keywords and the XISF properties.
+#[cfg(feature = "json")]
+#[derive(Debug, Serialize)]
+pub struct XISFMetadata<'a> {
+    header: &'a XISFHeader,
+    keywords: &'a [FITSKeyword],
+    properties: &'a [XISFProperty],
+}
+
+/// Attributes of the image of a XISF file, read from its XML header.
 #[derive(Debug, Getters, CopyGetters)]
 pub struct XISFHeader {
     signature: Box<str>,
+    /// Version of the XISF file (major, minor), from the signature.
+    #[getset(get_copy = "pub")]
+    version: (u8, u8),
+    /// Length of the XML header in bytes.
     #[getset(get_copy = "pub")]
     length: u32,
+    /// Reserved field of the XISF header.
     #[getset(get_copy = "pub")]
     reserved: u32,
+    /// Geometry of the image (dimensions and channel count).
     #[getset(get = "pub")]
     geometry: XISFGeometry,
+    /// Sample format of the image.
     #[getset(get_copy = "pub")]
     sample_format: XISFSampleFormat,
-    color_space: Box<str>,
+    /// Color space of the image.
+    #[getset(get_copy = "pub")]
+    color_space: XISFColorSpace,
     location: Box<str>,
     location_method: Box<str>,
+    location_url: Box<str>,
+    /// Position of the image data in the file, when `location_method` is `attachment`
+    /// or `url`.
     #[getset(get_copy = "pub")]
     location_start: u64,
+    /// Identifier of the data block in the XISB file, when `location_method` is `path`.
+    #[getset(get_copy = "pub")]
+    location_index: u64,
+    /// Length of the image data in bytes.
     #[getset(get_copy = "pub")]
     location_length: u64,
     compression: Box<str>,
     compression_codec: Box<str>,
+    /// Size of the uncompressed image data in bytes.
     #[getset(get_copy = "pub")]
     compression_size: usize,
+    /// Type of the image.
+    #[getset(get = "pub")]
+    image_type: XISFImageType,
+    cfa_pattern: Box<str>,
+    cfa_source_pattern_name: Box<str>,
+    color_filter_array: Option<XISFColorFilterArray>,
+    rgb_working_space: Option<XISFRGBWorkingSpace>,
+    display_function: Option<XISFDisplayFunction>,
+    resolution: Option<XISFResolution>,
+    properties: Box<[XISFProperty]>,
+    icc_profile_location: Box<str>,
+    bounds: Option<(f64, f64)>,
+    offset: f64,
+    checksum: Box<str>,
+    id: Option<Box<str>>,
+    uuid: Option<Box<str>>,
+    unknown_attributes: Box<[(String, String)]>,
+}
+
+/// Serializes the main attributes of the image: the version of the file, geometry,
+/// sample format, color space, image type, location method and compression, and the
+/// bounds, offset, identifiers and checksum of the image.
+#[cfg(feature = "json")]
+impl Serialize for XISFHeader {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        #[derive(Serialize)]
+        struct Compression<'a> {
+            codec: &'a str,
+            uncompressed_size: usize,
+        }
+
+        let compression = if self.compression.is_empty() {
+            None
+        } else {
+            Some(Compression {
+                codec: &self.compression_codec,
+                uncompressed_size: self.compression_size,
+            })
+        };
+        let checksum = if self.checksum.is_empty() {
+            None
+        } else {
+            Some(&self.checksum)
+        };
+        let mut header = serializer.serialize_struct("XISFHeader", 12)?;
+        header.serialize_field("version", &format!("{}.{}", self.version.0, self.version.1))?;
+        header.serialize_field("geometry", &self.geometry)?;
+        header.serialize_field("sample_format", &self.sample_format.to_string())?;
+        header.serialize_field("color_space", &self.color_space.to_string())?;
+        header.serialize_field("image_type", &self.image_type.to_string())?;
+        header.serialize_field("location", &self.location_method)?;
+        header.serialize_field("compression", &compression)?;
+        header.serialize_field("bounds", &self.bounds)?;
+        header.serialize_field("offset", &self.offset)?;
+        header.serialize_field("id", &self.id)?;
+        header.serialize_field("uuid", &self.uuid)?;
+        header.serialize_field("checksum", &checksum)?;
+        header.end()
+    }
 }
 
 impl XISFHeader {
+    /// Gets the signature of the file (e.g. "XISF0100").
     pub fn signature(&self) -> &str {
         &self.signature
     }
 
-    pub fn color_space(&self) -> &str {
-        &self.color_space
+    /// Gets the representable range of the pixel values (lower, upper), if the
+    /// image declares a valid one. Float images usually have "0:1" bounds.
+    pub fn bounds(&self) -> Option<(f64, f64)> {
+        self.bounds
+    }
+
+    /// Gets the pedestal added to all the pixel values (0 if not declared).
+    pub fn offset(&self) -> f64 {
+        self.offset
+    }
+
+    /// Gets the identifier (name) of the image, if declared.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_ref().map(|id| &id[..])
+    }
+
+    /// Gets the universally unique identifier of the image, if declared.
+    pub fn uuid(&self) -> Option<&str> {
+        self.uuid.as_ref().map(|uuid| &uuid[..])
+    }
+
+    /// Gets the checksum of the image data ("algorithm:digest"), empty if not declared.
+    pub fn checksum(&self) -> &str {
+        &self.checksum
+    }
+
+    /// Checks that the number of channels matches the color space (allowing an alpha
+    /// channel), e.g. that RGB images have three channels.
+    pub fn check_color_space(&self) -> Result<(), XisfError> {
+        let channels = self.geometry.channel_count();
+        let nominal = self.color_space.channel_count();
+        if channels == nominal || channels == nominal + 1 {
+            Ok(())
+        } else {
+            Err(XisfError::ColorSpaceMismatch {
+                color_space: self.color_space.to_string(),
+                channels,
+            })
+        }
+    }
+
+    /// Checks that the geometry is plausible: one-dimensional geometries must have the
+    /// channels of the color space (e.g. "256:256" would be a 1-D image of 256 channels,
+    /// most likely without its channel count), and the image data stored in the file must
+    /// have the size of the geometry.
+    pub fn check_geometry(&self) -> Result<(), XisfError> {
+        if self.geometry.dimensions().len() == 1 && self.check_color_space().is_err() {
+            return Err(XisfError::InvalidGeometry {
+                geometry: self.geometry.to_string(),
+            });
+        }
+        let length = if self.compression_codec.is_empty() {
+            self.location_length
+        } else {
+            self.compression_size as u64
+        };
+        let stored = &*self.location_method == "attachment" || &*self.location_method == "url";
+        match self.geometry.checked_size(self.sample_format.size()) {
+            Some(size) if stored && size as u64 != length => Err(XisfError::DataSizeMismatch {
+                length,
+                expected: size as u64,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Gets the attributes of the `Image` element that aren't recognized (name, value),
+    /// which may be XISF features not supported yet.
+    pub fn unknown_attributes(&self) -> &[(String, String)] {
+        &self.unknown_attributes
+    }
+
+    /// Checks that all the attributes of the `Image` element are recognized.
+    pub fn check_unknown_attributes(&self) -> Result<(), XisfError> {
+        if self.unknown_attributes.is_empty() {
+            Ok(())
+        } else {
+            Err(XisfError::UnknownAttributes {
+                element: "Image".to_owned(),
+                names: self
+                    .unknown_attributes
+                    .iter()
+                    .map(|(name, _)| name.clone())
+                    .collect(),
+            })
+        }
     }
 
+    /// Gets the `location` attribute of the image, as written in the file.
     pub fn location(&self) -> &str {
         &self.location
     }
 
+    /// Gets the method of the data location: `attachment`, `url` or `path`.
     pub fn location_method(&self) -> &str {
         &self.location_method
     }
 
+    /// URL of the file holding the image data, when `location_method` is `url`, or
+    /// path of the XISB file, when it's `path`.
+    pub fn location_url(&self) -> &str {
+        &self.location_url
+    }
+
+    /// Gets the `compression` attribute of the image, or an empty string if the image
+    /// data isn't compressed.
     pub fn compression(&self) -> &str {
         &self.compression
     }
 
+    /// Gets the codec of the compressed image data (e.g. `zlib+sh`).
     pub fn compression_codec(&self) -> &str {
         &self.compression_codec
     }
 
+    /// Gets the pattern of the color filter array (e.g. `RGGB`), if the file declares one.
+    pub fn cfa_pattern(&self) -> &str {
+        &self.cfa_pattern
+    }
+
+    /// Gets the name of the color filter array, if the file declares one.
+    pub fn cfa_source_pattern_name(&self) -> &str {
+        &self.cfa_source_pattern_name
+    }
+
+    /// Gets the color filter array (Bayer mosaic) of the image, if the file declares one.
+    pub fn color_filter_array(&self) -> Option<&XISFColorFilterArray> {
+        self.color_filter_array.as_ref()
+    }
+
+    /// Gets the size in bytes of a channel of the image.
     pub fn channel_size(&self) -> usize {
         self.geometry().channel_size() * self.sample_format().size()
     }
 
-    /// Print header data
-    fn print_info(&self) {
-        // Print header values
-        info!("Header signature: {}", self.signature());
+    /// Gets the number of channels of the image (the last element of its geometry).
+    pub fn geometry_channels(&self) -> usize {
+        self.geometry().channel_count()
+    }
 
-        info!("Length: {}", self.length());
-        info!("Reserved: {}", self.reserved());
+    /// Gets the sizes of the spatial dimensions of the image, without the channel count,
+    /// in FITS axis order (NAXIS1 = width, NAXIS2 = height, ...). XISF geometries are
+    /// also width first, so they're in the same order.
+    pub fn geometry_sizes(&self) -> Vec<u64> {
+        self.geometry()
+            .dimensions()
+            .iter()
+            .map(|&size| size as u64)
+            .collect()
+    }
 
-        info!("Geometry: {}", self.geometry());
-        info!("Geometry dimensions: {:?}", self.geometry().dimensions());
-        info!("Geometry channels: {}", self.geometry().channel_count());
-        info!("Geometry channel size: {}", self.geometry().channel_size());
-        info!("Sample format: {}", self.sample_format());
-        info!("Sample format bytes: {}", self.sample_format().size());
+    /// Gets the RGB working space of the image, if the file declares one.
+    pub fn rgb_working_space(&self) -> Option<&XISFRGBWorkingSpace> {
+        self.rgb_working_space.as_ref()
+    }
+
+    /// Gets the display function (screen stretch) of the image, if the file declares one.
+    pub fn display_function(&self) -> Option<&XISFDisplayFunction> {
+        self.display_function.as_ref()
+    }
+
+    /// Gets the resolution of the image, if the file declares a valid one.
+    pub fn resolution(&self) -> Option<&XISFResolution> {
+        self.resolution.as_ref()
+    }
+
+    /// Gets the location of the ICC profile (e.g. "attachment:4096:3024" or
+    /// "inline:base64"), empty if the image doesn't have one.
+    pub fn icc_profile_location(&self) -> &str {
+        &self.icc_profile_location
+    }
+
+    /// Gets the XISF properties of the file and the image.
+    pub fn properties(&self) -> &[XISFProperty] {
+        &self.properties
+    }
+
+    /// Gets the value of a XISF property by its identifier.
+    pub fn property(&self, id: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|property| property.id() == id)
+            .map(XISFProperty::value)
+    }
+
+    /// Builds the FITS HISTORY lines documenting the provenance of the XISF file.
+    pub fn history(&self) -> Vec<String> {
+        let mut history = vec![match self.property("XISF:CreatorApplication") {
+            Some(application) => format!("Converted from XISF created by {}", application),
+            None => "Converted from XISF".to_owned(),
+        }];
+        if let Some(creation_time) = self.property("XISF:CreationTime") {
+            history.push(format!("XISF creation time: {}", creation_time));
+        }
+        history
+    }
+
+    /// Builds the FITS keywords derived from the XISF header: image id and type, range
+    /// of float data, RGB working space, pixel size and Bayer pattern (from the
+    /// `ColorFilterArray` element or, when missing, the `cfaPattern` attribute).
+    pub fn fits_keywords(&self) -> Vec<FITSKeyword> {
+        let mut keywords = Vec::new();
+        if let Some(id) = self.id() {
+            keywords.push(FITSKeyword::string("OBJECT", id, "XISF image id"));
+        }
+        if let Some(uuid) = self.uuid() {
+            keywords.push(FITSKeyword::new(
+                "COMMENT",
+                "",
+                &format!("XISF image uuid {}", uuid),
+            ));
+        }
+        if let Some(frame) = self.image_type().fits_frame() {
+            keywords.push(FITSKeyword::string("IMAGETYP", frame, "Type of image"));
+        }
+        let float_bounds = self.bounds().filter(|_| self.sample_format().is_float());
+        if let Some((lower, upper)) = float_bounds {
+            keywords.push(FITSKeyword::new(
+                "DATAMIN",
+                &format!("{:?}", lower),
+                "Minimum data value (XISF bounds)",
+            ));
+            keywords.push(FITSKeyword::new(
+                "DATAMAX",
+                &format!("{:?}", upper),
+                "Maximum data value (XISF bounds)",
+            ));
+        }
+        if let Some(rgbws) = self.rgb_working_space() {
+            keywords.append(&mut rgbws.fits_keywords());
+        }
+        // 1-D images (e.g. spectra) have no height nor 2-D color filter arrays
+        let one_dimensional = self.geometry().dimensions().len() == 1;
+        if let Some(resolution) = self.resolution() {
+            keywords.extend(resolution.fits_keywords().into_iter().filter(|keyword| {
+                !one_dimensional || (keyword.name != "YPIXSZ" && keyword.name != "YRESOL")
+            }));
+        }
+        if one_dimensional {
+            if self.color_filter_array().is_some() || !self.cfa_pattern().is_empty() {
+                warn!("Ignoring the color filter array of a 1-D image");
+            }
+        } else if let Some(cfa) = self.color_filter_array() {
+            keywords.append(&mut cfa.fits_keywords());
+        } else if self.cfa_pattern().len() == 4 {
+            // 2x2 Bayer pattern (e.g. "RGGB"), written as the element would be
+            let cfa = XISFColorFilterArray {
+                pattern: self.cfa_pattern().into(),
+                width: 2,
+                height: 2,
+                name: self.cfa_source_pattern_name().into(),
+            };
+            keywords.append(&mut cfa.fits_keywords());
+        } else if !self.cfa_pattern().is_empty() {
+            keywords.push(FITSKeyword::string(
+                "BAYERPAT",
+                self.cfa_pattern(),
+                "Bayer color pattern",
+            ));
+        }
+        keywords
+    }
+
+    /// Print header data
+    fn print_info(&self) {
+        // Print header values
+        info!("Header signature: {}", self.signature());
+        info!("Version: {}.{}", self.version.0, self.version.1);
+        if let Some(id) = self.id() {
+            info!("Id: {}", id);
+        }
+        if let Some(uuid) = self.uuid() {
+            info!("UUID: {}", uuid);
+        }
+
+        info!("Length: {}", self.length());
+        info!("Reserved: {}", self.reserved());
+
+        info!("Geometry: {}", self.geometry());
+        info!("Geometry dimensions: {:?}", self.geometry().dimensions());
+        info!(
+            "Geometry shape: {} ({}-D{})",
+            self.geometry()
+                .dimensions()
+                .iter()
+                .map(usize::to_string)
+                .collect::<Vec<_>>()
+                .join(" x "),
+            self.geometry().dimensions().len(),
+            if self.geometry().dimensions().len() == 1 {
+                ", e.g. a spectrum"
+            } else {
+                ""
+            }
+        );
+        info!("Geometry channels: {}", self.geometry().channel_count());
+        info!("Geometry channel size: {}", self.geometry().channel_size());
+        info!("Sample format: {}", self.sample_format());
+        info!("Sample format bytes: {}", self.sample_format().size());
         info!("Color space: {}", self.color_space());
+        if let Some((lower, upper)) = self.bounds() {
+            info!("Bounds: {}:{}", lower, upper);
+        }
+        info!("Offset: {}", self.offset());
+        if !self.checksum().is_empty() {
+            info!("Checksum: {}", self.checksum());
+        }
         info!("Location: {}", self.location());
         info!("Location method: {}", self.location_method());
         info!("Location start: {}", self.location_start());
@@ -283,103 +1335,353 @@ impl XISFHeader {
             self.compression_codec(),
             self.compression_size()
         );
+        info!("Image type: {}", self.image_type());
+        if !self.cfa_pattern().is_empty() {
+            info!(
+                "CFA pattern: {} ({})",
+                self.cfa_pattern(),
+                self.cfa_source_pattern_name()
+            );
+        }
+        if let Some(cfa) = self.color_filter_array() {
+            info!("Color filter array: {}", cfa);
+        }
+        if let Some(rgbws) = self.rgb_working_space() {
+            info!("RGB working space: {}", rgbws);
+        }
+        if let Some(display_function) = self.display_function() {
+            info!("Display function: {}", display_function);
+        }
+        if let Some(resolution) = self.resolution() {
+            info!("Resolution: {}", resolution);
+        }
+        for property in self.properties().iter() {
+            info!("Property: {}", property);
+        }
+        for (name, value) in self.unknown_attributes().iter() {
+            info!("Unknown attribute: {}=\"{}\"", name, value);
+        }
     }
 }
 
 // Struct to read XISF header data
 #[derive(Debug, Default)]
 struct XISFHeaderReader {
+    mode: ParseMode,
     signature: String,
+    version: (u8, u8),
     length: u32,
     reserved: u32,
     geometry: XISFGeometry,
     sample_format: Option<XISFSampleFormat>,
-    color_space: String,
+    color_space: XISFColorSpace,
     location: String,
     location_method: String,
+    location_url: String,
     location_start: u64,
     location_length: u64,
+    location_index: u64,
     compression: String,
     compression_codec: String,
     compression_size: usize,
+    image_type: XISFImageType,
+    cfa_pattern: String,
+    cfa_source_pattern_name: String,
+    color_filter_array: Option<XISFColorFilterArray>,
+    rgb_working_space: Option<XISFRGBWorkingSpace>,
+    display_function: Option<XISFDisplayFunction>,
+    resolution: Option<XISFResolution>,
+    properties: Vec<XISFProperty>,
+    icc_profile_location: String,
+    icc_profile: Option<Vec<u8>>,
+    bounds: Option<(f64, f64)>,
+    offset: f64,
+    checksum: String,
+    id: Option<String>,
+    uuid: Option<String>,
+    unknown_attributes: Vec<(String, String)>,
+    thumbnail: Option<XISFImageReader>,
+}
+
+// Struct to read the attributes of secondary images (e.g. the thumbnail)
+#[derive(Debug, Default)]
+struct XISFImageReader {
+    geometry: Option<XISFGeometry>,
+    sample_format: Option<XISFSampleFormat>,
+    color_space: XISFColorSpace,
+    location: String,
+    compression: String,
 }
 
 impl XISFHeaderReader {
+    /// Report a deviation from the XISF specification (an error in strict mode).
+    fn deviation(&self, message: String) -> Result<(), XisfError> {
+        if self.mode == ParseMode::Strict {
+            Err(XisfError::SpecDeviation { message })
+        } else {
+            warn!("{}", message);
+            Ok(())
+        }
+    }
+
     /// Parse XISF's XML header and add it to this header information.
     fn fill_from_reader<R>(
         &mut self,
         reader: R,
         xisf_fits_keywords: &mut Vec<FITSKeyword>,
-    ) -> io::Result<()>
+    ) -> Result<(), XisfError>
     where
         R: BufRead,
     {
         // -- Parse XML Header
         // e.g. <Image geometry="256:256:1" sampleFormat="UInt8"
         //       colorSpace="Gray" location="attachment:4096:65536">
+        let mut reader = reader;
+        // Skip the UTF-8 byte order mark written by some XML writers
+        if reader.fill_buf()?.starts_with(UTF8_BOM) {
+            reader.consume(UTF8_BOM.len());
+        }
         let mut reader = Reader::from_reader(reader);
         let mut buf = Vec::new();
+        // Property whose value is being read from the element text
+        let mut text_property: Option<XISFProperty> = None;
+        // Whether the child elements of <DisplayFunction> are being read
+        let mut in_display_function = false;
+        // Display function parameter whose value is being read from the element text
+        let mut display_function_parameter: Option<Vec<u8>> = None;
+        // Whether the inline ICC profile is being read from the element text
+        let mut in_icc_profile = false;
+        // Whether the root element (</xisf>) has been closed
+        let mut root_closed = false;
 
         loop {
-            match reader.read_event(&mut buf) {
-                Ok(Event::Start(ref e)) => {
+            let event = reader.read_event(&mut buf);
+            // Self-closing elements (<Element/>) don't have children nor an end tag
+            let mut is_empty = false;
+            if let Ok(Event::Empty(_)) = event {
+                is_empty = true;
+            }
+            match event {
+                Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) => {
                     info!("<{}>", String::from_utf8_lossy(e.name()));
-                    match e.name() {
+                    // Element names are matched without namespace prefix (xisf:Image)
+                    match e.local_name() {
+                        name if in_display_function => {
+                            // Child element form of the display function parameters,
+                            // e.g. <m value="0.5"/> or <m>0.5</m>
+                            let display_function = self
+                                .display_function
+                                .get_or_insert_with(XISFDisplayFunction::default);
+                            let mut has_value = false;
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                if key == b"value" {
+                                    has_value = display_function.set_parameter(name, &value);
+                                }
+                            }
+                            if !has_value && !is_empty {
+                                display_function_parameter = Some(name.to_vec());
+                            }
+                        }
+                        b"xisf" => {
+                            // Check the namespace of the XISF elements (xmlns, or
+                            // xmlns:xisf for <xisf:xisf>)
+                            let prefix_length = e.name().len() - e.local_name().len();
+                            let mut namespace_key = b"xmlns".to_vec();
+                            if prefix_length > 0 {
+                                namespace_key.push(b':');
+                                namespace_key.extend_from_slice(&e.name()[..prefix_length - 1]);
+                            }
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                if key == namespace_key && value != XISF_NAMESPACE {
+                                    self.deviation(format!("Unknown XML namespace: {}", value))?;
+                                }
+                            }
+                        }
                         b"Image" => {
+                            // Resolution attributes (xResolution, yResolution, resolutionUnit)
+                            let mut x_resolution = String::new();
+                            let mut y_resolution = String::new();
+                            let mut resolution_unit = String::new();
+
                             // Parse and store <Image> tag attributes
-                            for attr in e.attributes() {
-                                let attr = attr.unwrap();
+                            for (key, value) in xml_attributes(e, &reader)? {
                                 info!(
                                     "<{} {}=\"{}\">",
                                     String::from_utf8_lossy(e.name()),
-                                    String::from_utf8_lossy(&attr.key),
-                                    String::from_utf8_lossy(&attr.value),
+                                    String::from_utf8_lossy(&key),
+                                    value,
                                 );
-                                match attr.key {
+                                match key.as_slice() {
                                     b"geometry" => {
                                         // Parse geometry string (dim1:...:dimN:channel-count)
-                                        self.geometry = attr.value.as_ref().try_into().unwrap();
-                                        // TODO: better error handling
+                                        self.geometry =
+                                            value.as_bytes().try_into().map_err(|e| match e {
+                                                XisfError::GeometryTooLarge { .. } => e,
+                                                _ => XisfParseError::Geometry {
+                                                    element: "Image".into(),
+                                                    attribute: "geometry".into(),
+                                                    value: value.clone(),
+                                                }
+                                                .into(),
+                                            })?;
                                     }
                                     b"sampleFormat" => {
                                         // Parse image format
-                                        self.sample_format = Some(
-                                            str::from_utf8(&attr.value).unwrap().parse().unwrap(),
-                                        );
+                                        match value.parse() {
+                                            Ok(sample_format) => {
+                                                self.sample_format = Some(sample_format);
+                                            }
+                                            Err(source) => {
+                                                return Err(XisfError::InvalidSampleFormat {
+                                                    element: "Image".to_owned(),
+                                                    source,
+                                                })
+                                            }
+                                        }
                                     }
                                     b"colorSpace" => {
-                                        // Parse space color
-                                        self.color_space =
-                                            str::from_utf8(&attr.value).unwrap().to_owned();
+                                        // Parse space color (Gray if not declared)
+                                        match value.parse() {
+                                            Ok(color_space) => self.color_space = color_space,
+                                            Err(_) => self.deviation(format!(
+                                                "Unknown color space: {}",
+                                                value
+                                            ))?,
+                                        }
                                     }
                                     b"location" => {
-                                        // Parse location. Format: "chan_size1:..:chan_size_n:n_channels" format
-                                        self.location =
-                                            str::from_utf8(&attr.value).unwrap().to_owned();
-                                        let split = self.location.split(':');
+                                        // Parse location. Format: "method:start:length",
+                                        // "url(URL):start:length" for data stored in other files,
+                                        // or "path(PATH):index-id" for blocks of XISB files
+                                        let location = value;
+                                        let mut positions = location.as_str();
+                                        if location.starts_with("path(") {
+                                            if let Some(end) = location.find(')') {
+                                                self.location_method = "path".to_owned();
+                                                self.location_url = location[5..end].to_owned();
+                                                self.location_index = parse_block_id(
+                                                    location[end + 1..].trim_start_matches(':'),
+                                                )?;
+                                                positions = "";
+                                            }
+                                        } else if location.starts_with("url(") {
+                                            if let Some(end) = location.find(')') {
+                                                self.location_method = "url".to_owned();
+                                                self.location_url = location[4..end].to_owned();
+                                                positions = &location[end + 1..];
+                                            }
+                                        }
+                                        let split = positions.split(':');
                                         for (n, s) in split.enumerate() {
                                             info!("Location part: {}", s);
                                             if n == 0 {
-                                                self.location_method = s.to_owned();
+                                                if self.location_method.is_empty() {
+                                                    self.location_method = s.to_owned();
+                                                }
                                             } else if n == 1 {
-                                                self.location_start = s.parse().unwrap();
+                                                self.location_start =
+                                                    parse_integer("Image", "location", s)?;
                                             } else if n == 2 {
                                                 // location_length = image data size (compressed)
-                                                self.location_length = s.parse().unwrap();
+                                                self.location_length =
+                                                    parse_integer("Image", "location", s)?;
                                             }
                                         }
+                                        self.location = location;
                                     }
                                     b"compression" => {
                                         // Parse compression. Format: "compression_algorithm:uncompressed-size"
-                                        self.compression =
-                                            str::from_utf8(&attr.value).unwrap().to_owned();
-                                        let mut iter = self.compression.split(':');
-
-                                        self.compression_codec = iter.next().unwrap().to_owned();
-                                        self.compression_size =
-                                            iter.next().unwrap().parse().unwrap();
+                                        let mut iter = value.split(':');
+
+                                        self.compression_codec =
+                                            iter.next().unwrap_or_default().to_owned();
+                                        self.compression_size = parse_integer(
+                                            "Image",
+                                            "compression",
+                                            iter.next().unwrap_or_default(),
+                                        )?;
+                                        self.compression = value;
+                                    }
+                                    b"bounds" => {
+                                        // Parse the range of the pixel values ("lower:upper")
+                                        self.bounds = parse_bounds(&value);
+                                        if self.bounds.is_none() {
+                                            self.deviation(format!("Invalid bounds: {}", value))?;
+                                        }
+                                    }
+                                    b"offset" => {
+                                        // Parse the pedestal added to the pixel values
+                                        match value.trim().parse() {
+                                            Ok(offset) => self.offset = offset,
+                                            Err(_) => self
+                                                .deviation(format!("Invalid offset: {}", value))?,
+                                        }
+                                    }
+                                    b"id" => {
+                                        // Identifier (name) of the image
+                                        let id = value;
+                                        if !is_valid_identifier(&id) {
+                                            self.deviation(format!("Invalid image id: {}", id))?;
+                                        }
+                                        self.id = Some(id);
+                                    }
+                                    b"uuid" => {
+                                        // Universally unique identifier of the image
+                                        self.uuid = Some(value);
+                                    }
+                                    b"checksum" => {
+                                        // Hash of the image data ("algorithm:digest")
+                                        self.checksum = value;
+                                    }
+                                    b"imageType" => {
+                                        // Parse the type of frame (light, dark, flat, bias)
+                                        self.image_type = match value.parse() {
+                                            Ok(image_type) => image_type,
+                                            Err(message) => {
+                                                // Unknown types are kept as they are
+                                                self.deviation(message)?;
+                                                XISFImageType::Other(value)
+                                            }
+                                        };
                                     }
-                                    _ => {} //name => eprintln!("unknown attribute name {}", name),
+                                    b"cfaPattern" => {
+                                        // Parse the Bayer pattern of the sensor (e.g. "RGGB")
+                                        self.cfa_pattern = value;
+                                    }
+                                    b"cfaSourcePatternName" => {
+                                        self.cfa_source_pattern_name = value;
+                                    }
+                                    b"xResolution" => {
+                                        x_resolution = value;
+                                    }
+                                    b"yResolution" => {
+                                        y_resolution = value;
+                                    }
+                                    b"resolutionUnit" => {
+                                        resolution_unit = value;
+                                    }
+                                    key => {
+                                        // Attributes not supported (yet), e.g. orientation
+                                        self.unknown_attributes.push((
+                                            String::from_utf8_lossy(key).into_owned(),
+                                            value,
+                                        ));
+                                    }
+                                }
+                            }
+
+                            // A <Resolution> child element, if any, replaces these values
+                            if !x_resolution.is_empty() || !y_resolution.is_empty() {
+                                self.resolution = XISFResolution::parse(
+                                    &x_resolution,
+                                    &y_resolution,
+                                    &resolution_unit,
+                                );
+                                if self.resolution.is_none() {
+                                    self.deviation(format!(
+                                        "Invalid resolution: {}:{} {}",
+                                        x_resolution, y_resolution, resolution_unit
+                                    ))?;
                                 }
                             }
                         }
@@ -387,11 +1689,8 @@ impl XISFHeaderReader {
                             // Parse and store the values of the FITS keyword
                             let mut xisf_fits_keyword = FITSKeyword::default();
 
-                            for attr in e.attributes() {
-                                let attr = attr.unwrap();
-
-                                let value = str::from_utf8(&attr.value).unwrap().to_owned();
-                                match attr.key {
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                match key.as_slice() {
                                     b"name" => {
                                         xisf_fits_keyword.name = value;
                                     }
@@ -411,14 +1710,216 @@ impl XISFHeaderReader {
                             );
                             xisf_fits_keywords.push(xisf_fits_keyword);
                         }
+                        b"ColorFilterArray" => {
+                            // Parse and store the color filter array
+                            let mut cfa = XISFColorFilterArray::default();
+
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                match key.as_slice() {
+                                    b"pattern" => cfa.pattern = value.into(),
+                                    b"width" => cfa.width = value.parse().unwrap_or(0),
+                                    b"height" => cfa.height = value.parse().unwrap_or(0),
+                                    b"name" => cfa.name = value.into(),
+                                    _ => {}
+                                }
+                            }
+
+                            info!("Color Filter Array: {}", cfa);
+                            self.color_filter_array = Some(cfa);
+                        }
+                        b"RGBWorkingSpace" => {
+                            // Parse and store the RGB working space parameters
+                            let mut rgbws = XISFRGBWorkingSpace::default();
+
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                match key.as_slice() {
+                                    b"gamma" => rgbws.gamma = value.into(),
+                                    b"x" => rgbws.x = parse_triplet(&value),
+                                    b"y" => rgbws.y = parse_triplet(&value),
+                                    b"Y" => rgbws.luminance = parse_triplet(&value),
+                                    b"name" => rgbws.name = value.into(),
+                                    _ => {}
+                                }
+                            }
+
+                            info!("RGB Working Space: {}", rgbws);
+                            self.rgb_working_space = Some(rgbws);
+                        }
+                        b"DisplayFunction" => {
+                            // Parse and store the display function parameters
+                            // (attribute form; the child element form is read below)
+                            let mut display_function = XISFDisplayFunction::default();
+
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                let _ = display_function.set_parameter(&key, &value);
+                            }
+
+                            if is_empty {
+                                info!("Display Function: {}", display_function);
+                            }
+                            in_display_function = !is_empty;
+                            self.display_function = Some(display_function);
+                        }
+                        b"Thumbnail" => {
+                            // Parse and store <Thumbnail> tag attributes
+                            let mut thumbnail = XISFImageReader::default();
+
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                match key.as_slice() {
+                                    b"geometry" => {
+                                        thumbnail.geometry = value.as_bytes().try_into().ok();
+                                    }
+                                    b"sampleFormat" => thumbnail.sample_format = value.parse().ok(),
+                                    b"colorSpace" => {
+                                        thumbnail.color_space = value.parse().unwrap_or_default();
+                                    }
+                                    b"location" => thumbnail.location = value.to_owned(),
+                                    b"compression" => thumbnail.compression = value.to_owned(),
+                                    _ => {}
+                                }
+                            }
+
+                            info!("Thumbnail: {:?}", thumbnail);
+                            self.thumbnail = Some(thumbnail);
+                        }
+                        b"ICCProfile" => {
+                            // Store the location of the ICC profile. Inline profiles are
+                            // encoded in the element text.
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                if key == b"location" {
+                                    self.icc_profile_location = value;
+                                }
+                            }
+                            info!("ICC profile location: {}", self.icc_profile_location);
+                            in_icc_profile = !is_empty;
+                        }
+                        b"Resolution" => {
+                            // Parse and store the image resolution
+                            let mut horizontal = String::new();
+                            let mut vertical = String::new();
+                            let mut unit = String::new();
+
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                match key.as_slice() {
+                                    b"horizontal" => horizontal = value,
+                                    b"vertical" => vertical = value,
+                                    b"unit" => unit = value,
+                                    _ => {}
+                                }
+                            }
+
+                            self.resolution = XISFResolution::parse(&horizontal, &vertical, &unit);
+                            match self.resolution {
+                                Some(ref resolution) => info!("Resolution: {}", resolution),
+                                None => self.deviation(format!(
+                                    "Invalid resolution: {}:{} {}",
+                                    horizontal, vertical, unit
+                                ))?,
+                            }
+                        }
+                        b"Property" => {
+                            // Parse and store the XISF property. Values are either stored
+                            // in the "value" attribute or as the text of the element.
+                            let mut property = XISFProperty::default();
+                            let mut has_value = false;
+
+                            for (key, value) in xml_attributes(e, &reader)? {
+                                match key.as_slice() {
+                                    b"id" => property.id = value.into(),
+                                    b"type" => property.property_type = value.into(),
+                                    b"value" => {
+                                        property.value = value.into();
+                                        has_value = true;
+                                    }
+                                    _ => {}
+                                }
+                            }
+
+                            // Empty elements have no text (nor end), their value is empty
+                            if has_value || is_empty {
+                                info!("Property: {}", property);
+                                self.properties.push(property);
+                            } else {
+                                text_property = Some(property);
+                            }
+                        }
                         tag => debug!("unknown tag {}", String::from_utf8_lossy(tag)),
                     }
                 }
-                Ok(Event::Eof) => break, // exits the loop when reaching end of file
-                Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+                Ok(Event::Text(ref e)) => {
+                    let text = e
+                        .unescape_and_decode(&reader)
+                        .map_err(|source| XisfError::Xml {
+                            position: reader.buffer_position(),
+                            source,
+                        })?;
+                    if let Some(ref mut property) = text_property {
+                        property.value = text.into();
+                    } else if in_icc_profile {
+                        self.icc_profile =
+                            xisf_decode_inline(&self.icc_profile_location, text.trim());
+                    } else if let Some(name) = display_function_parameter.take() {
+                        if let Some(ref mut display_function) = self.display_function {
+                            let _ = display_function.set_parameter(&name, text.trim());
+                        }
+                    }
+                }
+                Ok(Event::End(ref e)) => match e.local_name() {
+                    b"Property" => {
+                        if let Some(property) = text_property.take() {
+                            info!("Property: {}", property);
+                            self.properties.push(property);
+                        }
+                    }
+                    b"ICCProfile" => in_icc_profile = false,
+                    b"xisf" => root_closed = true,
+                    b"DisplayFunction" => {
+                        in_display_function = false;
+                        if let Some(ref display_function) = self.display_function {
+                            info!("Display Function: {}", display_function);
+                        }
+                    }
+                    _ => display_function_parameter = None,
+                },
+                Ok(Event::Decl(ref e)) => {
+                    // XML declaration (<?xml version="1.0" encoding="UTF-8"?>)
+                    if let Some(Ok(encoding)) = e.encoding() {
+                        if !encoding.eq_ignore_ascii_case(b"UTF-8") {
+                            self.deviation(format!(
+                                "Unsupported XML encoding: {}",
+                                String::from_utf8_lossy(&encoding)
+                            ))?;
+                        }
+                    }
+                }
+                Ok(Event::Comment(ref e)) => {
+                    debug!("Comment: {}", String::from_utf8_lossy(e));
+                }
+                Ok(Event::PI(ref e)) => {
+                    debug!("Processing instruction: {}", String::from_utf8_lossy(e));
+                }
+                Ok(Event::Eof) => {
+                    // A document without the end of the root element has been cut
+                    return Err(XisfError::TruncatedHeader {
+                        read: reader.buffer_position(),
+                        length: self.length,
+                    });
+                }
+                Err(source) => {
+                    return Err(XisfError::Xml {
+                        position: reader.buffer_position(),
+                        source,
+                    })
+                }
                 Ok(_) => (), // There are several other `Event`s we do not consider here
             }
 
+            // Whatever follows the root element is padding up to the image data (e.g. to a
+            // block boundary), which may not even be text
+            if root_closed {
+                break;
+            }
+
             // if we don't keep a borrow elsewhere, we can clear the buffer to keep memory usage low
             buf.clear();
         }
@@ -426,38 +1927,109 @@ impl XISFHeaderReader {
         Ok(())
     }
 
-    /// Builds the final header.
-    fn build(self) -> XISFHeader {
-        XISFHeader {
+    /// Builds the final header. Fails if no `<Image>` element declared a sample format.
+    fn build(self) -> Result<XISFHeader, XisfError> {
+        let sample_format = self.sample_format.ok_or(XisfError::MissingSampleFormat)?;
+        // Parsed geometries have at least one channel
+        if self.geometry.channel_count() == 0 {
+            return Err(XisfError::MissingGeometry);
+        }
+        // The size of the image data must be addressable
+        if self.geometry.checked_size(sample_format.size()).is_none() {
+            return Err(XisfError::GeometryTooLarge {
+                geometry: self.geometry.to_string(),
+            });
+        }
+        Ok(XISFHeader {
             signature: self.signature.into_boxed_str(),
+            version: self.version,
             length: self.length,
             reserved: self.reserved,
             geometry: self.geometry,
-            sample_format: self.sample_format.unwrap(), // TODO: proper error handling
-            color_space: self.color_space.into_boxed_str(),
+            sample_format,
+            color_space: self.color_space,
             location: self.location.into_boxed_str(),
             location_method: self.location_method.into_boxed_str(),
+            location_url: self.location_url.into_boxed_str(),
             location_start: self.location_start,
             location_length: self.location_length,
+            location_index: self.location_index,
             compression: self.compression.into_boxed_str(),
             compression_codec: self.compression_codec.into_boxed_str(),
             compression_size: self.compression_size,
-        }
+            image_type: self.image_type,
+            cfa_pattern: self.cfa_pattern.into_boxed_str(),
+            cfa_source_pattern_name: self.cfa_source_pattern_name.into_boxed_str(),
+            color_filter_array: self.color_filter_array,
+            rgb_working_space: self.rgb_working_space,
+            display_function: self.display_function,
+            resolution: self.resolution,
+            properties: self.properties.into_boxed_slice(),
+            icc_profile_location: self.icc_profile_location.into_boxed_str(),
+            bounds: self.bounds,
+            offset: self.offset,
+            checksum: self.checksum.into_boxed_str(),
+            id: self.id.map(String::into_boxed_str),
+            uuid: self.uuid.map(String::into_boxed_str),
+            unknown_attributes: self.unknown_attributes.into_boxed_slice(),
+        })
     }
 }
 
-// Image data as a vector
-#[derive(Debug, Clone)]
+// Conversion of the image data of a sample format to and from the interleaved
+// channel order (RGBRGB...)
+macro_rules! interleaved {
+    ($to_name:ident, $from_name:ident, $variant:ident, $type:ty) => {
+        /// Gets the samples with the channels interleaved (RGBRGB...) instead of one
+        /// after another (RRRR...GGGG...BBBB), `None` for data of other sample formats.
+        pub fn $to_name(&self) -> Option<Vec<$type>> {
+            match self {
+                Self::$variant(data) => Some(convert::interleave(&data.concat(), data.len())),
+                _ => None,
+            }
+        }
+
+        /// Builds the image data of `channels` channels from interleaved samples (RGBRGB...).
+        pub fn $from_name(data: &[$type], channels: usize) -> Self {
+            if channels == 0 {
+                return Self::Empty;
+            }
+            Self::$variant(
+                convert::deinterleave(data, channels)
+                    .into_iter()
+                    .map(Vec::into_boxed_slice)
+                    .collect(),
+            )
+        }
+    };
+}
+
+/// Image data as a vector of channels, with the samples of each channel
+#[derive(Debug, Clone, PartialEq)]
 pub enum XISFData {
+    /// No image data (not read yet).
     Empty,
+    /// 8 bits unsigned integer samples.
     UInt8(Box<[Box<[u8]>]>),
+    /// 16 bits unsigned integer samples.
     UInt16(Box<[Box<[u16]>]>),
+    /// 32 bits unsigned integer samples.
     UInt32(Box<[Box<[u32]>]>),
+    /// 8 bits signed integer samples.
+    Int8(Box<[Box<[i8]>]>),
+    /// 16 bits signed integer samples.
+    Int16(Box<[Box<[i16]>]>),
+    /// 32 bits signed integer samples.
+    Int32(Box<[Box<[i32]>]>),
     // UInt64(Box<[Box<[u64]>]>),
+    /// 32 bits floating point samples.
     Float32(Box<[Box<[f32]>]>),
+    /// 64 bits floating point samples.
     Float64(Box<[Box<[f64]>]>),
-    // Complex32(Box<[Box<[Complex32]>]>),
-    // Complex64(Box<[Box<[Complex64]>]>),
+    /// Complex samples with 32 bits floating point parts.
+    Complex32(Box<[Box<[Complex32]>]>),
+    /// Complex samples with 64 bits floating point parts.
+    Complex64(Box<[Box<[Complex64]>]>),
 }
 
 impl XISFData {
@@ -468,104 +2040,1226 @@ impl XISFData {
             Self::UInt8(_) => Some(XISFSampleFormat::UInt8),
             Self::UInt16(_) => Some(XISFSampleFormat::UInt16),
             Self::UInt32(_) => Some(XISFSampleFormat::UInt32),
+            Self::Int8(_) => Some(XISFSampleFormat::Int8),
+            Self::Int16(_) => Some(XISFSampleFormat::Int16),
+            Self::Int32(_) => Some(XISFSampleFormat::Int32),
             // Self::UInt64(_) => Some(XISFSampleFormat::UInt64),
             Self::Float32(_) => Some(XISFSampleFormat::Float32),
             Self::Float64(_) => Some(XISFSampleFormat::Float64),
-            // Self::Complex32(_) => Some(XISFSampleFormat::Complex32),
-            // Self::Complex64(_) => Some(XISFSampleFormat::Complex64),
+            Self::Complex32(_) => Some(XISFSampleFormat::Complex32),
+            Self::Complex64(_) => Some(XISFSampleFormat::Complex64),
         }
     }
-}
 
-impl Default for XISFData {
-    fn default() -> Self {
-        Self::Empty
+    /// Counts the floating point samples outside of the bounds (lower, upper).
+    /// Integer data is never out of bounds.
+    pub fn count_out_of_bounds(&self, bounds: (f64, f64)) -> usize {
+        let (lower, upper) = bounds;
+        let outside = |value: f64| value < lower || value > upper;
+        match self {
+            Self::Float32(data) => data
+                .iter()
+                .flat_map(|channel| channel.iter())
+                .filter(|value| outside(f64::from(**value)))
+                .count(),
+            Self::Float64(data) => data
+                .iter()
+                .flat_map(|channel| channel.iter())
+                .filter(|value| outside(**value))
+                .count(),
+            _ => 0,
+        }
     }
-}
 
-/// Image geometry information for a XISF file.
-///
-/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_Image_Core_Element_:_Mandatory_Image_Attributes__)
-#[derive(Debug, Clone, Default)]
-pub struct XISFGeometry {
-    dimensions: Box<[usize]>,
-    channel_count: usize,
-}
+    /// Computes the statistics of the samples of every channel (`None` for channels
+    /// without samples). Complex samples use their magnitude, and NaN samples are skipped.
+    pub fn channel_stats(&self) -> Vec<Option<XISFChannelStats>> {
+        fn stats<T: Copy>(
+            data: &[Box<[T]>],
+            value: impl Fn(T) -> f64,
+        ) -> Vec<Option<XISFChannelStats>> {
+            data.iter()
+                .map(|channel| {
+                    XISFChannelStats::from_values(channel.iter().map(|v| value(*v)).collect())
+                })
+                .collect()
+        }
+        match self {
+            Self::Empty => Vec::new(),
+            Self::UInt8(data) => stats(data, f64::from),
+            Self::UInt16(data) => stats(data, f64::from),
+            Self::UInt32(data) => stats(data, f64::from),
+            Self::Int8(data) => stats(data, f64::from),
+            Self::Int16(data) => stats(data, f64::from),
+            Self::Int32(data) => stats(data, f64::from),
+            Self::Float32(data) => stats(data, f64::from),
+            Self::Float64(data) => stats(data, |value| value),
+            Self::Complex32(data) => {
+                stats(data, |value| f64::from(value.re).hypot(f64::from(value.im)))
+            }
+            Self::Complex64(data) => stats(data, |value| value.re.hypot(value.im)),
+        }
+    }
 
-impl XISFGeometry {
-    pub fn dimensions(&self) -> &[usize] {
-        &self.dimensions
+    /// Keeps only the given channels, in the given order. Channels out of range are empty.
+    pub fn select_channels(&mut self, channels: &[usize]) {
+        fn select<T: Clone>(data: &mut Box<[Box<[T]>]>, channels: &[usize]) {
+            let mut all: Vec<Option<Box<[T]>>> = mem::replace(data, Box::new([]))
+                .into_vec()
+                .into_iter()
+                .map(Some)
+                .collect();
+            // The last use of each channel moves it, the others copy it
+            let selected: Vec<Box<[T]>> = channels
+                .iter()
+                .enumerate()
+                .map(|(n, &channel)| {
+                    let slot = all.get_mut(channel);
+                    if channels[n + 1..].contains(&channel) {
+                        slot.and_then(|slot| slot.clone()).unwrap_or_default()
+                    } else {
+                        slot.and_then(Option::take).unwrap_or_default()
+                    }
+                })
+                .collect();
+            *data = selected.into_boxed_slice();
+        }
+        match self {
+            Self::Empty => {}
+            Self::UInt8(data) => select(data, channels),
+            Self::UInt16(data) => select(data, channels),
+            Self::UInt32(data) => select(data, channels),
+            Self::Int8(data) => select(data, channels),
+            Self::Int16(data) => select(data, channels),
+            Self::Int32(data) => select(data, channels),
+            Self::Float32(data) => select(data, channels),
+            Self::Float64(data) => select(data, channels),
+            Self::Complex32(data) => select(data, channels),
+            Self::Complex64(data) => select(data, channels),
+        }
     }
 
-    pub fn channel_count(&self) -> usize {
-        self.channel_count
+    /// Gets a copy of a channel, as data with a single channel, or `None` if there's
+    /// no such channel.
+    pub fn channel(&self, channel: usize) -> Option<Self> {
+        fn copy<T: Clone>(data: &[Box<[T]>], channel: usize) -> Option<Box<[Box<[T]>]>> {
+            data.get(channel)
+                .map(|samples| vec![samples.clone()].into_boxed_slice())
+        }
+        match self {
+            Self::Empty => None,
+            Self::UInt8(data) => copy(data, channel).map(Self::UInt8),
+            Self::UInt16(data) => copy(data, channel).map(Self::UInt16),
+            Self::UInt32(data) => copy(data, channel).map(Self::UInt32),
+            Self::Int8(data) => copy(data, channel).map(Self::Int8),
+            Self::Int16(data) => copy(data, channel).map(Self::Int16),
+            Self::Int32(data) => copy(data, channel).map(Self::Int32),
+            Self::Float32(data) => copy(data, channel).map(Self::Float32),
+            Self::Float64(data) => copy(data, channel).map(Self::Float64),
+            Self::Complex32(data) => copy(data, channel).map(Self::Complex32),
+            Self::Complex64(data) => copy(data, channel).map(Self::Complex64),
+        }
     }
 
-    pub fn channel_size(&self) -> usize {
-        self.dimensions.iter().product()
+    /// Subtracts `offset` from all the samples. Integer samples are rounded and
+    /// clipped to the range of their type.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn subtract_offset(&mut self, offset: f64) {
+        let clip = |value: f64, min: f64, max: f64| (value - offset).round().max(min).min(max);
+        match self {
+            Self::Empty => {}
+            Self::UInt8(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    *value = clip(f64::from(*value), 0.0, f64::from(u8::MAX)) as u8;
+                }
+            }
+            Self::UInt16(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    *value = clip(f64::from(*value), 0.0, f64::from(u16::MAX)) as u16;
+                }
+            }
+            Self::UInt32(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    *value = clip(f64::from(*value), 0.0, f64::from(u32::MAX)) as u32;
+                }
+            }
+            Self::Int8(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    let (min, max) = (f64::from(i8::MIN), f64::from(i8::MAX));
+                    *value = clip(f64::from(*value), min, max) as i8;
+                }
+            }
+            Self::Int16(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    let (min, max) = (f64::from(i16::MIN), f64::from(i16::MAX));
+                    *value = clip(f64::from(*value), min, max) as i16;
+                }
+            }
+            Self::Int32(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    let (min, max) = (f64::from(i32::MIN), f64::from(i32::MAX));
+                    *value = clip(f64::from(*value), min, max) as i32;
+                }
+            }
+            Self::Float32(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    *value = (f64::from(*value) - offset) as f32;
+                }
+            }
+            Self::Float64(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    *value -= offset;
+                }
+            }
+            // The offset is a real number, only the real part changes
+            Self::Complex32(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    value.re = (f64::from(value.re) - offset) as f32;
+                }
+            }
+            Self::Complex64(data) => {
+                for value in data.iter_mut().flat_map(|channel| channel.iter_mut()) {
+                    value.re -= offset;
+                }
+            }
+        }
     }
-}
 
-impl TryFrom<&[u8]> for XISFGeometry {
-    type Error = &'static str;
+    interleaved!(to_interleaved_u8, from_interleaved_u8, UInt8, u8);
+    interleaved!(to_interleaved_u16, from_interleaved_u16, UInt16, u16);
+    interleaved!(to_interleaved_u32, from_interleaved_u32, UInt32, u32);
+    interleaved!(to_interleaved_i8, from_interleaved_i8, Int8, i8);
+    interleaved!(to_interleaved_i16, from_interleaved_i16, Int16, i16);
+    interleaved!(to_interleaved_i32, from_interleaved_i32, Int32, i32);
+    interleaved!(to_interleaved_f32, from_interleaved_f32, Float32, f32);
+    interleaved!(to_interleaved_f64, from_interleaved_f64, Float64, f64);
+    interleaved!(
+        to_interleaved_complex32,
+        from_interleaved_complex32,
+        Complex32,
+        Complex32
+    );
+    interleaved!(
+        to_interleaved_complex64,
+        from_interleaved_complex64,
+        Complex64,
+        Complex64
+    );
+}
 
-    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let mut iter = value.split(|b| *b == b':');
-
-        let channel_count = iter
-            .next_back()
-            .map(str::from_utf8)
-            .unwrap()
-            .unwrap()
-            .parse()
-            .unwrap(); // TODO: better error handling
-        let dimensions: Vec<_> = iter
-            .map(str::from_utf8)
-            .map(|dim| dim.unwrap().parse::<usize>().unwrap())
-            .collect();
+/// Statistics of the samples of a channel.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XISFChannelStats {
+    /// Minimum sample.
+    pub min: f64,
+    /// Maximum sample.
+    pub max: f64,
+    /// Mean of the samples.
+    pub mean: f64,
+    /// Median of the samples.
+    pub median: f64,
+    /// Standard deviation (of the population).
+    pub stddev: f64,
+}
 
-        Ok(Self {
-            dimensions: dimensions.into_boxed_slice(),
-            channel_count,
+impl XISFChannelStats {
+    /// Computes the statistics of the values (`None` if there are no values but NaN).
+    #[allow(clippy::cast_precision_loss)]
+    fn from_values(mut values: Vec<f64>) -> Option<Self> {
+        values.retain(|value| !value.is_nan());
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = values.len();
+        let mean = values.iter().sum::<f64>() / count as f64;
+        let variance = values
+            .iter()
+            .map(|value| (value - mean) * (value - mean))
+            .sum::<f64>()
+            / count as f64;
+        // The median of an even number of values is the mean of the two middle ones
+        let middle = &values[(count - 1) / 2..=count / 2];
+        let median = middle.iter().sum::<f64>() / middle.len() as f64;
+        Some(XISFChannelStats {
+            min: values[0],
+            max: values[count - 1],
+            mean,
+            median,
+            stddev: variance.sqrt(),
         })
     }
 }
 
-impl fmt::Display for XISFGeometry {
+impl fmt::Display for XISFChannelStats {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for dim in self.dimensions.iter() {
-            write!(f, "{}:", dim)?;
-        }
-        write!(f, "{}", self.channel_count)
+        write!(
+            f,
+            "min {}, max {}, mean {:.4}, median {:.4}, stddev {:.4}",
+            self.min, self.max, self.mean, self.median, self.stddev
+        )
     }
 }
 
-/// Enumeration with the different XISF sample formats
+/// Complex sample of the Complex32 and Complex64 sample formats.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Complex<T> {
+    /// Real part.
+    pub re: T,
+    /// Imaginary part.
+    pub im: T,
+}
+
+/// Complex sample with 32 bits floating point parts.
+pub type Complex32 = Complex<f32>;
+/// Complex sample with 64 bits floating point parts.
+pub type Complex64 = Complex<f64>;
+
+/// Secondary image stored in a XISF file, such as the thumbnail.
 ///
-/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#sampleformat_image_attribute)
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum XISFSampleFormat {
-    UInt8,
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_Thumbnail_Core_Element__)
+#[derive(Debug, Clone)]
+pub struct XISFImage {
+    geometry: XISFGeometry,
+    sample_format: XISFSampleFormat,
+    color_space: XISFColorSpace,
+    data: XISFData,
+}
+
+impl XISFImage {
+    /// Gets the geometry of the image.
+    pub fn geometry(&self) -> &XISFGeometry {
+        &self.geometry
+    }
+
+    /// Gets the sample format of the image.
+    pub fn sample_format(&self) -> XISFSampleFormat {
+        self.sample_format
+    }
+
+    /// Gets the color space of the image.
+    pub fn color_space(&self) -> XISFColorSpace {
+        self.color_space
+    }
+
+    /// Gets the image data.
+    pub fn data(&self) -> &XISFData {
+        &self.data
+    }
+}
+
+impl fmt::Display for XISFImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.geometry, self.sample_format, self.color_space
+        )
+    }
+}
+
+impl Default for XISFData {
+    fn default() -> Self {
+        Self::Empty
+    }
+}
+
+/// Image geometry information for a XISF file.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_Image_Core_Element_:_Mandatory_Image_Attributes__)
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct XISFGeometry {
+    dimensions: Box<[usize]>,
+    channel_count: usize,
+}
+
+impl XISFGeometry {
+    /// Gets the size of each dimension of the image (width, height...).
+    pub fn dimensions(&self) -> &[usize] {
+        &self.dimensions
+    }
+
+    /// Gets the number of channels of the image.
+    pub fn channel_count(&self) -> usize {
+        self.channel_count
+    }
+
+    /// Gets the number of samples of a channel (the product of the dimensions).
+    pub fn channel_size(&self) -> usize {
+        self.dimensions.iter().product()
+    }
+
+    /// Gets the size in bytes of the image (all the channels) with samples of
+    /// `sample_size` bytes, or `None` if it overflows.
+    pub fn checked_size(&self, sample_size: usize) -> Option<usize> {
+        self.dimensions
+            .iter()
+            .try_fold(self.channel_count, |size, dim| size.checked_mul(*dim))
+            .and_then(|size| size.checked_mul(sample_size))
+    }
+}
+
+impl TryFrom<&[u8]> for XISFGeometry {
+    type Error = XisfError;
+
+    /// Parses a geometry ("dim1:...:dimN:channel-count"). All the values must be
+    /// positive integers, with at least one dimension, and the number of samples
+    /// must fit in a `usize`.
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let invalid = || XisfError::InvalidGeometry {
+            geometry: String::from_utf8_lossy(value).into_owned(),
+        };
+        let values = str::from_utf8(value)
+            .map_err(|_| invalid())?
+            .split(':')
+            .map(|part| part.trim().parse::<usize>().ok().filter(|n| *n > 0))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+
+        let (channel_count, dimensions) = match values.split_last() {
+            Some((channel_count, dimensions)) if !dimensions.is_empty() => {
+                (*channel_count, dimensions.to_vec())
+            }
+            _ => return Err(invalid()),
+        };
+
+        let geometry = Self {
+            dimensions: dimensions.into_boxed_slice(),
+            channel_count,
+        };
+        if geometry.checked_size(1).is_none() {
+            return Err(XisfError::GeometryTooLarge {
+                geometry: String::from_utf8_lossy(value).into_owned(),
+            });
+        }
+        Ok(geometry)
+    }
+}
+
+impl fmt::Display for XISFGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for dim in self.dimensions.iter() {
+            write!(f, "{}:", dim)?;
+        }
+        write!(f, "{}", self.channel_count)
+    }
+}
+
+/// Color space of a XISF image. Images without a color space are grayscale.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#colorspace_image_attribute)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XISFColorSpace {
+    /// Grayscale (one channel).
+    Gray,
+    /// RGB (three channels).
+    Rgb,
+    /// CIE L*a*b* (three channels).
+    CieLab,
+}
+
+impl XISFColorSpace {
+    /// Gets the XISF color space as a string.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Gray => "Gray",
+            Self::Rgb => "RGB",
+            Self::CieLab => "CIELab",
+        }
+    }
+
+    /// Gets the number of channels of the color space, without alpha channels.
+    pub fn channel_count(self) -> usize {
+        match self {
+            Self::Gray => 1,
+            Self::Rgb | Self::CieLab => 3,
+        }
+    }
+}
+
+impl Default for XISFColorSpace {
+    fn default() -> Self {
+        Self::Gray
+    }
+}
+
+impl fmt::Display for XISFColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl str::FromStr for XISFColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Gray" => Ok(Self::Gray),
+            "RGB" => Ok(Self::Rgb),
+            "CIELab" => Ok(Self::CieLab),
+            _ => Err(format!("unknown color space: {}", s)),
+        }
+    }
+}
+
+/// Type of a XISF image, used to distinguish light frames from calibration frames.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#imagetype_image_attribute)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum XISFImageType {
+    /// Light frame.
+    Light,
+    /// Dark frame.
+    Dark,
+    /// Flat field frame.
+    Flat,
+    /// Bias frame.
+    Bias,
+    /// Integrated light frames.
+    MasterLight,
+    /// Integrated dark frames.
+    MasterDark,
+    /// Integrated flat field frames.
+    MasterFlat,
+    /// Integrated bias frames.
+    MasterBias,
+    /// Map of defective pixels.
+    DefectMap,
+    /// Map of the pixels rejected above the estimated value.
+    RejectionMapHigh,
+    /// Map of the pixels rejected below the estimated value.
+    RejectionMapLow,
+    /// Binary map of the pixels rejected above the estimated value.
+    BinaryRejectionMapHigh,
+    /// Binary map of the pixels rejected below the estimated value.
+    BinaryRejectionMapLow,
+    /// Map of the slopes of a pixel fit.
+    SlopeMap,
+    /// Map of the weights of the pixels.
+    WeightMap,
+    /// Image types not defined by the XISF specification.
+    Other(String),
+    /// The image doesn't declare its type.
+    Unknown,
+}
+
+impl XISFImageType {
+    /// Gets the XISF image type as a string.
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::Light => "Light",
+            Self::Dark => "Dark",
+            Self::Flat => "Flat",
+            Self::Bias => "Bias",
+            Self::MasterLight => "MasterLight",
+            Self::MasterDark => "MasterDark",
+            Self::MasterFlat => "MasterFlat",
+            Self::MasterBias => "MasterBias",
+            Self::DefectMap => "DefectMap",
+            Self::RejectionMapHigh => "RejectionMapHigh",
+            Self::RejectionMapLow => "RejectionMapLow",
+            Self::BinaryRejectionMapHigh => "BinaryRejectionMapHigh",
+            Self::BinaryRejectionMapLow => "BinaryRejectionMapLow",
+            Self::SlopeMap => "SlopeMap",
+            Self::WeightMap => "WeightMap",
+            Self::Other(image_type) => image_type,
+            Self::Unknown => "Unknown",
+        }
+    }
+
+    /// Gets the value of the IMAGETYP FITS keyword (IRAF convention) for the image type.
+    /// Maps and unknown types have no FITS equivalent.
+    pub fn fits_frame(&self) -> Option<&'static str> {
+        match self {
+            Self::Light => Some("Light Frame"),
+            Self::Dark => Some("Dark Frame"),
+            Self::Flat => Some("Flat Field"),
+            Self::Bias => Some("Bias Frame"),
+            Self::MasterLight => Some("Master Light"),
+            Self::MasterDark => Some("Master Dark"),
+            Self::MasterFlat => Some("Master Flat"),
+            Self::MasterBias => Some("Master Bias"),
+            _ => None,
+        }
+    }
+}
+
+impl Default for XISFImageType {
+    fn default() -> Self {
+        Self::Unknown
+    }
+}
+
+impl fmt::Display for XISFImageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl str::FromStr for XISFImageType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Light" => Ok(Self::Light),
+            "Dark" => Ok(Self::Dark),
+            "Flat" => Ok(Self::Flat),
+            "Bias" => Ok(Self::Bias),
+            "MasterLight" => Ok(Self::MasterLight),
+            "MasterDark" => Ok(Self::MasterDark),
+            "MasterFlat" => Ok(Self::MasterFlat),
+            "MasterBias" => Ok(Self::MasterBias),
+            "DefectMap" => Ok(Self::DefectMap),
+            "RejectionMapHigh" => Ok(Self::RejectionMapHigh),
+            "RejectionMapLow" => Ok(Self::RejectionMapLow),
+            "BinaryRejectionMapHigh" => Ok(Self::BinaryRejectionMapHigh),
+            "BinaryRejectionMapLow" => Ok(Self::BinaryRejectionMapLow),
+            "SlopeMap" => Ok(Self::SlopeMap),
+            "WeightMap" => Ok(Self::WeightMap),
+            _ => Err(format!("unknown image type: {}", s)),
+        }
+    }
+}
+
+/// Color filter array (CFA) of a XISF image taken with a one-shot color
+/// camera, such as a Bayer mosaic.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_ColorFilterArray_Core_Element__)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XISFColorFilterArray {
+    pattern: Box<str>,
+    width: usize,
+    height: usize,
+    name: Box<str>,
+}
+
+impl XISFColorFilterArray {
+    /// Gets the pattern of the filter elements, row by row (e.g. "RGGB").
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Gets the width of the pattern, in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Gets the height of the pattern, in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Gets the name of the color filter array.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Builds the FITS keywords describing the pattern. 2x2 Bayer patterns use
+    /// the de-facto standard `BAYERPAT`, `XBAYROFF` and `YBAYROFF` keywords;
+    /// other patterns (such as X-Trans) are only documented in a COMMENT.
+    pub fn fits_keywords(&self) -> Vec<FITSKeyword> {
+        if self.width == 2 && self.height == 2 {
+            vec![
+                FITSKeyword::string("BAYERPAT", &self.pattern, "Bayer color pattern"),
+                FITSKeyword::new("XBAYROFF", "0", "X offset of Bayer array"),
+                FITSKeyword::new("YBAYROFF", "0", "Y offset of Bayer array"),
+            ]
+        } else {
+            warn!(
+                "Color filter array {}x{} isn't a Bayer pattern, written as COMMENT",
+                self.width, self.height
+            );
+            vec![FITSKeyword {
+                name: "COMMENT".to_owned(),
+                value: String::new(),
+                comment: format!(
+                    "XISF color filter array {}x{}: {}",
+                    self.width, self.height, self.pattern
+                ),
+            }]
+        }
+    }
+}
+
+impl fmt::Display for XISFColorFilterArray {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}x{} name={}",
+            self.pattern, self.width, self.height, self.name
+        )
+    }
+}
+
+/// RGB working space of a XISF image: gamma, chromaticity coordinates of the
+/// RGB primaries and luminance coefficients.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_RGBWorkingSpace_Core_Element__)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XISFRGBWorkingSpace {
+    gamma: Box<str>,
+    x: [f64; 3],
+    y: [f64; 3],
+    luminance: [f64; 3],
+    name: Box<str>,
+}
+
+impl XISFRGBWorkingSpace {
+    /// Gets the gamma, either a number or "sRGB" for the sRGB transfer function.
+    pub fn gamma(&self) -> &str {
+        &self.gamma
+    }
+
+    /// Whether the working space uses the sRGB transfer function instead of a gamma value.
+    pub fn is_srgb(&self) -> bool {
+        self.gamma.eq_ignore_ascii_case("sRGB")
+    }
+
+    /// Gets the x chromaticity coordinates of the red, green and blue primaries.
+    pub fn x(&self) -> [f64; 3] {
+        self.x
+    }
+
+    /// Gets the y chromaticity coordinates of the red, green and blue primaries.
+    pub fn y(&self) -> [f64; 3] {
+        self.y
+    }
+
+    /// Gets the luminance coefficients of the red, green and blue primaries.
+    pub fn luminance(&self) -> [f64; 3] {
+        self.luminance
+    }
+
+    /// Gets the name of the working space.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Builds the FITS keywords storing the working space (`RGBWS_GA`, `RGBWS_SR`,
+    /// `RGBWS_X1`...).
+    pub fn fits_keywords(&self) -> Vec<FITSKeyword> {
+        let mut keywords = Vec::with_capacity(12);
+        // Other gammas (e.g. "sRGB" or "inf") are written as strings
+        match self.gamma.parse::<f64>() {
+            Ok(gamma) if gamma.is_finite() => {
+                keywords.push(FITSKeyword::real("RGBWS_GA", gamma, "RGBWS gamma"));
+            }
+            _ => keywords.push(FITSKeyword::string("RGBWS_GA", &self.gamma, "RGBWS gamma")),
+        }
+        keywords.push(FITSKeyword::new(
+            "RGBWS_SR",
+            if self.is_srgb() { "T" } else { "F" },
+            "RGBWS uses the sRGB transfer function",
+        ));
+        let primaries = ["red", "green", "blue"];
+        for (i, primary) in primaries.iter().enumerate() {
+            keywords.push(FITSKeyword::real(
+                &format!("RGBWS_X{}", i + 1),
+                self.x[i],
+                &format!("RGBWS {} x chromaticity", primary),
+            ));
+            keywords.push(FITSKeyword::real(
+                &format!("RGBWS_Y{}", i + 1),
+                self.y[i],
+                &format!("RGBWS {} y chromaticity", primary),
+            ));
+            keywords.push(FITSKeyword::real(
+                &format!("RGBWS_L{}", i + 1),
+                self.luminance[i],
+                &format!("RGBWS {} luminance coefficient", primary),
+            ));
+        }
+        if !self.name.is_empty() {
+            keywords.push(FITSKeyword::string("RGBWS_NM", &self.name, "RGBWS name"));
+        }
+        keywords
+    }
+}
+
+impl fmt::Display for XISFRGBWorkingSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "gamma={} x={:?} y={:?} Y={:?} name={}",
+            self.gamma, self.x, self.y, self.luminance, self.name
+        )
+    }
+}
+
+/// Display function of a XISF image: the screen transfer function (midtones
+/// balance, shadows and highlights clipping points, and dynamic range
+/// expansion) `PixInsight` applies when showing the image.
+///
+/// Each parameter holds four values: red, green, blue and the combined
+/// luminance/gray channel.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_DisplayFunction_Core_Element__)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct XISFDisplayFunction {
+    midtones: Box<[f64]>,
+    shadows: Box<[f64]>,
+    highlights: Box<[f64]>,
+    low: Box<[f64]>,
+    high: Box<[f64]>,
+    name: Box<str>,
+}
+
+impl XISFDisplayFunction {
+    /// Sets a parameter (`m`, `s`, `h`, `l`, `r` or `name`) from the value of
+    /// an attribute or child element. Returns `false` if the name is unknown.
+    fn set_parameter(&mut self, name: &[u8], value: &str) -> bool {
+        match name {
+            b"m" => self.midtones = parse_values(value),
+            b"s" => self.shadows = parse_values(value),
+            b"h" => self.highlights = parse_values(value),
+            b"l" => self.low = parse_values(value),
+            b"r" => self.high = parse_values(value),
+            b"name" => self.name = value.into(),
+            _ => {
+                debug!(
+                    "unknown display function parameter {}",
+                    String::from_utf8_lossy(name)
+                );
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Gets the midtones balance values.
+    pub fn midtones(&self) -> &[f64] {
+        &self.midtones
+    }
+
+    /// Gets the shadows clipping points.
+    pub fn shadows(&self) -> &[f64] {
+        &self.shadows
+    }
+
+    /// Gets the highlights clipping points.
+    pub fn highlights(&self) -> &[f64] {
+        &self.highlights
+    }
+
+    /// Gets the low dynamic range expansion bounds.
+    pub fn low(&self) -> &[f64] {
+        &self.low
+    }
+
+    /// Gets the high dynamic range expansion bounds.
+    pub fn high(&self) -> &[f64] {
+        &self.high
+    }
+
+    /// Gets the name of the display function.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Builds the FITS keywords storing the display function (`DSPF_M0`, `DSPF_S0`,
+    /// `DSPF_H0`...), numbered by channel.
+    pub fn fits_keywords(&self) -> Vec<FITSKeyword> {
+        let parameters = [
+            ("M", "midtones balance", &self.midtones),
+            ("S", "shadows clipping point", &self.shadows),
+            ("H", "highlights clipping point", &self.highlights),
+            ("L", "low range expansion", &self.low),
+            ("R", "high range expansion", &self.high),
+        ];
+        let mut keywords = Vec::new();
+        for (letter, description, values) in parameters.iter() {
+            for (channel, value) in values.iter().enumerate() {
+                keywords.push(FITSKeyword::real(
+                    &format!("DSPF_{}{}", letter, channel),
+                    *value,
+                    &format!("Display function {} {}", description, channel),
+                ));
+            }
+        }
+        if !self.name.is_empty() {
+            keywords.push(FITSKeyword::string(
+                "DSPF_NM",
+                &self.name,
+                "Display function name",
+            ));
+        }
+        keywords
+    }
+
+    /// Builds the lines of the FITS `COMMENT` cards describing the display
+    /// function, one per parameter and channel (e.g. "XISF display function m0 = 0.5").
+    pub fn comment_lines(&self) -> Vec<String> {
+        let parameters = [
+            ("m", &self.midtones),
+            ("s", &self.shadows),
+            ("h", &self.highlights),
+            ("l", &self.low),
+            ("r", &self.high),
+        ];
+        let mut lines = Vec::new();
+        if !self.name.is_empty() {
+            lines.push(format!("XISF display function name = {}", self.name));
+        }
+        for (parameter, values) in parameters.iter() {
+            for (channel, value) in values.iter().enumerate() {
+                lines.push(format!(
+                    "XISF display function {}{} = {}",
+                    parameter, channel, value
+                ));
+            }
+        }
+        lines
+    }
+}
+
+impl fmt::Display for XISFDisplayFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "m={:?} s={:?} h={:?} l={:?} r={:?} name={}",
+            self.midtones, self.shadows, self.highlights, self.low, self.high, self.name
+        )
+    }
+}
+
+/// XISF property: a typed value identified by a name such as
+/// "XISF:CreationTime". The value is kept as written in the file.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_Property_Core_Element__)
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "json", derive(Serialize))]
+pub struct XISFProperty {
+    id: Box<str>,
+    #[cfg_attr(feature = "json", serde(rename = "type"))]
+    property_type: Box<str>,
+    value: Box<str>,
+}
+
+impl XISFProperty {
+    /// Gets the property identifier.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Gets the property type (e.g. "String", "Int32").
+    pub fn property_type(&self) -> &str {
+        &self.property_type
+    }
+
+    /// Gets the property value.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for XISFProperty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({}) = {}", self.id, self.property_type, self.value)
+    }
+}
+
+/// Unit of the resolution of a XISF image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XISFResolutionUnit {
+    /// Pixels per inch.
+    Inch,
+    /// Pixels per centimeter.
+    Centimeter,
+}
+
+impl XISFResolutionUnit {
+    /// Gets the unit as written in XISF files.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Inch => "inch",
+            Self::Centimeter => "cm",
+        }
+    }
+
+    /// Gets the length of the unit, in micrometers.
+    fn microns(self) -> f64 {
+        match self {
+            Self::Inch => 25400.0,
+            Self::Centimeter => 10000.0,
+        }
+    }
+}
+
+/// Resolution of a XISF image, in pixels per resolution unit.
+///
+/// The resolution comes from the `<Resolution>` element or from the
+/// `xResolution`, `yResolution` and `resolutionUnit` attributes of `<Image>`.
+/// In the FITS file, it is written as the physical size of a pixel in
+/// micrometers, using the `XPIXSZ` (horizontal) and `YPIXSZ` (vertical)
+/// keywords read by `PlateSolve` and Astro Pixel Processor, and as declared
+/// (`XRESOL` and `YRESOL`), e.g. for printing.
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#__XISF_Core_Elements_:_Resolution_Core_Element__)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct XISFResolution {
+    horizontal: f64,
+    vertical: f64,
+    unit: XISFResolutionUnit,
+}
+
+impl XISFResolution {
+    /// Parses the resolution attributes. The unit defaults to inches when
+    /// missing. Returns `None` if the values aren't positive finite numbers or
+    /// the unit is unknown.
+    fn parse(horizontal: &str, vertical: &str, unit: &str) -> Option<Self> {
+        let horizontal: f64 = horizontal.trim().parse().ok()?;
+        let vertical: f64 = vertical.trim().parse().ok()?;
+        let valid = |value: f64| value > 0.0 && value.is_finite();
+        if !(valid(horizontal) && valid(vertical)) {
+            return None;
+        }
+        let unit = match unit {
+            "" | "inch" => XISFResolutionUnit::Inch,
+            "cm" => XISFResolutionUnit::Centimeter,
+            _ => return None,
+        };
+        Some(Self {
+            horizontal,
+            vertical,
+            unit,
+        })
+    }
+
+    /// Gets the horizontal resolution, in pixels per unit.
+    pub fn horizontal(&self) -> f64 {
+        self.horizontal
+    }
+
+    /// Gets the vertical resolution, in pixels per unit.
+    pub fn vertical(&self) -> f64 {
+        self.vertical
+    }
+
+    /// Gets the resolution unit.
+    pub fn unit(&self) -> XISFResolutionUnit {
+        self.unit
+    }
+
+    /// Gets the horizontal and vertical size of a pixel, in micrometers.
+    pub fn pixel_size(&self) -> (f64, f64) {
+        (
+            self.unit.microns() / self.horizontal,
+            self.unit.microns() / self.vertical,
+        )
+    }
+
+    /// Builds the FITS keywords storing the pixel size (`XPIXSZ` and `YPIXSZ`) and
+    /// the resolution as declared (`XRESOL` and `YRESOL`, with the unit in the comment).
+    pub fn fits_keywords(&self) -> Vec<FITSKeyword> {
+        let (width, height) = self.pixel_size();
+        let unit = format!("[pixel/{}]", self.unit.as_str());
+        vec![
+            FITSKeyword::real("XPIXSZ", width, "[um] Pixel width from XISF resolution"),
+            FITSKeyword::real("YPIXSZ", height, "[um] Pixel height from XISF resolution"),
+            FITSKeyword::real(
+                "XRESOL",
+                self.horizontal,
+                &format!("{} XISF horizontal resolution", unit),
+            ),
+            FITSKeyword::real(
+                "YRESOL",
+                self.vertical,
+                &format!("{} XISF vertical resolution", unit),
+            ),
+        ]
+    }
+}
+
+impl fmt::Display for XISFResolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{} pixels per {}",
+            self.horizontal,
+            self.vertical,
+            self.unit.as_str()
+        )
+    }
+}
+
+/// Gets the position to split an ASCII XML line at `width`, or before it if the
+/// split would cut a character or entity reference (e.g. `&#xC5;` or `&amp;`).
+fn xml_reference_boundary(line: &str, width: usize) -> usize {
+    let start = match line[..width].rfind('&') {
+        Some(start) if start > 0 => start,
+        _ => return width,
+    };
+    match line[start + 1..].find(';') {
+        Some(end)
+            if start + 1 + end >= width
+                && line[start + 1..start + 1 + end]
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '#') =>
+        {
+            start
+        }
+        _ => width,
+    }
+}
+
+/// Parses a "value:...:value" attribute into (finite) numbers.
+fn parse_values(value: &str) -> Box<[f64]> {
+    let mut values = Vec::new();
+    for part in value.split(':') {
+        match part.trim().parse::<f64>() {
+            Ok(number) if number.is_finite() => values.push(number),
+            _ => {
+                warn!("Invalid number in \"{}\": {}", value, part);
+                values.push(0.0);
+            }
+        }
+    }
+    values.into_boxed_slice()
+}
+
+/// Checks the XISF identifier grammar: a letter or underscore, followed by
+/// letters, digits or underscores.
+fn is_valid_identifier(id: &str) -> bool {
+    let mut chars = id.chars();
+    match chars.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+/// Reads the attributes of an element as (name, unescaped value) pairs.
+fn xml_attributes<B>(
+    e: &BytesStart<'_>,
+    reader: &Reader<B>,
+) -> Result<Vec<(Vec<u8>, String)>, XisfParseError>
+where
+    B: BufRead,
+{
+    let element: Box<str> = String::from_utf8_lossy(e.local_name()).into();
+    let mut attributes = Vec::new();
+    for attr in e.attributes() {
+        let attr = attr.map_err(|source| XisfParseError::Attribute {
+            element: element.clone(),
+            source,
+        })?;
+        let value =
+            attr.unescape_and_decode_value(reader)
+                .map_err(|source| XisfParseError::Decode {
+                    element: element.clone(),
+                    attribute: String::from_utf8_lossy(attr.key).into(),
+                    value: String::from_utf8_lossy(&attr.value).into_owned(),
+                    source,
+                })?;
+        attributes.push((attr.key.to_vec(), value));
+    }
+    Ok(attributes)
+}
+
+/// Parses an integer from (a part of) the value of an attribute.
+fn parse_integer<T>(element: &str, attribute: &str, value: &str) -> Result<T, XisfParseError>
+where
+    T: str::FromStr<Err = ParseIntError>,
+{
+    value.parse().map_err(|source| XisfParseError::Integer {
+        element: element.into(),
+        attribute: attribute.into(),
+        value: value.to_owned(),
+        source,
+    })
+}
+
+/// Parses the identifier of a block of a XISB file (a hexadecimal number) in the
+/// location of an image.
+fn parse_block_id(value: &str) -> Result<u64, XisfParseError> {
+    u64::from_str_radix(value, 16).map_err(|source| XisfParseError::Integer {
+        element: "Image".into(),
+        attribute: "location".into(),
+        value: value.to_owned(),
+        source,
+    })
+}
+
+/// Parses a "lower:upper" bounds attribute. The lower bound must be less than the upper one,
+/// and the range finite (the data is scaled to it).
+fn parse_bounds(value: &str) -> Option<(f64, f64)> {
+    let mut parts = value.split(':');
+    let lower: f64 = parts.next()?.trim().parse().ok()?;
+    let upper: f64 = parts.next()?.trim().parse().ok()?;
+    if parts.next().is_none() && lower < upper && (upper - lower).is_finite() {
+        Some((lower, upper))
+    } else {
+        None
+    }
+}
+
+/// Parses a "value:value:value" attribute (one value per RGB primary).
+fn parse_triplet(value: &str) -> [f64; 3] {
+    let mut triplet = [0.0; 3];
+    for (i, number) in parse_values(value).iter().take(3).enumerate() {
+        triplet[i] = *number;
+    }
+    triplet
+}
+
+/// Former name of [`XISFSampleFormat`](enum.XISFSampleFormat.html).
+#[deprecated(since = "0.1.0", note = "renamed to XISFSampleFormat")]
+pub type XISFType = XISFSampleFormat;
+
+/// Enumeration with the different XISF sample formats
+///
+/// [More information](https://pixinsight.com/doc/docs/XISF-1.0-spec/XISF-1.0-spec.html#sampleformat_image_attribute)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XISFSampleFormat {
+    /// 8 bits unsigned integer.
+    UInt8,
+    /// 16 bits unsigned integer.
     UInt16,
+    /// 32 bits unsigned integer.
     UInt32,
+    /// 64 bits unsigned integer.
     UInt64,
+    /// 8 bits signed integer.
+    Int8,
+    /// 16 bits signed integer.
+    Int16,
+    /// 32 bits signed integer.
+    Int32,
+    /// 32 bits floating point.
     Float32,
+    /// 64 bits floating point.
     Float64,
+    /// Complex with 32 bits floating point parts.
     Complex32,
+    /// Complex with 64 bits floating point parts.
     Complex64,
 }
 
 impl XISFSampleFormat {
     /// Gets the size of the XISF type, in bytes.
-    fn size(self) -> usize {
+    pub fn size(self) -> usize {
         match self {
-            Self::UInt8 => 1,
-            Self::UInt16 => 2,
-            Self::UInt32 | Self::Float32 | Self::Complex32 => 4,
-            Self::UInt64 | Self::Float64 | Self::Complex64 => 8,
+            Self::UInt8 | Self::Int8 => 1,
+            Self::UInt16 | Self::Int16 => 2,
+            Self::UInt32 | Self::Int32 | Self::Float32 => 4,
+            // Complex samples have two parts (real and imaginary)
+            Self::UInt64 | Self::Float64 | Self::Complex32 => 8,
+            Self::Complex64 => 16,
         }
     }
 
+    /// Whether the samples are complex numbers.
+    pub fn is_complex(self) -> bool {
+        self == Self::Complex32 || self == Self::Complex64
+    }
+
+    /// Whether the samples are floating point numbers.
+    pub fn is_float(self) -> bool {
+        self == Self::Float32 || self == Self::Float64
+    }
+
     /// Gets the XISF type as a string.
     pub fn as_str(self) -> &'static str {
         match self {
@@ -573,6 +3267,9 @@ impl XISFSampleFormat {
             Self::UInt16 => "UInt16",
             Self::UInt32 => "UInt32",
             Self::UInt64 => "UInt64",
+            Self::Int8 => "Int8",
+            Self::Int16 => "Int16",
+            Self::Int32 => "Int32",
             Self::Float32 => "Float32",
             Self::Float64 => "Float64",
             Self::Complex32 => "Complex32",
@@ -588,84 +3285,752 @@ impl fmt::Display for XISFSampleFormat {
 }
 
 impl str::FromStr for XISFSampleFormat {
-    type Err = String; // TODO: propper error handling.
+    type Err = SampleFormatParseError;
 
+    /// Parses a sample format, ignoring case (some writers use e.g. "uint16").
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "UInt8" => Ok(Self::UInt8),
-            "UInt16" => Ok(Self::UInt16),
-            "UInt32" => Ok(Self::UInt32),
-            "UInt64" => Ok(Self::UInt64),
-            "Float32" => Ok(Self::Float32),
-            "Float64" => Ok(Self::Float64),
-            "Complex32" => Ok(Self::Complex32),
-            "Complex64" => Ok(Self::Complex64),
-            _ => Err(format!("unsupported XISF type found: {}", s)),
+        match s.to_ascii_lowercase().as_str() {
+            "uint8" => Ok(Self::UInt8),
+            "uint16" => Ok(Self::UInt16),
+            "uint32" => Ok(Self::UInt32),
+            "uint64" => Ok(Self::UInt64),
+            "int8" => Ok(Self::Int8),
+            "int16" => Ok(Self::Int16),
+            "int32" => Ok(Self::Int32),
+            "float32" => Ok(Self::Float32),
+            "float64" => Ok(Self::Float64),
+            "complex32" => Ok(Self::Complex32),
+            "complex64" => Ok(Self::Complex64),
+            _ => Err(SampleFormatParseError {
+                format: s.to_owned(),
+            }),
+        }
+    }
+}
+
+/// Number of complete channels in `length` bytes of image data (at most `channel_count`)
+fn xisf_channel_count(length: usize, channel_size: usize, channel_count: usize) -> usize {
+    length
+        .checked_div(channel_size)
+        .map_or(channel_count, |channels| cmp::min(channels, channel_count))
+}
+
+/// Split image data into channels and interpret them as numbers. Returns `None`
+/// if the sample format isn't supported.
+fn xisf_bytes_to_data(
+    image_data: &[u8],
+    sample_format: XISFSampleFormat,
+    channel_size: usize,
+    channel_count: usize,
+) -> Option<XISFData> {
+    let chunks: Vec<&[u8]> = image_data
+        .chunks_exact(channel_size)
+        .take(channel_count)
+        .collect();
+    let xisf_data = match sample_format {
+        XISFSampleFormat::UInt8 => XISFData::UInt8(xisf_decode_channels(chunks, <[u8]>::to_vec)),
+        XISFSampleFormat::UInt16 => {
+            XISFData::UInt16(xisf_decode_channels(chunks, convert::u8_to_v_u16))
+        }
+        XISFSampleFormat::UInt32 => {
+            XISFData::UInt32(xisf_decode_channels(chunks, convert::u8_to_v_u32))
+        }
+        XISFSampleFormat::Int8 => XISFData::Int8(xisf_decode_channels(chunks, convert::u8_to_i8)),
+        XISFSampleFormat::Int16 => {
+            XISFData::Int16(xisf_decode_channels(chunks, convert::u8_to_v_i16))
+        }
+        XISFSampleFormat::Int32 => {
+            XISFData::Int32(xisf_decode_channels(chunks, convert::u8_to_v_i32))
+        }
+        XISFSampleFormat::Float32 => {
+            XISFData::Float32(xisf_decode_channels(chunks, convert::u8_to_v_f32))
+        }
+        XISFSampleFormat::Float64 => {
+            XISFData::Float64(xisf_decode_channels(chunks, convert::u8_to_v_f64))
+        }
+        XISFSampleFormat::Complex32 => {
+            XISFData::Complex32(xisf_decode_channels(chunks, convert::u8_to_v_complex32))
+        }
+        XISFSampleFormat::Complex64 => {
+            XISFData::Complex64(xisf_decode_channels(chunks, convert::u8_to_v_complex64))
+        }
+        XISFSampleFormat::UInt64 => return None,
+    };
+    Some(xisf_data)
+}
+
+/// Decode the samples of each channel, in parallel with the `parallel` feature. The
+/// channels keep their order (the parallel iterator is indexed).
+fn xisf_decode_channels<T, F>(chunks: Vec<&[u8]>, decode: F) -> Box<[Box<[T]>]>
+where
+    T: Send,
+    F: Fn(&[u8]) -> Vec<T> + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    let channels: Vec<Box<[T]>> = chunks
+        .into_par_iter()
+        .map(|chunk| decode(chunk).into_boxed_slice())
+        .collect();
+    #[cfg(not(feature = "parallel"))]
+    let channels: Vec<Box<[T]>> = chunks
+        .into_iter()
+        .map(|chunk| decode(chunk).into_boxed_slice())
+        .collect();
+    channels.into_boxed_slice()
+}
+
+/// Read uncompressed image data stored as an attachment channel by channel, so only a
+/// channel is buffered at a time. The data is verified while it's read.
+fn xisf_stream_channels<R>(
+    f: &mut R,
+    xisf_header: &XISFHeader,
+    file_size: u64,
+    options: XisfReadOptions,
+    progress: &dyn ConversionProgress,
+) -> Result<XISFData, XisfError>
+where
+    R: Read + Seek,
+{
+    let sample_format = xisf_header.sample_format();
+    let channel_size = xisf_header.channel_size();
+    let channel_count = xisf_header.geometry().channel_count();
+    let checksum = xisf_header.checksum();
+    let mut digest = if options.verify_checksum && !checksum.is_empty() {
+        xisf_checksum_digest(checksum)
+    } else {
+        None
+    };
+
+    // In lenient mode, the attachment may start beyond the end of the file
+    let start = cmp::min(xisf_header.location_start(), file_size);
+    let _ = f.seek(SeekFrom::Start(start))?;
+    let length = xisf_header.location_length();
+    let mut data = f.take(length);
+    // Only the data in the file can be read. In lenient mode, the missing channels are
+    // zero, up to what the file could hold.
+    let available = cmp::min(length, file_size - start);
+    let image_size = xisf_header.geometry().checked_size(sample_format.size());
+    let short = match image_size {
+        Some(size) => size as u64 > available,
+        None => true,
+    };
+    if short && options.parse_mode == ParseMode::Lenient {
+        let _ = xisf_padded_size(image_size, file_size)?;
+    }
+    let mut channels = 0;
+    let mut read_channel = |data: &mut io::Take<&mut R>| -> Result<Vec<u8>, XisfError> {
+        // Check the channel is in the file before allocating it
+        let remaining = available.saturating_sub(length - data.limit());
+        if remaining < channel_size as u64 && options.parse_mode != ParseMode::Lenient {
+            return Err(XisfError::TruncatedData {
+                channels,
+                expected: channel_count,
+            });
+        }
+        let mut buffer = Vec::with_capacity(channel_size);
+        let _ = data
+            .by_ref()
+            .take(channel_size as u64)
+            .read_to_end(&mut buffer)?;
+        progress.on_step(ConversionStep::ReadData, length - data.limit(), length);
+        if let Some((ref mut hasher, _)) = digest {
+            hasher.update(&buffer);
+        }
+        if buffer.len() == channel_size {
+            channels += 1;
+        }
+        // The missing samples are zero
+        buffer.resize(channel_size, 0);
+        Ok(buffer)
+    };
+    let xisf_data = match sample_format {
+        XISFSampleFormat::UInt8 => XISFData::UInt8(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| bytes,
+        )?),
+        XISFSampleFormat::UInt16 => XISFData::UInt16(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_u16(&bytes),
+        )?),
+        XISFSampleFormat::UInt32 => XISFData::UInt32(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_u32(&bytes),
+        )?),
+        XISFSampleFormat::Int8 => XISFData::Int8(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_i8(&bytes),
+        )?),
+        XISFSampleFormat::Int16 => XISFData::Int16(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_i16(&bytes),
+        )?),
+        XISFSampleFormat::Int32 => XISFData::Int32(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_i32(&bytes),
+        )?),
+        XISFSampleFormat::Float32 => XISFData::Float32(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_f32(&bytes),
+        )?),
+        XISFSampleFormat::Float64 => XISFData::Float64(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_f64(&bytes),
+        )?),
+        XISFSampleFormat::Complex32 => XISFData::Complex32(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_complex32(&bytes),
+        )?),
+        XISFSampleFormat::Complex64 => XISFData::Complex64(xisf_read_channels(
+            &mut data,
+            channel_count,
+            &mut read_channel,
+            |bytes| convert::u8_to_v_complex64(&bytes),
+        )?),
+        XISFSampleFormat::UInt64 => {
+            return Err(XisfError::UnsupportedSampleFormat {
+                sample_format: sample_format.to_string(),
+            })
+        }
+    };
+
+    // The checksum covers the whole attachment, beyond the declared channels too
+    if let Some((mut hasher, expected)) = digest {
+        xisf_digest_update(&mut *hasher, &mut data)?;
+        match xisf_check_digest(hasher, expected) {
+            Err(e @ XisfError::ChecksumMismatch { .. })
+                if options.parse_mode == ParseMode::Lenient =>
+            {
+                warn!("{}", e);
+            }
+            result => result?,
+        }
+    }
+
+    // The data must hold all the channels declared by the geometry
+    if channels < channel_count {
+        let e = XisfError::TruncatedData {
+            channels,
+            expected: channel_count,
+        };
+        if options.parse_mode != ParseMode::Lenient {
+            return Err(e);
+        }
+        warn!("{}", e);
+    }
+    Ok(xisf_data)
+}
+
+/// Read `channel_count` channels with `read_channel`, decoding each one with `decode`
+/// before reading the next one.
+fn xisf_read_channels<R, T, F, D>(
+    data: &mut R,
+    channel_count: usize,
+    read_channel: &mut F,
+    decode: D,
+) -> Result<Box<[Box<[T]>]>, XisfError>
+where
+    F: FnMut(&mut R) -> Result<Vec<u8>, XisfError>,
+    D: Fn(Vec<u8>) -> Vec<T>,
+{
+    (0..channel_count)
+        .map(|_| read_channel(data).map(|bytes| decode(bytes).into_boxed_slice()))
+        .collect()
+}
+
+/// Read a secondary image (e.g. the thumbnail) stored as an uncompressed attachment
+fn xisf_read_image<R>(
+    f: &mut R,
+    image: XISFImageReader,
+    file_size: u64,
+) -> io::Result<Option<XISFImage>>
+where
+    R: Read + Seek,
+{
+    if !image.compression.is_empty() || !image.location.starts_with("attachment:") {
+        warn!(
+            "Unsupported image location or compression: {} {}",
+            image.location, image.compression
+        );
+        return Ok(None);
+    }
+
+    if let (Some(geometry), Some(sample_format)) = (image.geometry, image.sample_format) {
+        if geometry.checked_size(sample_format.size()).is_none() {
+            warn!("Image geometry too large: {}", geometry);
+            return Ok(None);
+        }
+        let image_data = xisf_read_attachment(f, &image.location, file_size)?;
+        let channel_size = geometry.channel_size() * sample_format.size();
+        let image_data = image_data.filter(|image_data| {
+            let channels =
+                xisf_channel_count(image_data.len(), channel_size, geometry.channel_count());
+            if channels < geometry.channel_count() {
+                warn!(
+                    "Truncated image data: {} of {} channels",
+                    channels,
+                    geometry.channel_count()
+                );
+            }
+            channels == geometry.channel_count()
+        });
+        let data = image_data.and_then(|image_data| {
+            xisf_bytes_to_data(
+                &image_data,
+                sample_format,
+                channel_size,
+                geometry.channel_count(),
+            )
+        });
+        let color_space = image.color_space;
+        Ok(data.map(|data| XISFImage {
+            geometry,
+            sample_format,
+            color_space,
+            data,
+        }))
+    } else {
+        warn!("Image without geometry or sample format");
+        Ok(None)
+    }
+}
+
+/// Size of the chunks read to compute checksums
+const CHECKSUM_CHUNK_SIZE: usize = 1 << 20;
+
+/// Size of the chunks of image data read between progress reports
+const READ_CHUNK_SIZE: u64 = 16 << 20;
+
+/// Read up to `length` bytes of image data, reporting the bytes read so far to
+/// `progress` after each whole chunk (the end of the data is reported by the caller).
+fn xisf_read_data<R: Read>(
+    f: &mut R,
+    length: u64,
+    progress: &dyn ConversionProgress,
+) -> io::Result<Vec<u8>> {
+    let mut image_data = Vec::new();
+    let mut data = f.take(length);
+    loop {
+        let size = data
+            .by_ref()
+            .take(READ_CHUNK_SIZE)
+            .read_to_end(&mut image_data)?;
+        if (size as u64) < READ_CHUNK_SIZE {
+            break;
+        }
+        progress.on_step(ConversionStep::ReadData, image_data.len() as u64, length);
+    }
+    Ok(image_data)
+}
+
+/// Verify the data read from `data` against a checksum attribute ("algorithm:digest").
+/// Checksums with unknown algorithms are not verified.
+pub fn xisf_verify_checksum<R: Read>(data: &mut R, checksum: &str) -> Result<(), XisfError> {
+    if let Some((mut hasher, expected)) = xisf_checksum_digest(checksum) {
+        xisf_digest_update(&mut *hasher, data)?;
+        xisf_check_digest(hasher, expected)
+    } else {
+        Ok(())
+    }
+}
+
+/// Gets the hasher of the checksum algorithm and the expected digest (in lowercase
+/// hexadecimal), `None` if the algorithm isn't supported.
+fn xisf_checksum_digest(checksum: &str) -> Option<(Box<dyn DynDigest>, String)> {
+    let mut parts = checksum.splitn(2, ':');
+    let algorithm = parts.next().unwrap_or_default();
+    let expected = parts.next().unwrap_or_default().trim().to_lowercase();
+    let hasher: Box<dyn DynDigest> = match algorithm.to_lowercase().as_str() {
+        "sha-1" | "sha1" => Box::new(Sha1::new()),
+        "sha-256" | "sha256" => Box::new(Sha256::new()),
+        "sha-512" | "sha512" => Box::new(Sha512::new()),
+        _ => {
+            warn!("Unsupported checksum algorithm: {}", algorithm);
+            return None;
+        }
+    };
+    Some((hasher, expected))
+}
+
+/// Hash the rest of `data`, chunk by chunk.
+fn xisf_digest_update<R: Read>(hasher: &mut dyn DynDigest, data: &mut R) -> io::Result<()> {
+    let mut chunk = vec![0; CHECKSUM_CHUNK_SIZE];
+    loop {
+        let read = data.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        hasher.update(&chunk[..read]);
+    }
+}
+
+/// Compare the digest of the hashed data with the expected one.
+fn xisf_check_digest(hasher: Box<dyn DynDigest>, expected: String) -> Result<(), XisfError> {
+    let got = hasher
+        .finalize()
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        });
+
+    if got == expected {
+        info!("Checksum: Ok");
+        Ok(())
+    } else {
+        Err(XisfError::ChecksumMismatch { expected, got })
+    }
+}
+
+/// Resolve the file referenced by a `url(...)` location: plain paths (relative to the
+/// XISF file) and `file://` URLs. The file must be in the directory of the XISF file
+/// (or a subdirectory), so crafted files can't read any other file.
+fn xisf_url_path(xisf_filename: &Path, url: &str) -> Result<PathBuf, XisfError> {
+    let directory = match xisf_filename.parent() {
+        Some(directory) if directory != Path::new("") => directory,
+        _ => Path::new("."),
+    };
+    let mut parts = url.splitn(2, "file://");
+    let path = if let (Some(""), Some(path)) = (parts.next(), parts.next()) {
+        PathBuf::from(path)
+    } else if url.contains("://") {
+        return Err(XisfError::UnsupportedLocationMethod {
+            method: format!("url({})", url),
+        });
+    } else {
+        directory.join(url)
+    };
+    if fs::canonicalize(&path)?.starts_with(fs::canonicalize(directory)?) {
+        Ok(path)
+    } else {
+        Err(XisfError::UrlOutsideDirectory {
+            url: url.to_owned(),
+        })
+    }
+}
+
+/// Resolve the XISB file of a `path(...)` location: `@header_dir` stands for the
+/// directory of the XISF file, and relative paths are relative to it. As with URLs, the
+/// file must be in the directory of the XISF file (or a subdirectory).
+fn xisf_block_path(xisf_filename: &Path, path: &str) -> Result<PathBuf, XisfError> {
+    let path = path.trim_start_matches("@header_dir/");
+    let block_filename = match xisf_filename.parent() {
+        Some(directory) => directory.join(path),
+        None => PathBuf::from(path),
+    };
+    // Name the missing file, instead of a bare I/O error
+    if let Err(source) = fs::metadata(&block_filename) {
+        return Err(XisfError::BlockFile {
+            path: block_filename.display().to_string(),
+            source,
+        });
+    }
+    xisf_url_path(xisf_filename, path)
+}
+
+/// Read the block `id` of a XISB file. The blocks are listed by the block index, a
+/// chain of nodes starting after the signature ("XISB0100") and 8 reserved bytes. Each
+/// node holds the number of elements (uint32), 4 reserved bytes, the position of the
+/// next node (uint64, 0 for the last one) and its elements: the identifier, position,
+/// length and uncompressed length of a block, and 8 reserved bytes (uint64 each).
+fn xisf_read_block(block_filename: &Path, id: u64) -> Result<Vec<u8>, XisfError> {
+    let path = block_filename.display().to_string();
+    let block_error = |source| XisfError::BlockFile {
+        path: path.clone(),
+        source,
+    };
+    let invalid = || XisfError::InvalidBlockFile { path: path.clone() };
+    let mut f = BufReader::new(File::open(block_filename).map_err(block_error)?);
+    let file_size = f.get_ref().metadata().map_err(block_error)?.len();
+    let mut signature = [0; 16];
+    f.read_exact(&mut signature).map_err(|_| invalid())?;
+    if &signature[..8] != b"XISB0100" {
+        return Err(invalid());
+    }
+
+    // Follow the nodes (at most as many as fit in the file, so loops end) until the block
+    let mut node_position = 16;
+    let mut block = None;
+    for _ in 0..file_size / 16 {
+        let _ = f
+            .seek(SeekFrom::Start(node_position))
+            .map_err(block_error)?;
+        let mut node = [0; 16];
+        f.read_exact(&mut node).map_err(|_| invalid())?;
+        let elements = u32::from_le_bytes(node[..4].try_into().unwrap_or_default());
+        let next_node = u64::from_le_bytes(node[8..].try_into().unwrap_or_default());
+        for _ in 0..elements {
+            let mut element = [0; 40];
+            f.read_exact(&mut element).map_err(|_| invalid())?;
+            let field = |n: usize| {
+                u64::from_le_bytes(element[n * 8..(n + 1) * 8].try_into().unwrap_or_default())
+            };
+            // Free elements have no position
+            if field(0) == id && field(1) != 0 {
+                block = Some((field(1), field(2)));
+                break;
+            }
+        }
+        if block.is_some() || next_node == 0 {
+            break;
+        }
+        node_position = next_node;
+    }
+    let (start, length) = block.ok_or_else(|| XisfError::BlockNotFound {
+        path: path.clone(),
+        id,
+    })?;
+    match start.checked_add(length) {
+        Some(end) if end <= file_size => {}
+        _ => {
+            return Err(XisfError::BlockOutOfBounds {
+                path,
+                start,
+                length,
+                file_size,
+            })
+        }
+    }
+    info!(
+        "Read XISF > Data block {:x}: {} bytes at {}",
+        id, length, start
+    );
+    let _ = f.seek(SeekFrom::Start(start)).map_err(block_error)?;
+    let mut data = Vec::new();
+    let _ = f.take(length).read_to_end(&mut data).map_err(block_error)?;
+    Ok(data)
+}
+
+/// Whether the URL of a `url(...)` location is a remote file (HTTP or HTTPS).
+fn xisf_is_remote(url: &str) -> bool {
+    let url = url.to_ascii_lowercase();
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+/// Download `length` bytes at `start` of a remote file.
+#[cfg(feature = "remote")]
+fn xisf_read_remote(
+    url: &str,
+    start: u64,
+    length: u64,
+    options: XisfReadOptions,
+) -> Result<Vec<u8>, XisfError> {
+    let end = match start.checked_add(length) {
+        Some(end) if end <= options.max_remote_size => end,
+        _ => {
+            return Err(XisfError::RemoteTooLarge {
+                url: url.to_owned(),
+                length,
+                limit: options.max_remote_size,
+            })
         }
+    };
+    info!(
+        "Read XISF > Download {} bytes at {} of {}",
+        length, start, url
+    );
+    let mut request = ureq::get(url).timeout(options.remote_timeout);
+    if length > 0 {
+        request = request.set("Range", &format!("bytes={}-{}", start, end - 1));
+    }
+    let response = request.call().map_err(|e| XisfError::Remote {
+        url: url.to_owned(),
+        message: e.to_string(),
+    })?;
+    // Servers may ignore the range and send the whole file
+    let partial = response.status() == 206;
+    let mut data = Vec::new();
+    let _ = response
+        .into_reader()
+        .take(if partial { length } else { end })
+        .read_to_end(&mut data)?;
+    if !partial {
+        let _ = data.drain(..cmp::min(usize::try_from(start).unwrap_or(usize::MAX), data.len()));
+    }
+    Ok(data)
+}
+
+/// Remote files can't be read without the `remote` feature.
+#[cfg(not(feature = "remote"))]
+fn xisf_read_remote(
+    url: &str,
+    _start: u64,
+    _length: u64,
+    _options: XisfReadOptions,
+) -> Result<Vec<u8>, XisfError> {
+    Err(XisfError::RemoteUnsupported {
+        url: url.to_owned(),
+    })
+}
+
+/// Parse the signature of a XISF file ("XISF" followed by the major and minor
+/// version numbers, two digits each) and get the version.
+fn xisf_parse_signature(signature: [u8; 8]) -> Option<(u8, u8)> {
+    if &signature[..4] != b"XISF" || !signature[4..].iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let digits = str::from_utf8(&signature[4..]).ok()?;
+    Some((digits[..2].parse().ok()?, digits[2..].parse().ok()?))
+}
+
+/// Parse the position and size of an attachment ("attachment:position:size")
+fn xisf_parse_attachment(location: &str) -> Option<(u64, u64)> {
+    let mut parts = location.split(':').skip(1);
+    let start = parts.next()?.parse().ok()?;
+    let length = parts.next()?.parse().ok()?;
+    Some((start, length))
+}
+
+/// Read a block of data stored as an attachment
+fn xisf_read_attachment<R>(f: &mut R, location: &str, file_size: u64) -> io::Result<Option<Vec<u8>>>
+where
+    R: Read + Seek,
+{
+    match xisf_parse_attachment(location) {
+        Some((start, length)) if start <= file_size && length <= file_size - start => {
+            let _ = f.seek(SeekFrom::Start(start))?;
+            let mut data = Vec::new();
+            let _ = f.by_ref().take(length).read_to_end(&mut data)?;
+            Ok(Some(data))
+        }
+        _ => {
+            warn!("Invalid attachment location: {}", location);
+            Ok(None)
+        }
+    }
+}
+
+/// Maps the XISF file in memory.
+#[cfg(feature = "mmap")]
+#[allow(unsafe_code)]
+fn xisf_map_file(f: &File) -> io::Result<memmap2::Mmap> {
+    // Safety: the map is only read while the file is read. As with any map, the file
+    // must not be modified by other processes meanwhile.
+    unsafe { memmap2::Mmap::map(f) }
+}
+
+/// Decode a block of data stored inline ("inline:encoding")
+fn xisf_decode_inline(location: &str, text: &str) -> Option<Vec<u8>> {
+    if location != "inline:base64" {
+        warn!("Unsupported inline location: {}", location);
+        return None;
+    }
+    // Ignore the line breaks of the encoded text
+    let text: String = text.split_whitespace().collect();
+    match base64::decode(&text) {
+        Ok(data) => Some(data),
+        Err(e) => {
+            warn!("Invalid base64 data: {}", e);
+            None
+        }
+    }
+}
+
+/// Best compression ratio of the supported codecs (zlib's), which bounds the size of
+/// compressed data once uncompressed.
+const MAX_COMPRESSION_RATIO: u64 = 1032;
+
+/// Gets the size of image data padded with zeros in lenient mode, which can't exceed
+/// `max_size` (what the stored data could plausibly hold), so a few bytes of a crafted
+/// file can't allocate all the memory.
+fn xisf_padded_size(size: Option<usize>, max_size: u64) -> Result<usize, XisfError> {
+    match size {
+        Some(size) if size as u64 <= max_size => Ok(size),
+        _ => Err(XisfError::ImplausibleDataSize { max_size }),
     }
 }
 
 /// Uncompress image data
-fn xisf_uncompress_data(xisf_header: &XISFHeader, image_data: &[u8]) -> Box<[u8]> {
+fn xisf_uncompress_data(
+    xisf_header: &XISFHeader,
+    image_data: &[u8],
+    parse_mode: ParseMode,
+) -> Result<Box<[u8]>, XisfError> {
     info!("Read XISF > Uncompressing");
+    let codec = xisf_header.compression_codec();
+    // Byte shuffling is a suffix of the codec (e.g. "zlib+sh"), applied after decompression
+    let (base_codec, shuffled) = match codec.rfind('+') {
+        Some(index) if &codec[index..] == "+sh" => (&codec[..index], true),
+        _ => (codec, false),
+    };
     let mut decompressed = Vec::new();
-    let result;
+    // Never uncompress more than the declared size, so crafted data (a "zip bomb") can't
+    // exhaust the memory. A byte more tells that the data is larger than declared.
+    let limit = (xisf_header.compression_size() as u64).saturating_add(1);
     // Match compression codec and call decoder
-    match xisf_header.compression_codec() {
-        "zlib" | "zlib+sh" => {
-            // Uncompress using zlib decoder
-            result =
-                zlib::Decoder::new(BufReader::new(&image_data[..])).read_to_end(&mut decompressed);
-        }
-        "lz4" => {
-            // Uncompress using lz4 decoder
-            result =
-                lz4::Decoder::new(BufReader::new(&image_data[..])).read_to_end(&mut decompressed);
-        }
+    let result = match (base_codec, shuffled) {
+        // Uncompress using zlib decoder
+        ("zlib", _) => zlib::Decoder::new(BufReader::new(image_data))
+            .take(limit)
+            .read_to_end(&mut decompressed),
+        // Uncompress using lz4 decoder
+        ("lz4", false) => lz4::Decoder::new(BufReader::new(image_data))
+            .take(limit)
+            .read_to_end(&mut decompressed),
         // "lz4+sh" => {} // Gives error with lz4 decoder
         // "lz4hc" => {} // Not supported by lz4 decoder
         _ => {
-            // Unsupported codec. Abort.
-            eprintln!(
-                "Read XISF > Uncompressing > Unsupported codec: {}",
-                xisf_header.compression_codec()
-            );
-            process::exit(1);
+            // Unsupported codec
+            return Err(XisfError::UnsupportedCompression {
+                codec: codec.to_owned(),
+            });
         }
-    }
+    };
     info!("Read XISF > Uncompressed size: {}", decompressed.len());
-    match result {
-        Ok(_v) => {
-            // Data uncompressed
-            // If expected size doesn't match, abort
-            if decompressed.len() != xisf_header.compression_size {
-                eprintln!(
-                    "Read XISF > Uncompressing > Sizes don't match. Uncompressed: {} Expected: {}",
-                    image_data.len(),
-                    xisf_header.compression_size()
-                );
-                process::exit(1);
-            }
-        }
-        Err(r) => {
-            // Error uncompressing data
-            eprintln!("Read XISF > Uncompressing > Cannot uncompress: {}", r);
-            process::exit(1);
+    if let Err(source) = result {
+        // Error uncompressing data
+        return Err(XisfError::Decompression {
+            codec: codec.to_owned(),
+            source,
+        });
+    }
+    // The uncompressed data must have the declared size
+    if decompressed.len() > xisf_header.compression_size() {
+        return Err(XisfError::UncompressedSizeExceeded {
+            expected: xisf_header.compression_size(),
+        });
+    }
+    if decompressed.len() != xisf_header.compression_size() {
+        let e = XisfError::UncompressedSizeMismatch {
+            size: decompressed.len(),
+            expected: xisf_header.compression_size(),
+        };
+        if parse_mode != ParseMode::Lenient {
+            return Err(e);
         }
+        warn!("{}", e);
+        let max_size = (image_data.len() as u64).saturating_mul(MAX_COMPRESSION_RATIO);
+        decompressed.resize(
+            xisf_padded_size(Some(xisf_header.compression_size()), max_size)?,
+            0,
+        );
     }
-    // Unshuffle
-    if xisf_header.sample_format().size() > 1 {
+    // Unshuffle (a no-op for 1 byte samples)
+    let item_size = xisf_header.sample_format().size();
+    if shuffled && item_size > 1 {
+        info!("Read XISF > Uncompressing > Unshuffling {}", codec);
+        decompressed = convert::unshuffle(&decompressed, item_size);
         info!(
-            "Read XISF > Uncompressing > Unshuffling {}",
-            xisf_header.compression_codec()
+            "Read XISF > Uncompressing > Unshuffling > Decompressed len: {}",
+            decompressed.len()
         );
-        if xisf_header.compression_codec() == "zlib+sh" {
-            decompressed = convert::unshuffle(&decompressed, xisf_header.sample_format().size());
-            info!(
-                "Read XISF > Uncompressing > Unshuffling > Decompressed len: {}",
-                decompressed.len()
-            );
-        }
     }
-    decompressed.into_boxed_slice()
+    Ok(decompressed.into_boxed_slice())
 }